@@ -0,0 +1,111 @@
+/// Block length for level measurements, 20ms at 16kHz
+const BLOCK: usize = 320;
+/// Samples at or above this magnitude count as clipped
+const CLIP_LEVEL: f32 = 0.99;
+/// Noise floor below this means the microphone is probably muted or too quiet
+const QUIET_FLOOR: f32 = 0.0005;
+/// Peak below this while speaking is too quiet to recognize reliably
+const QUIET_PEAK: f32 = 0.1;
+/// Share of clipped samples above which the input is too hot
+const CLIPPING_RATIO: f32 = 0.001;
+/// Speech has to rise this far above the noise floor
+const SPEECH_MARGIN: f32 = 4.0;
+/// ... and above this absolute level
+const SPEECH_MIN_RMS: f32 = 0.015;
+
+/// Levels measured over a calibration recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationReport {
+    /// RMS of the quietest tenth of the recording
+    pub noise_floor: f32,
+    pub peak: f32,
+    /// Share of samples at full scale
+    pub clipping: f32,
+    pub speech_detected: bool,
+    /// "ok", "silent", "too_quiet", "clipping" or "no_speech"
+    pub verdict: &'static str,
+}
+
+/// Measure a mono recording, expected to hold a few seconds of the user talking
+pub fn analyze(samples: &[f32]) -> CalibrationReport {
+    let mut levels: Vec<f32> = samples
+        .chunks(BLOCK)
+        .map(|block| (block.iter().map(|s| s * s).sum::<f32>() / block.len() as f32).sqrt())
+        .collect();
+    levels.sort_by(|a, b| a.total_cmp(b));
+
+    let quietest = (levels.len() / 10).max(1).min(levels.len());
+    let noise_floor = if levels.is_empty() {
+        0.0
+    } else {
+        levels[..quietest].iter().sum::<f32>() / quietest as f32
+    };
+    let loudest = levels.last().copied().unwrap_or(0.0);
+
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let clipped = samples.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+    let clipping = if samples.is_empty() {
+        0.0
+    } else {
+        clipped as f32 / samples.len() as f32
+    };
+    let speech_detected = loudest >= SPEECH_MIN_RMS && loudest >= noise_floor * SPEECH_MARGIN;
+
+    let verdict = if peak < QUIET_FLOOR {
+        "silent"
+    } else if clipping > CLIPPING_RATIO {
+        "clipping"
+    } else if !speech_detected {
+        "no_speech"
+    } else if peak < QUIET_PEAK {
+        "too_quiet"
+    } else {
+        "ok"
+    };
+
+    CalibrationReport {
+        noise_floor,
+        peak,
+        clipping,
+        speech_detected,
+        verdict,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second of quiet hiss followed by one second of a loud tone
+    fn recording(amplitude: f32) -> Vec<f32> {
+        let hiss = (0..16000).map(|i| if i % 2 == 0 { 0.001 } else { -0.001 });
+        let tone = (0..16000).map(move |i| {
+            (2.0 * std::f32::consts::PI * 200.0 * i as f32 / 16000.0).sin() * amplitude
+        });
+        hiss.chain(tone).collect()
+    }
+
+    #[test]
+    fn test_speech_over_quiet_room_is_ok() {
+        let report = analyze(&recording(0.5));
+
+        assert!((report.noise_floor - 0.001).abs() < 1e-4);
+        assert!((report.peak - 0.5).abs() < 0.01);
+        assert!(report.speech_detected);
+        assert_eq!(report.verdict, "ok");
+    }
+
+    #[test]
+    fn test_overdriven_input_is_clipping() {
+        let samples: Vec<f32> = recording(3.0).iter().map(|s| s.clamp(-1.0, 1.0)).collect();
+
+        assert_eq!(analyze(&samples).verdict, "clipping");
+    }
+
+    #[test]
+    fn test_quiet_input_is_reported() {
+        assert_eq!(analyze(&recording(0.05)).verdict, "too_quiet");
+        assert_eq!(analyze(&vec![0.0; 32000]).verdict, "silent");
+        assert_eq!(analyze(&vec![0.001; 32000]).verdict, "no_speech");
+    }
+}
@@ -1,5 +1,198 @@
 use opus2::{Application, Bandwidth, Channels, Decoder, Encoder, Signal};
+use std::borrow::Cow;
 use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Scratch space for one encoded packet, above what Opus emits for a frame
+const MAX_PACKET_SIZE: usize = 4000;
+/// Highest Opus encoder complexity, and the default
+pub const MAX_COMPLEXITY: u8 = 10;
+/// Opus frame durations in units of 2.5ms: 2.5, 5, 10, 20, 40 and 60ms
+const FRAME_DURATIONS: [usize; 6] = [1, 2, 4, 8, 16, 24];
+/// Opens a relay header. Read as a length prefix it is above
+/// `MAX_PACKET_SIZE`, so it can't be mistaken for the start of a legacy blob.
+const RELAY_MAGIC: [u8; 2] = *b"GW";
+/// Magic, u32 sequence number and u64 timestamp, little-endian
+pub const RELAY_HEADER_LEN: usize = 14;
+
+/// What went wrong encoding or decoding, for callers to tell apart
+#[derive(Debug)]
+pub enum CodecError {
+    /// Not one of the rates Opus runs at
+    InvalidSampleRate(u32),
+    /// Not an Opus frame duration at the sample rate
+    InvalidFrameSize { sample_rate: u32, frame_size: usize },
+    /// Stereo input with an odd number of samples
+    NonInterleavedStereo(usize),
+    /// Opus only handles mono and stereo
+    InvalidChannels(usize),
+    /// Rejected by libopus itself
+    OpusError(opus2::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::InvalidSampleRate(rate) => write!(
+                f,
+                "Opus supports 8000, 12000, 16000, 24000 or 48000 Hz, got {}",
+                rate
+            ),
+            CodecError::InvalidFrameSize {
+                sample_rate,
+                frame_size,
+            } => write!(
+                f,
+                "{} is not an Opus frame size at {} Hz, expected one of {:?}",
+                frame_size,
+                sample_rate,
+                get_valid_frame_sizes(*sample_rate)
+            ),
+            CodecError::NonInterleavedStereo(len) => write!(
+                f,
+                "Stereo input must be interleaved, got an odd {} samples",
+                len
+            ),
+            CodecError::InvalidChannels(channels) => {
+                write!(f, "Opus supports 1 or 2 channels, got {}", channels)
+            }
+            CodecError::OpusError(err) => write!(f, "Opus error: {}", err),
+        }
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CodecError::OpusError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<opus2::Error> for CodecError {
+    fn from(err: opus2::Error) -> Self {
+        CodecError::OpusError(err)
+    }
+}
+
+/// Encoder tuning applied by `encode_stereo_to_opus`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncodeOptions {
+    /// Content hint. It only biases Opus's choice between its speech (SILK) and
+    /// music (CELT) modes; the `Application` the encoder was created with still
+    /// decides the overall tuning. `Voice` pairs with `Application::Voip` for
+    /// chat, `Auto` lets Opus classify the input itself.
+    pub signal: Signal,
+    /// Zero-pad a trailing partial frame and encode it instead of dropping it
+    pub pad_final_frame: bool,
+    pub bitrate_mode: BitrateMode,
+    /// Carry a low-bitrate copy of each frame in the next packet, so the
+    /// receiver can rebuild a single lost packet (speech modes only)
+    pub inband_fec: bool,
+    /// Expected packet loss (0-100). Opus spends more on redundancy the higher it is.
+    pub packet_loss_perc: u8,
+    /// Discontinuous transmission: silent frames shrink to a byte or two. In
+    /// framed output they become empty packets, which decoders conceal.
+    pub dtx: bool,
+    /// CPU spent per frame (0-10). Lower values save CPU at some quality cost.
+    pub complexity: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Music,
+            pad_final_frame: false,
+            bitrate_mode: BitrateMode::ConstrainedVbr,
+            inband_fec: false,
+            packet_loss_perc: 0,
+            dtx: false,
+            complexity: MAX_COMPLEXITY,
+        }
+    }
+}
+
+/// How the encoder spends its bitrate from one packet to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitrateMode {
+    /// Every packet the same size, for links provisioned at a fixed rate
+    Cbr,
+    /// Packet size follows the content; best quality per byte
+    Vbr,
+    /// VBR that never overshoots the target by more than a frame's worth
+    /// (the libopus default)
+    ConstrainedVbr,
+}
+
+impl BitrateMode {
+    /// Parse "cbr", "vbr" or "cvbr" (case insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cbr" => Some(Self::Cbr),
+            "vbr" => Some(Self::Vbr),
+            "cvbr" | "constrained_vbr" => Some(Self::ConstrainedVbr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cbr => "cbr",
+            Self::Vbr => "vbr",
+            Self::ConstrainedVbr => "cvbr",
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Defaults for the microphone voice relay
+    pub fn voice() -> Self {
+        Self {
+            signal: Signal::Voice,
+            ..Self::default()
+        }
+    }
+}
+
+/// Split `stereo` into frames of `samples_per_frame`, zero-padding the last
+/// partial one when `pad` is set and dropping it otherwise
+fn frames(
+    stereo: &[f32],
+    samples_per_frame: usize,
+    pad: bool,
+) -> impl Iterator<Item = Cow<'_, [f32]>> {
+    stereo.chunks(samples_per_frame).filter_map(move |chunk| {
+        if chunk.len() == samples_per_frame {
+            Some(Cow::Borrowed(chunk))
+        } else if pad {
+            let mut frame = chunk.to_vec();
+            frame.resize(samples_per_frame, 0.0);
+            Some(Cow::Owned(frame))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse a signal hint name: "voice", "music" or "auto" (case insensitive)
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    match name.to_ascii_lowercase().as_str() {
+        "voice" => Some(Signal::Voice),
+        "music" => Some(Signal::Music),
+        "auto" => Some(Signal::Auto),
+        _ => None,
+    }
+}
+
+pub fn signal_name(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Voice => "voice",
+        Signal::Music => "music",
+        Signal::Auto => "auto",
+    }
+}
 
 /// Encode a stereo f32 buffer to Opus with packet framing.
 /// frame_size = frames per channel
@@ -8,61 +201,438 @@ pub fn encode_stereo_to_opus(
     stereo: &[f32],
     sample_rate: u32,
     frame_size: usize,
-) -> Result<Vec<u8>, Box<dyn Error>> {
-    validate_input(stereo, sample_rate, frame_size)?;
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, CodecError> {
+    let mut buffers = EncodeBuffers::default();
+    encode_framed(
+        encoder,
+        stereo,
+        2,
+        sample_rate,
+        frame_size,
+        options,
+        &mut buffers,
+    )?;
+    Ok(buffers.output)
+}
 
-    encoder.set_bitrate(opus2::Bitrate::Bits(128000))?;
-    encoder.set_bandwidth(Bandwidth::Fullband)?;
-    encoder.set_signal(Signal::Music)?;
+/// Encode a mono f32 buffer to Opus with packet framing, with an encoder
+/// created with `Channels::Mono`. Half the bitrate of mono-as-stereo.
+pub fn encode_mono_to_opus(
+    encoder: &mut Encoder,
+    mono: &[f32],
+    sample_rate: u32,
+    frame_size: usize,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, CodecError> {
+    let mut buffers = EncodeBuffers::default();
+    encode_framed(
+        encoder,
+        mono,
+        1,
+        sample_rate,
+        frame_size,
+        options,
+        &mut buffers,
+    )?;
+    Ok(buffers.output)
+}
 
-    let mut output = Vec::new();
-    let samples_per_frame = frame_size * 2;
-    let total_frames = stereo.len() / samples_per_frame;
+/// Encode an interleaved stereo buffer into `buffers.output`, replacing what
+/// it held. Reuses the caller's buffers, so encoding frame after frame (the
+/// capture callback) stops allocating once they have grown.
+pub fn encode_stereo_to_opus_into(
+    encoder: &mut Encoder,
+    stereo: &[f32],
+    sample_rate: u32,
+    frame_size: usize,
+    options: &EncodeOptions,
+    buffers: &mut EncodeBuffers,
+) -> Result<(), CodecError> {
+    encode_framed(
+        encoder,
+        stereo,
+        2,
+        sample_rate,
+        frame_size,
+        options,
+        buffers,
+    )
+}
+
+/// Caller-owned buffers for `encode_stereo_to_opus_into`
+#[derive(Debug, Default)]
+pub struct EncodeBuffers {
+    /// Space for one packet as the encoder writes it
+    scratch: Vec<u8>,
+    /// Length-prefixed packets of the last encode
+    pub output: Vec<u8>,
+}
 
-    for frame_idx in 0..total_frames {
-        let offset = frame_idx * samples_per_frame;
-        let frame = &stereo[offset..offset + samples_per_frame];
+fn encode_framed(
+    encoder: &mut Encoder,
+    pcm: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    frame_size: usize,
+    options: &EncodeOptions,
+    buffers: &mut EncodeBuffers,
+) -> Result<(), CodecError> {
+    validate_input(pcm, channels, sample_rate, frame_size)?;
 
-        let mut encoded_buf = vec![0u8; 4000];
-        let encoded_len = encoder.encode_float(frame, &mut encoded_buf)?;
+    configure_encoder(encoder, options)?;
 
-        output.extend_from_slice(&(encoded_len as u16).to_le_bytes());
-        output.extend_from_slice(&encoded_buf[..encoded_len]);
+    let samples_per_frame = frame_size * channels;
+    buffers.output.clear();
+    buffers.scratch.resize(MAX_PACKET_SIZE, 0);
+
+    for frame in frames(pcm, samples_per_frame, options.pad_final_frame) {
+        let mut encoded_len = encoder.encode_float(&frame, &mut buffers.scratch)?;
+        if options.dtx && is_dtx_packet(&buffers.scratch[..encoded_len]) {
+            encoded_len = 0;
+        }
+
+        buffers
+            .output
+            .extend_from_slice(&(encoded_len as u16).to_le_bytes());
+        buffers
+            .output
+            .extend_from_slice(&buffers.scratch[..encoded_len]);
     }
 
-    Ok(output)
+    Ok(())
 }
 
-/// Decode Opus data back to stereo f32 buffer
+/// Decode Opus data back to stereo f32 buffer.
+/// `trim` holds the frames per channel still to be dropped from the start of the
+/// stream to compensate the encoder lookahead; it is decremented across calls.
 pub fn decode_opus_to_stereo(
     decoder: &mut Decoder,
     opus_data: &[u8],
-    sample_rate: u32,
+    _sample_rate: u32,
+    frame_size: usize,
+    trim: Option<&mut usize>,
+) -> Result<Vec<f32>, CodecError> {
+    decode_framed(decoder, opus_data, 2, frame_size, trim)
+}
+
+/// Decode Opus data to mono f32 with a `Channels::Mono` decoder, see `decode_opus_to_stereo`
+pub fn decode_opus_to_mono(
+    decoder: &mut Decoder,
+    opus_data: &[u8],
+    _sample_rate: u32,
+    frame_size: usize,
+    trim: Option<&mut usize>,
+) -> Result<Vec<f32>, CodecError> {
+    decode_framed(decoder, opus_data, 1, frame_size, trim)
+}
+
+/// Decode length-prefixed packets to `channels` interleaved channels
+pub fn decode_framed(
+    decoder: &mut Decoder,
+    opus_data: &[u8],
+    channels: usize,
     frame_size: usize,
-) -> Result<Vec<f32>, Box<dyn Error>> {
+    trim: Option<&mut usize>,
+) -> Result<Vec<f32>, CodecError> {
     let mut output = Vec::new();
-    let mut offset = 0;
+    for packet in framed_packets(opus_data) {
+        output.extend(decode_opus_packet(decoder, packet, channels, frame_size));
+    }
+
+    if let Some(pending) = trim {
+        trim_frames(&mut output, channels, pending);
+    }
+
+    Ok(output)
+}
 
-    while offset + 2 <= opus_data.len() {
-        let packet_len = u16::from_le_bytes([opus_data[offset], opus_data[offset + 1]]) as usize;
-        offset += 2;
+/// Decode a relay blob to stereo f32 along with its header. Blobs from
+/// senders without sequencing decode the same, with no header.
+pub fn decode_relay_to_stereo(
+    decoder: &mut Decoder,
+    blob: &[u8],
+    frame_size: usize,
+    trim: Option<&mut usize>,
+) -> Result<(Option<RelayHeader>, Vec<f32>), CodecError> {
+    let (header, _) = split_relay_header(blob);
+    let pcm = decode_framed(decoder, blob, 2, frame_size, trim)?;
+    Ok((header, pcm))
+}
+
+/// Packets of a complete length-prefixed blob; a truncated last packet is
+/// dropped. A leading relay header is skipped.
+pub fn framed_packets(opus_data: &[u8]) -> Vec<&[u8]> {
+    iter_framed_packets(opus_data).collect()
+}
 
-        if offset + packet_len > opus_data.len() {
-            break;
+/// `framed_packets` without collecting them
+fn iter_framed_packets(opus_data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let (_, mut rest) = split_relay_header(opus_data);
+    std::iter::from_fn(move || {
+        let [low, high, tail @ ..] = rest else {
+            return None;
+        };
+        let packet_len = u16::from_le_bytes([*low, *high]) as usize;
+        if packet_len > tail.len() {
+            return None;
         }
 
-        let packet = &opus_data[offset..offset + packet_len];
-        offset += packet_len;
+        let (packet, next) = tail.split_at(packet_len);
+        rest = next;
+        Some(packet)
+    })
+}
+
+/// Ordering info in front of a relay blob, so a receiver can put blobs
+/// back in order and see which never arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayHeader {
+    /// One more than the previous blob's
+    pub sequence: u32,
+    /// Stream position of the blob's first frame, in 48kHz frames since
+    /// capture started. Runs on through frames DTX left out.
+    pub timestamp: u64,
+}
 
-        let mut pcm = vec![0f32; frame_size * 2];
+impl RelayHeader {
+    pub fn to_bytes(&self) -> [u8; RELAY_HEADER_LEN] {
+        let mut bytes = [0u8; RELAY_HEADER_LEN];
+        bytes[..2].copy_from_slice(&RELAY_MAGIC);
+        bytes[2..6].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[6..].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
 
-        match decoder.decode_float(packet, &mut pcm, false) {
-            Ok(decoded_frames) => output.extend_from_slice(&pcm[..decoded_frames * 2]),
-            Err(_) => output.extend(vec![0.0f32; frame_size * 2]),
+    /// The header `bytes` start with, if they start with one
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < RELAY_HEADER_LEN || bytes[..2] != RELAY_MAGIC {
+            return None;
         }
+        Some(Self {
+            sequence: u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(bytes[6..RELAY_HEADER_LEN].try_into().unwrap()),
+        })
     }
+}
 
-    Ok(output)
+/// The header of a relay blob and its length-prefixed packets. Legacy blobs
+/// have no header and are returned whole.
+pub fn split_relay_header(blob: &[u8]) -> (Option<RelayHeader>, &[u8]) {
+    match RelayHeader::parse(blob) {
+        Some(header) => (Some(header), &blob[RELAY_HEADER_LEN..]),
+        None => (None, blob),
+    }
+}
+
+/// Numbers the blobs of the relay. Shared by everything that sends on it
+/// (capture, WAV playback, keepalive) so the sequence stays monotonic.
+#[derive(Debug)]
+pub struct RelayClock {
+    enabled: AtomicBool,
+    sequence: AtomicU32,
+    position: AtomicU64,
+}
+
+impl Default for RelayClock {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            sequence: AtomicU32::new(0),
+            position: AtomicU64::new(0),
+        }
+    }
+}
+
+impl RelayClock {
+    /// Without headers the relay sends legacy blobs, for receivers that
+    /// predate them
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Put a header in front of a framed blob of `frame_size` packets and
+    /// advance the clock past it
+    pub fn stamp(&self, blob: Vec<u8>, frame_size: usize) -> Vec<u8> {
+        let mut stamped = Vec::with_capacity(RELAY_HEADER_LEN + blob.len());
+        self.stamp_into(&blob, frame_size, &mut stamped);
+        stamped
+    }
+
+    /// `stamp`, appending the header and `framed` to `out` so a caller that
+    /// sizes it up front allocates nothing else
+    pub fn stamp_into(&self, framed: &[u8], frame_size: usize, out: &mut Vec<u8>) {
+        let frames = (iter_framed_packets(framed).count() * frame_size) as u64;
+        let timestamp = self.position.fetch_add(frames, Ordering::Relaxed);
+        if self.is_enabled() {
+            let header = RelayHeader {
+                sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+                timestamp,
+            };
+            out.extend_from_slice(&header.to_bytes());
+        }
+        out.extend_from_slice(framed);
+    }
+
+    /// Advance over `frames` that were captured but not sent
+    pub fn skip(&self, frames: usize) {
+        self.position.fetch_add(frames as u64, Ordering::Relaxed);
+    }
+}
+
+/// Decode a single Opus packet, concealing a lost or corrupt one
+pub fn decode_opus_packet_to_stereo(
+    decoder: &mut Decoder,
+    packet: &[u8],
+    frame_size: usize,
+) -> Vec<f32> {
+    decode_opus_packet(decoder, packet, 2, frame_size)
+}
+
+/// Decode a single Opus packet to `channels` interleaved channels. An empty
+/// packet marks a lost one and a corrupt one is treated the same way: both are
+/// filled in by Opus packet loss concealment instead of silence.
+pub fn decode_opus_packet(
+    decoder: &mut Decoder,
+    packet: &[u8],
+    channels: usize,
+    frame_size: usize,
+) -> Vec<f32> {
+    let mut pcm = vec![0f32; frame_size * channels];
+
+    match decoder.decode_float(packet, &mut pcm, false) {
+        Ok(decoded_frames) => pcm.truncate(decoded_frames * channels),
+        Err(_) => return conceal_frame(decoder, None, channels, frame_size),
+    }
+
+    pcm
+}
+
+/// Decode `packet` after `lost` packets went missing right before it. With
+/// `fec` set, the last lost frame is rebuilt from the redundancy `packet`
+/// carries when the sender enabled inband FEC; the other lost frames are
+/// extrapolated by packet loss concealment.
+pub fn decode_after_loss(
+    decoder: &mut Decoder,
+    packet: &[u8],
+    lost: usize,
+    fec: bool,
+    channels: usize,
+    frame_size: usize,
+) -> Vec<f32> {
+    let mut output = Vec::new();
+
+    for i in 0..lost {
+        let recover = fec && !packet.is_empty() && i + 1 == lost;
+        output.extend(conceal_frame(
+            decoder,
+            recover.then_some(packet),
+            channels,
+            frame_size,
+        ));
+    }
+
+    output.extend(decode_opus_packet(decoder, packet, channels, frame_size));
+    output
+}
+
+/// One frame standing in for a lost packet: recovered from the FEC data of
+/// `next` when given, extrapolated by PLC otherwise, silence as a last resort
+fn conceal_frame(
+    decoder: &mut Decoder,
+    next: Option<&[u8]>,
+    channels: usize,
+    frame_size: usize,
+) -> Vec<f32> {
+    let mut pcm = vec![0f32; frame_size * channels];
+
+    match decoder.decode_float(next.unwrap_or(&[]), &mut pcm, next.is_some()) {
+        Ok(decoded_frames) => pcm.truncate(decoded_frames * channels),
+        Err(_) => pcm.fill(0.0),
+    }
+
+    pcm
+}
+
+/// Drop up to `pending` frames from the start of `output`, decrementing `pending`
+pub fn trim_lookahead(output: &mut Vec<f32>, pending: &mut usize) {
+    trim_frames(output, 2, pending);
+}
+
+/// `trim_lookahead` for `channels` interleaved channels
+pub fn trim_frames(output: &mut Vec<f32>, channels: usize, pending: &mut usize) {
+    let dropped = (*pending * channels).min(output.len());
+    output.drain(..dropped);
+    *pending -= dropped / channels;
+}
+
+/// Splits a length-prefixed Opus byte stream back into packets, holding a
+/// partial packet until the rest of its bytes arrive. Lets the framed blobs
+/// from `encode_stereo_to_opus` be chunked arbitrarily by the network.
+#[derive(Debug, Default)]
+pub struct OpusReassembler {
+    pending: Vec<u8>,
+}
+
+impl OpusReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `bytes` and return every packet they complete
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+
+        while offset + 2 <= self.pending.len() {
+            if self.pending[offset..offset + 2] == RELAY_MAGIC {
+                if offset + RELAY_HEADER_LEN > self.pending.len() {
+                    break;
+                }
+                offset += RELAY_HEADER_LEN;
+                continue;
+            }
+
+            let packet_len =
+                u16::from_le_bytes([self.pending[offset], self.pending[offset + 1]]) as usize;
+
+            if offset + 2 + packet_len > self.pending.len() {
+                break;
+            }
+
+            packets.push(self.pending[offset + 2..offset + 2 + packet_len].to_vec());
+            offset += 2 + packet_len;
+        }
+
+        self.pending.drain(..offset);
+        packets
+    }
+
+    /// Bytes buffered for an incomplete packet
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Algorithmic delay of the encoder in frames per channel (OPUS_GET_LOOKAHEAD)
+pub fn encoder_lookahead(encoder: &mut Encoder) -> Result<usize, CodecError> {
+    Ok(encoder.get_lookahead()? as usize)
+}
+
+/// Lookahead of a default Opus encoder (2.5ms + 4ms delay compensation),
+/// for when the encoder lives on the other end of the network
+pub fn default_lookahead(sample_rate: u32) -> usize {
+    sample_rate as usize * 13 / 2000
 }
 
 /// Encode into individual Opus packets
@@ -70,27 +640,21 @@ pub fn encode_stereo_to_opus_packets(
     stereo: &[f32],
     sample_rate: u32,
     frame_size: usize,
-) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
-    validate_input(stereo, sample_rate, frame_size)?;
+    options: &EncodeOptions,
+) -> Result<Vec<Vec<u8>>, CodecError> {
+    validate_input(stereo, 2, sample_rate, frame_size)?;
 
     let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio)?;
-    encoder.set_bitrate(opus2::Bitrate::Bits(128000))?;
-    encoder.set_bandwidth(Bandwidth::Fullband)?;
-    encoder.set_signal(Signal::Music)?;
+    configure_encoder(&mut encoder, options)?;
 
     let mut packets = Vec::new();
     let samples_per_frame = frame_size * 2;
 
-    for chunk in stereo.chunks(samples_per_frame) {
-        if chunk.len() != samples_per_frame {
-            continue;
-        }
-
-        let mut buf = vec![0u8; 4000];
-        let len = encoder.encode_float(chunk, &mut buf)?;
-        buf.truncate(len);
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
 
-        packets.push(buf);
+    for chunk in frames(stereo, samples_per_frame, options.pad_final_frame) {
+        let len = encoder.encode_float(&chunk, &mut buf)?;
+        packets.push(buf[..len].to_vec());
     }
 
     Ok(packets)
@@ -101,87 +665,134 @@ pub fn decode_opus_packets_to_stereo(
     packets: &[Vec<u8>],
     sample_rate: u32,
     frame_size: usize,
-) -> Result<Vec<f32>, Box<dyn Error>> {
+) -> Result<Vec<f32>, CodecError> {
     let mut decoder = Decoder::new(sample_rate, Channels::Stereo)?;
     let mut output = Vec::new();
 
     for packet in packets {
-        let mut pcm = vec![0f32; frame_size * 2];
-
-        match decoder.decode_float(packet, &mut pcm, false) {
-            Ok(decoded_frames) => output.extend_from_slice(&pcm[..decoded_frames * 2]),
-            Err(_) => output.extend(vec![0.0f32; frame_size * 2]),
-        }
+        output.extend(decode_opus_packet_to_stereo(
+            &mut decoder,
+            packet,
+            frame_size,
+        ));
     }
 
     Ok(output)
 }
 
+/// Apply the relay bitrate, bandwidth and `options` to `encoder`
+fn configure_encoder(encoder: &mut Encoder, options: &EncodeOptions) -> Result<(), CodecError> {
+    encoder.set_bitrate(opus2::Bitrate::Bits(128000))?;
+    encoder.set_bandwidth(Bandwidth::Fullband)?;
+    encoder.set_signal(options.signal)?;
+    encoder.set_vbr(options.bitrate_mode != BitrateMode::Cbr)?;
+    encoder.set_vbr_constraint(options.bitrate_mode == BitrateMode::ConstrainedVbr)?;
+    encoder.set_inband_fec(options.inband_fec)?;
+    encoder.set_packet_loss_perc(options.packet_loss_perc.min(100) as i32)?;
+    encoder.set_dtx(options.dtx)?;
+    encoder.set_complexity(options.complexity.min(MAX_COMPLEXITY) as i32)?;
+    Ok(())
+}
+
+/// Packet the encoder emits for a frame DTX left out: at most the TOC byte
+/// and a frame count, without audio data
+pub fn is_dtx_packet(packet: &[u8]) -> bool {
+    packet.len() <= 2
+}
+
 /// Validate input buffer, sample rate, frame size
 fn validate_input(
-    stereo: &[f32],
+    pcm: &[f32],
+    channels: usize,
     sample_rate: u32,
     frame_size: usize,
-) -> Result<(), Box<dyn Error>> {
-    if !matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000) {
-        return Err("Invalid sample rate".into());
+) -> Result<(), CodecError> {
+    if !is_valid_sample_rate(sample_rate) {
+        return Err(CodecError::InvalidSampleRate(sample_rate));
     }
 
-    if !get_valid_frame_sizes(sample_rate).contains(&frame_size) {
-        return Err("Invalid frame size".into());
+    if !is_valid_frame_size(sample_rate, frame_size) {
+        return Err(CodecError::InvalidFrameSize {
+            sample_rate,
+            frame_size,
+        });
     }
 
-    if stereo.len() % 2 != 0 {
-        return Err("Stereo input must be interleaved".into());
+    if channels == 2 && pcm.len() % 2 != 0 {
+        return Err(CodecError::NonInterleavedStereo(pcm.len()));
     }
 
     Ok(())
 }
 
+/// Sample rates Opus encodes and decodes at
+pub fn is_valid_sample_rate(sample_rate: u32) -> bool {
+    matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000)
+}
+
 /// Allowed frame sizes for each sample rate
-fn get_valid_frame_sizes(sample_rate: u32) -> Vec<usize> {
-    match sample_rate {
-        48000 => vec![120, 240, 480, 960, 1920, 2880],
-        24000 => vec![60, 120, 240, 480, 960, 1440],
-        16000 => vec![40, 80, 160, 320, 640, 960],
-        12000 => vec![30, 60, 120, 240, 480, 720],
-        8000 => vec![20, 40, 80, 160, 320, 480],
-        _ => vec![],
+pub fn get_valid_frame_sizes(sample_rate: u32) -> Vec<usize> {
+    if !is_valid_sample_rate(sample_rate) {
+        return Vec::new();
+    }
+    FRAME_DURATIONS
+        .iter()
+        .map(|&duration| duration * sample_rate as usize / 400)
+        .collect()
+}
+
+/// Whether `frame_size` is an Opus frame duration at `sample_rate`, without
+/// building the list
+pub fn is_valid_frame_size(sample_rate: u32, frame_size: usize) -> bool {
+    is_valid_sample_rate(sample_rate)
+        && FRAME_DURATIONS
+            .iter()
+            .any(|&duration| duration * sample_rate as usize / 400 == frame_size)
+}
+
+/// 10ms frames, what the relay sends unless configured otherwise
+pub fn default_frame_size(sample_rate: u32) -> usize {
+    sample_rate as usize / 100
+}
+
+/// Decoder for `channels` (1 or 2) interleaved channels at `sample_rate`
+pub fn new_decoder(sample_rate: u32, channels: usize) -> Result<Decoder, CodecError> {
+    if !is_valid_sample_rate(sample_rate) {
+        return Err(CodecError::InvalidSampleRate(sample_rate));
     }
+    let layout = match channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        _ => return Err(CodecError::InvalidChannels(channels)),
+    };
+    Ok(Decoder::new(sample_rate, layout)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::alloc_count::allocations;
 
-    #[test]
-    fn test_simple_encode_decode() {
-        let sample_rate = 48000;
-        let frame_size = 960;
-        let duration_frames = 50;
-
-        let mut stereo_samples = Vec::new();
-        for i in 0..(frame_size * duration_frames) {
+    fn sine_stereo(sample_rate: u32, frames: usize) -> Vec<f32> {
+        let mut stereo_samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
             let t = i as f32 / sample_rate as f32;
             let s = (2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.5;
             stereo_samples.push(s);
             stereo_samples.push(s);
         }
+        stereo_samples
+    }
 
-        let packets =
-            encode_stereo_to_opus_packets(&stereo_samples, sample_rate, frame_size).unwrap();
-        let decoded = decode_opus_packets_to_stereo(&packets, sample_rate, frame_size).unwrap();
-
-        assert!(decoded.len() > 0);
-
-        let delay = 312 * 2; // approximate Opus lookahead in stereo samples
-        let compare_len = decoded.len().min(stereo_samples.len() - delay);
+    /// SNR of `decoded` against `original`, with `delay` stereo samples of offset
+    fn snr(original: &[f32], decoded: &[f32], delay: usize) -> f64 {
+        let compare_len = decoded.len().min(original.len() - delay);
 
         let mut signal = 0.0;
         let mut noise = 0.0;
 
         for i in 0..compare_len {
-            let orig = stereo_samples[i + delay] as f64;
+            let orig = original[i + delay] as f64;
             let dec = decoded[i] as f64;
             let err = orig - dec;
 
@@ -189,9 +800,605 @@ mod tests {
             noise += err * err;
         }
 
-        let snr = 10.0 * (signal / noise.max(1e-12)).log10();
+        10.0 * (signal / noise.max(1e-12)).log10()
+    }
+
+    #[test]
+    fn test_simple_encode_decode() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+        let duration_frames = 50;
+
+        let stereo_samples = sine_stereo(sample_rate, frame_size * duration_frames);
+
+        let packets = encode_stereo_to_opus_packets(
+            &stereo_samples,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let decoded = decode_opus_packets_to_stereo(&packets, sample_rate, frame_size).unwrap();
+
+        assert!(decoded.len() > 0);
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio).unwrap();
+        let delay = encoder_lookahead(&mut encoder).unwrap() * 2; // lookahead in stereo samples
+
+        let snr = snr(&stereo_samples, &decoded, delay);
         println!("SNR = {:.2} dB", snr);
 
         assert!(snr > 25.0, "SNR too low!"); // agora seguro para f32 contínuo
     }
+
+    #[test]
+    fn test_delay_compensated_decode_is_aligned() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+
+        let stereo_samples = sine_stereo(sample_rate, frame_size * 50);
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio).unwrap();
+        let mut pending = encoder_lookahead(&mut encoder).unwrap();
+        assert_eq!(pending, default_lookahead(sample_rate));
+
+        let encoded = encode_stereo_to_opus(
+            &mut encoder,
+            &stereo_samples,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+
+        // Split on a packet boundary so the trim has to carry across calls
+        let mut split = 0;
+        for _ in 0..25 {
+            split += 2 + u16::from_le_bytes([encoded[split], encoded[split + 1]]) as usize;
+        }
+
+        let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+        let mut decoded = decode_opus_to_stereo(
+            &mut decoder,
+            &encoded[..split],
+            sample_rate,
+            frame_size,
+            Some(&mut pending),
+        )
+        .unwrap();
+        decoded.extend(
+            decode_opus_to_stereo(
+                &mut decoder,
+                &encoded[split..],
+                sample_rate,
+                frame_size,
+                Some(&mut pending),
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(pending, 0);
+        assert!(
+            snr(&stereo_samples, &decoded, 0) > 25.0,
+            "decoded audio is not aligned"
+        );
+    }
+
+    fn framed(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut blob = Vec::new();
+        for packet in packets {
+            blob.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+            blob.extend_from_slice(packet);
+        }
+        blob
+    }
+
+    #[test]
+    fn test_reassembler_handles_any_split() {
+        let packets: Vec<Vec<u8>> = (1..6u8).map(|n| vec![n; n as usize * 7]).collect();
+        let blob = framed(&packets);
+
+        for split in 0..=blob.len() {
+            let mut reassembler = OpusReassembler::new();
+            let mut out = reassembler.push(&blob[..split]);
+            out.extend(reassembler.push(&blob[split..]));
+
+            assert_eq!(out, packets, "split at {}", split);
+            assert_eq!(reassembler.pending_len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_reassembler_byte_by_byte_decodes_like_whole_blob() {
+        let sample_rate = 48000;
+        let frame_size = 480;
+
+        let stereo_samples = sine_stereo(sample_rate, frame_size * 20);
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Voip).unwrap();
+        let blob = encode_stereo_to_opus(
+            &mut encoder,
+            &stereo_samples,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+
+        let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+        let whole =
+            decode_opus_to_stereo(&mut decoder, &blob, sample_rate, frame_size, None).unwrap();
+
+        let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+        let mut reassembler = OpusReassembler::new();
+        let mut streamed = Vec::new();
+        for byte in &blob {
+            for packet in reassembler.push(std::slice::from_ref(byte)) {
+                streamed.extend(decode_opus_packet_to_stereo(
+                    &mut decoder,
+                    &packet,
+                    frame_size,
+                ));
+            }
+        }
+
+        assert_eq!(streamed, whole);
+        assert_eq!(reassembler.pending_len(), 0);
+    }
+
+    #[test]
+    fn test_padded_final_frame_keeps_the_tail() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+        // Ten and a half frames: the last half frame is the tail under test
+        let total = frame_size * 10 + frame_size / 2;
+        let stereo_samples = sine_stereo(sample_rate, total);
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio).unwrap();
+        let delay = encoder_lookahead(&mut encoder).unwrap();
+
+        let dropped = encode_stereo_to_opus_packets(
+            &stereo_samples,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(dropped.len(), 10);
+
+        let options = EncodeOptions {
+            pad_final_frame: true,
+            ..EncodeOptions::default()
+        };
+        let packets =
+            encode_stereo_to_opus_packets(&stereo_samples, sample_rate, frame_size, &options)
+                .unwrap();
+        assert_eq!(packets.len(), 11);
+
+        let decoded = decode_opus_packets_to_stereo(&packets, sample_rate, frame_size).unwrap();
+        assert!(decoded.len() >= (total + delay) * 2);
+
+        let tail_start = frame_size * 10 * 2;
+        let tail = &stereo_samples[tail_start..];
+        let decoded_tail = &decoded[tail_start + delay * 2..tail_start + delay * 2 + tail.len()];
+        let snr = snr(tail, decoded_tail, 0);
+        println!("Tail SNR = {:.2} dB", snr);
+
+        assert!(snr > 15.0, "tail lost in the padded frame");
+    }
+
+    #[test]
+    fn test_mono_encode_decode() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+
+        let mono: Vec<f32> = sine_stereo(sample_rate, frame_size * 50)
+            .into_iter()
+            .step_by(2)
+            .collect();
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Mono, Application::Audio).unwrap();
+        let encoded = encode_mono_to_opus(
+            &mut encoder,
+            &mono,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+
+        let mut decoder = Decoder::new(sample_rate, Channels::Mono).unwrap();
+        let mut pending = encoder_lookahead(&mut encoder).unwrap();
+        let decoded = decode_opus_to_mono(
+            &mut decoder,
+            &encoded,
+            sample_rate,
+            frame_size,
+            Some(&mut pending),
+        )
+        .unwrap();
+
+        assert_eq!(pending, 0);
+        assert_eq!(
+            decoded.len(),
+            mono.len() - encoder_lookahead(&mut encoder).unwrap()
+        );
+
+        let snr = snr(&mono, &decoded, 0);
+        println!("Mono SNR = {:.2} dB", snr);
+        assert!(snr > 25.0, "SNR too low!");
+    }
+
+    #[test]
+    fn test_cbr_packets_keep_a_constant_size() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+
+        // Sweep from 100Hz to 8kHz so the content keeps changing
+        let frames = frame_size * 50;
+        let mut sweep = Vec::with_capacity(frames * 2);
+        let mut phase = 0.0f32;
+        for i in 0..frames {
+            let freq = 100.0 + 7900.0 * i as f32 / frames as f32;
+            phase += 2.0 * std::f32::consts::PI * freq / sample_rate as f32;
+            let s = phase.sin() * 0.5;
+            sweep.push(s);
+            sweep.push(s);
+        }
+
+        let sizes = |mode| -> Vec<usize> {
+            let options = EncodeOptions {
+                bitrate_mode: mode,
+                ..EncodeOptions::default()
+            };
+            encode_stereo_to_opus_packets(&sweep, sample_rate, frame_size, &options)
+                .unwrap()
+                .iter()
+                .map(|packet| packet.len())
+                .collect()
+        };
+
+        let cbr = sizes(BitrateMode::Cbr);
+        let vbr = sizes(BitrateMode::Vbr);
+        assert_eq!(cbr.len(), vbr.len());
+
+        let (min, max) = (cbr.iter().min().unwrap(), cbr.iter().max().unwrap());
+        println!("CBR packet sizes {}..={}", min, max);
+        assert!(
+            max - min <= 2,
+            "CBR packet sizes vary from {} to {}",
+            min,
+            max
+        );
+    }
+
+    #[test]
+    fn test_lost_packets_are_concealed_not_silenced() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+
+        let stereo_samples = sine_stereo(sample_rate, frame_size * 20);
+        let options = EncodeOptions {
+            inband_fec: true,
+            packet_loss_perc: 20,
+            ..EncodeOptions::voice()
+        };
+        let packets =
+            encode_stereo_to_opus_packets(&stereo_samples, sample_rate, frame_size, &options)
+                .unwrap();
+
+        // Drop packets 10 and 11
+        let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+        let mut decoded = Vec::new();
+        for packet in &packets[..10] {
+            decoded.extend(decode_opus_packet(&mut decoder, packet, 2, frame_size));
+        }
+        let recovered = decode_after_loss(&mut decoder, &packets[12], 2, true, 2, frame_size);
+
+        assert_eq!(recovered.len(), frame_size * 2 * 3);
+        let concealed = &recovered[..frame_size * 2 * 2];
+        let energy = concealed.iter().map(|s| s * s).sum::<f32>() / concealed.len() as f32;
+        assert!(energy > 0.01, "lost packets decoded as silence");
+
+        // An empty packet stands for a lost one too
+        let marker = decode_opus_packet(&mut decoder, &[], 2, frame_size);
+        assert_eq!(marker.len(), frame_size * 2);
+    }
+
+    #[test]
+    fn test_malformed_blobs_decode_without_panicking() {
+        let sample_rate = 48000;
+        let frame_size = 480;
+
+        let stereo_samples = sine_stereo(sample_rate, frame_size * 4);
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Voip).unwrap();
+        let blob = encode_stereo_to_opus(
+            &mut encoder,
+            &stereo_samples,
+            sample_rate,
+            frame_size,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+
+        let garbage: Vec<u8> = (0..997u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let inputs: [&[u8]; 5] = [
+            &[],
+            &[0xff],
+            &blob[..blob.len() - 3],
+            &garbage,
+            &[4, 0, 0xde, 0xad, 0xbe, 0xef],
+        ];
+
+        for input in inputs {
+            let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+            let decoded =
+                decode_opus_to_stereo(&mut decoder, input, sample_rate, frame_size, None).unwrap();
+            assert!(decoded.len() % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn test_frame_sizes_keep_their_duration_across_rates() {
+        let at_48k = get_valid_frame_sizes(48000);
+        for rate in [8000, 12000, 16000, 24000] {
+            assert!(is_valid_sample_rate(rate));
+            let scaled: Vec<usize> = at_48k.iter().map(|f| f * rate as usize / 48000).collect();
+            assert_eq!(scaled, get_valid_frame_sizes(rate));
+        }
+        assert!(!is_valid_sample_rate(44100));
+        assert!(get_valid_frame_sizes(44100).is_empty());
+    }
+
+    #[test]
+    fn test_encoding_reuses_one_scratch_buffer() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+        let frame_count = 250; // 5 seconds
+        let stereo = sine_stereo(sample_rate, frame_size * frame_count);
+        let options = EncodeOptions::default();
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio).unwrap();
+        let before = allocations();
+        let encoded =
+            encode_stereo_to_opus(&mut encoder, &stereo, sample_rate, frame_size, &options)
+                .unwrap();
+        let framed = allocations() - before;
+        println!(
+            "encode_stereo_to_opus: {} allocations for {} frames",
+            framed, frame_count
+        );
+        assert_eq!(framed_packets(&encoded).len(), frame_count);
+        // The scratch buffer plus the amortized growth of the output
+        assert!(framed < frame_count / 4, "{} allocations", framed);
+
+        let before = allocations();
+        let packets =
+            encode_stereo_to_opus_packets(&stereo, sample_rate, frame_size, &options).unwrap();
+        let per_packet = allocations() - before;
+        println!(
+            "encode_stereo_to_opus_packets: {} allocations for {} frames",
+            per_packet, frame_count
+        );
+        assert_eq!(packets.len(), frame_count);
+        // One per packet, the scratch buffer and the amortized growth of the list
+        assert!(
+            per_packet < frame_count + frame_count / 4,
+            "{} allocations",
+            per_packet
+        );
+        assert!(
+            packets
+                .iter()
+                .all(|packet| packet.capacity() == packet.len())
+        );
+    }
+
+    #[test]
+    fn test_encoding_into_warm_buffers_does_not_allocate() {
+        let frame_size = 480;
+        let stereo = sine_stereo(48000, frame_size * 100);
+        let options = EncodeOptions::default();
+        let clock = RelayClock::default();
+        let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap();
+        let mut buffers = EncodeBuffers::default();
+        let mut stamped = Vec::with_capacity(MAX_PACKET_SIZE);
+        let mut encode = |frame: &[f32], buffers: &mut EncodeBuffers| {
+            encode_stereo_to_opus_into(&mut encoder, frame, 48000, frame_size, &options, buffers)
+                .unwrap();
+            stamped.clear();
+            clock.stamp_into(&buffers.output, frame_size, &mut stamped);
+        };
+
+        let mut frames = stereo.chunks(frame_size * 2);
+        encode(frames.next().unwrap(), &mut buffers);
+
+        let before = allocations();
+        for frame in frames {
+            encode(frame, &mut buffers);
+        }
+        let steady = allocations() - before;
+
+        assert_eq!(steady, 0, "{} allocations in 99 frames", steady);
+        assert_eq!(framed_packets(&buffers.output).len(), 1);
+    }
+
+    #[test]
+    fn test_new_decoder_validates_its_layout() {
+        assert!(new_decoder(48000, 2).is_ok());
+        assert!(new_decoder(16000, 1).is_ok());
+        assert!(matches!(
+            new_decoder(44100, 2),
+            Err(CodecError::InvalidSampleRate(44100))
+        ));
+        assert!(matches!(
+            new_decoder(48000, 3),
+            Err(CodecError::InvalidChannels(3))
+        ));
+        assert_eq!(default_frame_size(48000), 480);
+        assert!(get_valid_frame_sizes(8000).contains(&default_frame_size(8000)));
+    }
+
+    #[test]
+    fn test_dtx_shrinks_silence() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+        // A second of faint noise, which DTX should treat as silence
+        let quiet: Vec<f32> = (0..sample_rate as usize * 2)
+            .map(|i| ((i as u32).wrapping_mul(2654435761) >> 16) as f32 / 65536.0 * 2e-4 - 1e-4)
+            .collect();
+
+        let encoded_size = |dtx| {
+            let options = EncodeOptions {
+                dtx,
+                ..EncodeOptions::voice()
+            };
+            let mut encoder =
+                Encoder::new(sample_rate, Channels::Stereo, Application::Voip).unwrap();
+            encode_stereo_to_opus(&mut encoder, &quiet, sample_rate, frame_size, &options)
+                .unwrap()
+                .len()
+        };
+
+        let continuous = encoded_size(false);
+        let dtx = encoded_size(true);
+        println!("Silence: {} bytes, {} with DTX", continuous, dtx);
+        assert!(dtx * 4 < continuous, "{} vs {} bytes", dtx, continuous);
+
+        // The empty packets decode as concealment, keeping the timeline intact
+        let options = EncodeOptions {
+            dtx: true,
+            ..EncodeOptions::voice()
+        };
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Voip).unwrap();
+        let encoded =
+            encode_stereo_to_opus(&mut encoder, &quiet, sample_rate, frame_size, &options).unwrap();
+        let mut decoder = Decoder::new(sample_rate, Channels::Stereo).unwrap();
+        let decoded =
+            decode_opus_to_stereo(&mut decoder, &encoded, sample_rate, frame_size, None).unwrap();
+        assert_eq!(decoded.len(), quiet.len());
+    }
+
+    #[test]
+    fn test_invalid_input_reports_what_was_wrong() {
+        let options = EncodeOptions::default();
+        let stereo = vec![0.0; 960 * 2];
+
+        assert!(matches!(
+            encode_stereo_to_opus_packets(&stereo, 44100, 960, &options),
+            Err(CodecError::InvalidSampleRate(44100))
+        ));
+        assert!(matches!(
+            encode_stereo_to_opus_packets(&stereo, 48000, 1000, &options),
+            Err(CodecError::InvalidFrameSize {
+                sample_rate: 48000,
+                frame_size: 1000
+            })
+        ));
+        assert!(matches!(
+            encode_stereo_to_opus_packets(&stereo[1..], 48000, 960, &options),
+            Err(CodecError::NonInterleavedStereo(1919))
+        ));
+    }
+
+    #[test]
+    fn test_lowest_complexity_still_carries_the_signal() {
+        let sample_rate = 48000;
+        let frame_size = 960;
+        let stereo_samples = sine_stereo(sample_rate, frame_size * 50);
+
+        let options = EncodeOptions {
+            complexity: 0,
+            ..EncodeOptions::default()
+        };
+        let packets =
+            encode_stereo_to_opus_packets(&stereo_samples, sample_rate, frame_size, &options)
+                .unwrap();
+        let decoded = decode_opus_packets_to_stereo(&packets, sample_rate, frame_size).unwrap();
+
+        let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio).unwrap();
+        let delay = encoder_lookahead(&mut encoder).unwrap() * 2;
+        assert!(snr(&stereo_samples, &decoded, delay) > 15.0);
+    }
+
+    #[test]
+    fn test_relay_headers_number_blobs_and_decode_like_legacy_ones() {
+        let frame_size = 480;
+        let stereo = sine_stereo(48000, frame_size * 4);
+        let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap();
+        let options = EncodeOptions::default();
+        let clock = RelayClock::default();
+
+        let mut blobs = Vec::new();
+        for (i, frames) in stereo.chunks(frame_size * 2).enumerate() {
+            let blob =
+                encode_stereo_to_opus(&mut encoder, frames, 48000, frame_size, &options).unwrap();
+            if i == 1 {
+                // Left out by DTX: the timestamp still moves on
+                clock.skip(frame_size);
+                continue;
+            }
+            blobs.push(clock.stamp(blob, frame_size));
+        }
+
+        let headers: Vec<_> = blobs
+            .iter()
+            .map(|blob| split_relay_header(blob).0.unwrap())
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                RelayHeader {
+                    sequence: 0,
+                    timestamp: 0
+                },
+                RelayHeader {
+                    sequence: 1,
+                    timestamp: 960
+                },
+                RelayHeader {
+                    sequence: 2,
+                    timestamp: 1440
+                },
+            ]
+        );
+
+        let mut legacy = Vec::new();
+        for blob in &blobs {
+            legacy.extend_from_slice(split_relay_header(blob).1);
+        }
+        let mut decoder = new_decoder(48000, 2).unwrap();
+        let expected =
+            decode_opus_to_stereo(&mut decoder, &legacy, 48000, frame_size, None).unwrap();
+
+        let mut decoder = new_decoder(48000, 2).unwrap();
+        let mut decoded = Vec::new();
+        for blob in &blobs {
+            let (header, pcm) =
+                decode_relay_to_stereo(&mut decoder, blob, frame_size, None).unwrap();
+            assert!(header.is_some());
+            decoded.extend(pcm);
+        }
+        assert_eq!(decoded, expected);
+
+        // A stream of stamped blobs reassembles to the same packets
+        let stream = blobs.concat();
+        let mut reassembler = OpusReassembler::new();
+        let mut packets = Vec::new();
+        for byte in &stream {
+            packets.extend(reassembler.push(std::slice::from_ref(byte)));
+        }
+        assert_eq!(packets.len(), 3);
+        assert_eq!(reassembler.pending_len(), 0);
+
+        clock.set_enabled(false);
+        let blob = encode_stereo_to_opus(
+            &mut encoder,
+            &stereo[..frame_size * 2],
+            48000,
+            frame_size,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(clock.stamp(blob.clone(), frame_size), blob);
+    }
 }
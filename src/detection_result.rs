@@ -0,0 +1,64 @@
+use godot::prelude::*;
+
+use crate::whisper::KeywordDetection;
+
+/// Keyword detection handed to GDScript through the `detected` signal
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub struct DetectionResult {
+    #[var]
+    keyword: GString,
+    #[var]
+    spell: GString,
+    #[var]
+    transcription: GString,
+    #[var]
+    confidence: f32,
+    /// Seconds since the Unix epoch
+    #[var]
+    timestamp: f64,
+    /// Dictionaries with `text`, `t0` and `t1` (centiseconds from the utterance start)
+    #[var]
+    segments: Array<Dictionary>,
+    /// Tokens that spelled the keyword, as dictionaries with `text`, `t0` and `t1`
+    #[var]
+    words: Array<Dictionary>,
+}
+
+impl DetectionResult {
+    pub fn from_detection(detection: &KeywordDetection, spell: &str) -> Gd<Self> {
+        let mut segments: Array<Dictionary> = Array::new();
+        for segment in &detection.segments {
+            let mut dict = Dictionary::new();
+            dict.set("text", segment.text.clone());
+            dict.set("t0", segment.t0);
+            dict.set("t1", segment.t1);
+            segments.push(&dict);
+        }
+
+        let mut words: Array<Dictionary> = Array::new();
+        for (text, t0, t1) in &detection.words {
+            let mut dict = Dictionary::new();
+            dict.set("text", text.clone());
+            dict.set("t0", *t0);
+            dict.set("t1", *t1);
+            words.push(&dict);
+        }
+
+        let timestamp = detection
+            .timestamp
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or_default();
+
+        Gd::from_object(Self {
+            keyword: GString::from(detection.keyword.as_str()),
+            spell: GString::from(spell),
+            transcription: GString::from(detection.transcription.as_str()),
+            confidence: detection.confidence,
+            timestamp,
+            segments,
+            words,
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+
+/// Filter length in samples, 64ms at 16kHz
+pub const ECHO_TAPS: usize = 1024;
+/// NLMS adaptation step, between 0 (frozen) and 1 (fastest, least stable)
+const ECHO_STEP: f32 = 0.3;
+/// Keeps the normalization finite while the reference is silent
+const ECHO_EPSILON: f32 = 1e-3;
+/// Reference audio queued ahead of the capture before the oldest is dropped
+const MAX_REFERENCE: usize = 16000;
+
+/// NLMS echo canceller. The known playback (reference) is queued with
+/// `push_reference`, then each captured sample is paired with the next
+/// reference sample and the adaptive estimate of its echo is subtracted.
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    /// Reference window, written twice so `pos..pos + taps` is always contiguous
+    history: Vec<f32>,
+    pos: usize,
+    reference: VecDeque<f32>,
+}
+
+impl EchoCanceller {
+    pub fn new(taps: usize) -> Self {
+        let taps = taps.max(1);
+        Self {
+            weights: vec![0.0; taps],
+            history: vec![0.0; taps * 2],
+            pos: 0,
+            reference: VecDeque::with_capacity(MAX_REFERENCE),
+        }
+    }
+
+    /// Queue playback audio, at the capture rate, that may leak into the microphone
+    pub fn push_reference(&mut self, samples: &[f32]) {
+        self.reference.extend(samples);
+        let excess = self.reference.len().saturating_sub(MAX_REFERENCE);
+        self.reference.drain(..excess);
+    }
+
+    /// Remove the estimated echo from `capture` in place
+    pub fn process(&mut self, capture: &mut [f32]) {
+        for sample in capture.iter_mut() {
+            *sample = self.process_sample(*sample);
+        }
+    }
+
+    /// Forget the adapted filter and queued reference
+    pub fn reset(&mut self) {
+        self.weights.fill(0.0);
+        self.history.fill(0.0);
+        self.pos = 0;
+        self.reference.clear();
+    }
+
+    fn process_sample(&mut self, captured: f32) -> f32 {
+        let taps = self.weights.len();
+        let reference = self.reference.pop_front().unwrap_or(0.0);
+        self.history[self.pos] = reference;
+        self.history[self.pos + taps] = reference;
+        self.pos = (self.pos + 1) % taps;
+
+        // Oldest to newest
+        let window = &self.history[self.pos..self.pos + taps];
+
+        let mut echo = 0.0;
+        let mut energy = 0.0;
+        for (x, w) in window.iter().zip(&self.weights) {
+            echo += x * w;
+            energy += x * x;
+        }
+
+        let error = captured - echo;
+        let gain = ECHO_STEP * error / (energy + ECHO_EPSILON);
+        for (w, x) in self.weights.iter_mut().zip(window) {
+            *w += gain * x;
+        }
+
+        error
+    }
+}
+
+impl Default for EchoCanceller {
+    fn default() -> Self {
+        Self::new(ECHO_TAPS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white-ish noise in [-0.5, 0.5]
+    fn noise(len: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn test_delayed_scaled_echo_is_removed() {
+        let reference = noise(16000);
+        let delay = 40;
+        let mut capture: Vec<f32> = (0..reference.len())
+            .map(|i| {
+                if i >= delay {
+                    reference[i - delay] * 0.6
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let before = energy(&capture[15000..]);
+
+        let mut canceller = EchoCanceller::new(128);
+        canceller.push_reference(&reference);
+        canceller.process(&mut capture);
+        let after = energy(&capture[15000..]);
+
+        assert!(
+            after < before * 0.01,
+            "echo only reduced from {} to {}",
+            before,
+            after
+        );
+    }
+
+    #[test]
+    fn test_capture_passes_through_without_reference() {
+        let mut capture = vec![0.25, -0.5, 0.75];
+        EchoCanceller::new(16).process(&mut capture);
+
+        assert_eq!(capture, vec![0.25, -0.5, 0.75]);
+    }
+}
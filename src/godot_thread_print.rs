@@ -1,10 +1,24 @@
+use godot::classes::ProjectSettings;
 use godot::prelude::*;
 #[cfg(debug_assertions)]
 use std::fs::{self, OpenOptions};
 #[cfg(debug_assertions)]
 use std::io::Write;
+use std::path::PathBuf;
 #[cfg(debug_assertions)]
-use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+static LOG_PATH: OnceLock<Mutex<PathBuf>> = OnceLock::new();
+
+/// Set once the log file failed, so the console isn't flooded from the audio threads
+#[cfg(debug_assertions)]
+static LOG_FAILED: AtomicBool = AtomicBool::new(false);
+
+fn log_path() -> &'static Mutex<PathBuf> {
+    LOG_PATH
+        .get_or_init(|| Mutex::new(std::env::temp_dir().join("godot_whisper").join("debug.txt")))
+}
 
 #[derive(GodotClass)]
 #[class(base=Object)]
@@ -21,26 +35,45 @@ impl IObject for GodotThreadPrint {
 
 #[godot_api]
 impl GodotThreadPrint {
+    /// File the debug log is appended to (defaults to `godot_whisper/debug.txt`
+    /// in the OS temp directory). Accepts `user://` and `res://` paths.
+    #[func]
+    pub fn set_debug_log_path(path: GString) {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        if let Ok(mut current) = log_path().lock() {
+            *current = PathBuf::from(path);
+        }
+    }
+
     #[cfg(debug_assertions)]
     pub fn print(message: String) {
-        let path = Path::new(r"C:\godot_whisper\debug.txt");
+        let path = match log_path().lock() {
+            Ok(path) => path.clone(),
+            Err(_) => return,
+        };
 
+        if let Err(err) = Self::append(&path, &message) {
+            if !LOG_FAILED.swap(true, Ordering::Relaxed) {
+                godot_error!("Can't write the debug log to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn print(_message: String) {}
+
+    #[cfg(debug_assertions)]
+    fn append(path: &std::path::Path, message: &str) -> std::io::Result<()> {
         // Ensure the directory exists
         if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).unwrap();
+            fs::create_dir_all(parent)?;
         }
 
         // Open file for append (create if it does not exist)
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .unwrap();
-
-        // Write the content (with newline if desired)
-        file.write_all(format!("{}\n", message).as_bytes()).unwrap();
-    }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
 
-    #[cfg(not(debug_assertions))]
-    pub fn print(_message: String) {}
+        file.write_all(format!("{}\n", message).as_bytes())
+    }
 }
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+/// Packets held, in multiples of the depth, before the oldest are skipped to
+/// catch up with the sender (e.g. after the receiver stalled)
+const OVERFLOW_FACTOR: usize = 4;
+
+/// What to play for the next slot of the stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// The packet with the next sequence number
+    Packet(Vec<u8>),
+    /// That packet is missing; play a concealment frame in its place
+    Lost,
+    /// Still filling up to the depth, nothing to play yet
+    Waiting,
+}
+
+/// Reorders network packets by sequence number and hands them out one slot
+/// at a time. Playout starts once `depth` packets are buffered, giving late
+/// packets that long to slot in; packets arriving after their slot played
+/// are dropped.
+#[derive(Debug)]
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<u8>>,
+    depth: usize,
+    /// Sequence number of the next slot to play, once playing
+    next: Option<u64>,
+    playing: bool,
+    /// Slots played as lost in a row
+    missing: usize,
+    /// Packets dropped for arriving after their slot played
+    pub late: u64,
+    /// Slots played without their packet
+    pub lost: u64,
+    /// Packets skipped because too many were buffered
+    pub overflowed: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            depth: depth.max(1),
+            next: None,
+            playing: false,
+            missing: 0,
+            late: 0,
+            lost: 0,
+            overflowed: 0,
+        }
+    }
+
+    /// Packets buffered before playout starts, and after an underrun
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.max(1);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Packets waiting to be played
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Forget all packets and counters, e.g. when the sender reconnects
+    pub fn reset(&mut self) {
+        *self = Self::new(self.depth);
+    }
+
+    /// Take packet `sequence`. Returns false when it came too late to play
+    /// or duplicates one already buffered.
+    pub fn push(&mut self, sequence: u64, packet: Vec<u8>) -> bool {
+        if self.next.is_some_and(|next| sequence < next) {
+            self.late += 1;
+            return false;
+        }
+        if self.packets.contains_key(&sequence) {
+            return false;
+        }
+        self.packets.insert(sequence, packet);
+
+        while self.packets.len() > self.depth * OVERFLOW_FACTOR {
+            self.packets.pop_first();
+            self.overflowed += 1;
+            if let (Some(next), Some((&oldest, _))) = (self.next, self.packets.first_key_value()) {
+                self.next = Some(next.max(oldest));
+            }
+        }
+        true
+    }
+
+    /// What to play for the next slot
+    pub fn pop(&mut self) -> Playout {
+        if !self.playing {
+            let Some((&first, _)) = self.packets.first_key_value() else {
+                return Playout::Waiting;
+            };
+            if self.packets.len() < self.depth {
+                return Playout::Waiting;
+            }
+            self.playing = true;
+            // After an underrun, carry on from where playout stopped
+            self.next = Some(self.next.map_or(first, |next| next.max(first)));
+        }
+
+        let next = self.next.unwrap_or_default();
+        self.next = Some(next + 1);
+        if let Some(packet) = self.packets.remove(&next) {
+            self.missing = 0;
+            return Playout::Packet(packet);
+        }
+
+        self.lost += 1;
+        self.missing += 1;
+        // The sender went quiet: stop concealing and wait to fill up again
+        if self.packets.is_empty() && self.missing >= self.depth {
+            self.playing = false;
+            self.missing = 0;
+        }
+        Playout::Lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: u64) -> Vec<u8> {
+        vec![sequence as u8]
+    }
+
+    #[test]
+    fn test_packets_come_out_in_sequence_order() {
+        let mut buffer = JitterBuffer::new(3);
+        assert!(buffer.push(11, packet(11)));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        assert!(buffer.push(10, packet(10)));
+        assert!(buffer.push(12, packet(12)));
+        assert!(!buffer.push(12, packet(12)));
+
+        assert_eq!(buffer.pop(), Playout::Packet(packet(10)));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(11)));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(12)));
+    }
+
+    #[test]
+    fn test_missing_packets_are_concealed_and_late_ones_dropped() {
+        let mut buffer = JitterBuffer::new(2);
+        buffer.push(0, packet(0));
+        buffer.push(2, packet(2));
+
+        assert_eq!(buffer.pop(), Playout::Packet(packet(0)));
+        assert_eq!(buffer.pop(), Playout::Lost);
+        // Arrives after its slot was concealed
+        assert!(!buffer.push(1, packet(1)));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(2)));
+
+        assert_eq!(buffer.lost, 1);
+        assert_eq!(buffer.late, 1);
+    }
+
+    #[test]
+    fn test_underrun_refills_before_playing_again() {
+        let mut buffer = JitterBuffer::new(2);
+        buffer.push(0, packet(0));
+        buffer.push(1, packet(1));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(0)));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(1)));
+
+        // Two slots of concealment, then it stops and waits
+        assert_eq!(buffer.pop(), Playout::Lost);
+        assert_eq!(buffer.pop(), Playout::Lost);
+        assert_eq!(buffer.pop(), Playout::Waiting);
+
+        buffer.push(5, packet(5));
+        assert_eq!(buffer.pop(), Playout::Waiting);
+        buffer.push(6, packet(6));
+        assert_eq!(buffer.pop(), Playout::Packet(packet(5)));
+    }
+
+    #[test]
+    fn test_overflow_skips_to_the_newest_packets() {
+        let mut buffer = JitterBuffer::new(1);
+        for sequence in 0..10 {
+            buffer.push(sequence, packet(sequence));
+        }
+
+        assert_eq!(buffer.len(), OVERFLOW_FACTOR);
+        assert_eq!(buffer.overflowed, 10 - OVERFLOW_FACTOR as u64);
+        assert_eq!(
+            buffer.pop(),
+            Playout::Packet(packet(10 - OVERFLOW_FACTOR as u64))
+        );
+    }
+}
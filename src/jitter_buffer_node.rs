@@ -0,0 +1,165 @@
+use godot::prelude::*;
+use opus2::Decoder;
+
+use crate::codec::{
+    RelayHeader, decode_opus_packet, decode_opus_to_stereo, default_frame_size,
+    get_valid_frame_sizes, new_decoder,
+};
+use crate::jitter_buffer::{JitterBuffer, Playout};
+
+/// Rate of the relay the `speak` signal carries
+const SAMPLE_RATE: u32 = 48000;
+/// Packets buffered before playout unless configured otherwise
+const DEFAULT_DEPTH: usize = 3;
+
+/// Receives `speak` blobs from the network with their sequence numbers,
+/// puts them back in order and plays them out at the pace they were
+/// captured, concealing the ones that never arrive.
+#[derive(GodotClass)]
+#[class(base=Node)]
+pub struct JitterBufferNode {
+    base: Base<Node>,
+    decoder: Decoder,
+    buffer: JitterBuffer,
+    frame_size: usize,
+    /// Seconds of audio emitted ahead of the playout clock
+    ahead: f64,
+}
+
+#[godot_api]
+impl INode for JitterBufferNode {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            base,
+            decoder: new_decoder(SAMPLE_RATE, 2).unwrap(),
+            buffer: JitterBuffer::new(DEFAULT_DEPTH),
+            frame_size: default_frame_size(SAMPLE_RATE),
+            ahead: 0.0,
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        self.ahead -= delta;
+        while self.ahead <= 0.0 {
+            let pcm = match self.buffer.pop() {
+                Playout::Waiting => {
+                    // Don't build up a backlog to burst out once packets arrive
+                    self.ahead = 0.0;
+                    break;
+                }
+                Playout::Packet(blob) => match decode_opus_to_stereo(
+                    &mut self.decoder,
+                    &blob,
+                    SAMPLE_RATE,
+                    self.frame_size,
+                    None,
+                ) {
+                    Ok(pcm) => pcm,
+                    Err(err) => {
+                        godot_error!("JitterBufferNode: {}", err);
+                        Vec::new()
+                    }
+                },
+                Playout::Lost => decode_opus_packet(&mut self.decoder, &[], 2, self.frame_size),
+            };
+
+            // A blob that held no whole packet still takes up its slot
+            let frames = (pcm.len() / 2).max(self.frame_size);
+            self.ahead += frames as f64 / SAMPLE_RATE as f64;
+
+            if !pcm.is_empty() {
+                let frames: PackedVector2Array = pcm
+                    .chunks_exact(2)
+                    .map(|frame| Vector2::new(frame[0], frame[1]))
+                    .collect();
+                self.signals().decoded_chunk().emit(&frames);
+            }
+        }
+    }
+}
+
+#[godot_api]
+impl JitterBufferNode {
+    /// Queue a blob of the `speak` signal with the sequence number the sender
+    /// gave it. Returns false when it arrived too late to be played or is a
+    /// duplicate.
+    #[func]
+    pub fn push_packet(&mut self, sequence: i64, encoded: PackedByteArray) -> bool {
+        if sequence < 0 {
+            godot_error!("push_packet: sequence numbers start at 0, got {}", sequence);
+            return false;
+        }
+        self.buffer.push(sequence as u64, encoded.to_vec())
+    }
+
+    /// Queue a `speak` blob by the sequence number in its header. Returns
+    /// false for blobs sent without one (see `set_relay_sequencing`), late
+    /// blobs and duplicates.
+    #[func]
+    pub fn push_blob(&mut self, encoded: PackedByteArray) -> bool {
+        let encoded = encoded.to_vec();
+        let Some(header) = RelayHeader::parse(&encoded) else {
+            godot_error!("push_blob: the blob has no relay header, use push_packet");
+            return false;
+        };
+        self.buffer.push(header.sequence as u64, encoded)
+    }
+
+    /// Blobs held before playout starts (default 3). Each one is a relay
+    /// frame, so the depth times the frame duration is the added latency,
+    /// and the longest a reordered blob can be late.
+    #[func]
+    pub fn set_depth(&mut self, packets: u32) {
+        self.buffer.set_depth(packets as usize);
+    }
+
+    #[func]
+    pub fn get_depth(&self) -> u32 {
+        self.buffer.depth() as u32
+    }
+
+    /// Frames per channel of each relay packet, matching the sender's
+    /// `set_opus_frame_size` (default 480, 10ms). Concealment fills missing
+    /// blobs with frames this long.
+    #[func]
+    pub fn set_frame_size(&mut self, frame_size: u32) {
+        let valid = get_valid_frame_sizes(SAMPLE_RATE);
+        if !valid.contains(&(frame_size as usize)) {
+            godot_error!(
+                "set_frame_size: {} is not an Opus frame size at {} Hz, expected one of {:?}",
+                frame_size,
+                SAMPLE_RATE,
+                valid
+            );
+            return;
+        }
+        self.frame_size = frame_size as usize;
+    }
+
+    /// Drop everything buffered and start over, e.g. when the sender
+    /// reconnects and its sequence numbers restart
+    #[func]
+    pub fn reset(&mut self) {
+        self.buffer.reset();
+        self.decoder = new_decoder(SAMPLE_RATE, 2).unwrap();
+        self.ahead = 0.0;
+    }
+
+    /// `buffered` blobs, and since the last `reset`: `late` blobs dropped for
+    /// arriving after their slot, `lost` slots concealed and `overflowed`
+    /// blobs skipped to catch up
+    #[func]
+    pub fn get_stats(&self) -> Dictionary {
+        let mut stats = Dictionary::new();
+        stats.set("buffered", self.buffer.len() as u32);
+        stats.set("late", self.buffer.late);
+        stats.set("lost", self.buffer.lost);
+        stats.set("overflowed", self.buffer.overflowed);
+        stats
+    }
+
+    /// Decoded stereo audio, one blob's worth at a time, for
+    /// `AudioStreamGenerator.push_buffer`
+    #[signal]
+    fn decoded_chunk(frames: PackedVector2Array);
+}
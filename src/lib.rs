@@ -1,8 +1,21 @@
+#[cfg(test)]
+mod alloc_count;
+pub mod calibration;
 pub mod codec;
+pub mod detection_result;
+pub mod echo;
 pub mod godot_thread_print;
+pub mod jitter_buffer;
+pub mod jitter_buffer_node;
 pub mod microphone;
+pub mod model;
+pub mod ogg;
 pub mod opus_decoder_node;
+pub mod raw_capture;
+pub mod resample;
 pub mod runtime;
+pub mod spellbook;
+pub mod wav_source;
 pub mod whisper;
 pub mod whisper_node;
 
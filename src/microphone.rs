@@ -1,17 +1,273 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Host, Stream, StreamConfig, SupportedStreamConfig};
-use godot::global::godot_print;
-use opus2::{Application, Channels, Encoder};
-use rubato::{
-    Resampler, SincFixedOut, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+use cpal::{
+    Device, Host, SizedSample, Stream, StreamConfig, StreamError, SupportedStreamConfig,
+    SupportedStreamConfigRange,
 };
+use godot::global::{godot_error, godot_print, godot_warn};
+use opus2::{Application, Channels, Encoder};
+use std::collections::VecDeque;
 use std::error::Error;
-use std::sync::mpsc::Sender;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::codec::encode_stereo_to_opus;
+use crate::codec::{
+    CodecError, EncodeBuffers, EncodeOptions, RELAY_HEADER_LEN, RelayClock,
+    encode_stereo_to_opus_into, get_valid_frame_sizes,
+};
+use crate::echo::EchoCanceller;
 use crate::godot_thread_print::GodotThreadPrint;
+use crate::raw_capture::RawCapture;
+use crate::resample::{self, ResampleQuality, StreamingLinear, StreamingSinc, resample};
+use crate::wav_source::{WavFeed, WavPlayer, read_wav};
+use crate::whisper::WHISPER_SAMPLE_RATE;
+
+/// Relay frame size unless configured otherwise, 10ms at 48kHz
+pub const OPUS_FRAME_SIZE: usize = 480;
+/// Capture buffers queued for the whisper thread before new audio is dropped
+pub const CAPTURE_QUEUE_CAPACITY: usize = 256;
+/// Monitor buffering unless configured otherwise
+const DEFAULT_MONITOR_LATENCY_MS: u32 = 50;
+
+/// How multichannel input is folded into the mono whisper feed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Downmix {
+    /// Average all captured channels
+    #[default]
+    Average,
+    /// Keep only the first channel, e.g. the one mic on a multichannel interface
+    First,
+    /// Keep the channel with the most energy in each captured block, for
+    /// stereo mics with one dead or noisy-but-quiet side
+    Loudest,
+    /// Mix the channels weighted by their RMS level, so a silent channel
+    /// doesn't halve the level the way averaging does
+    Weighted,
+}
+
+impl Downmix {
+    /// Parse "average", "first", "loudest" or "weighted" (case insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "average" => Some(Self::Average),
+            "first" => Some(Self::First),
+            "loudest" => Some(Self::Loudest),
+            "weighted" => Some(Self::Weighted),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Average => "average",
+            Self::First => "first",
+            Self::Loudest => "loudest",
+            Self::Weighted => "weighted",
+        }
+    }
+
+    /// Whether the relay carries the mono mix on both sides instead of the
+    /// captured left and right channels
+    fn mixes_relay(&self) -> bool {
+        matches!(self, Self::Loudest | Self::Weighted)
+    }
+}
+
+/// Scratch space of the capture callback. Every buffer keeps its allocation
+/// between callbacks, so once they have grown to the device's buffer size
+/// the hot path stops allocating.
+#[derive(Default)]
+struct CaptureBuffers {
+    /// Device samples converted to f32
+    samples: Vec<f32>,
+    /// Channels picked by the channel mask
+    selected: Vec<f32>,
+    /// Relay input as interleaved stereo at the device rate
+    stereo: Vec<f32>,
+    /// Relay audio at 48kHz waiting to fill an Opus frame
+    relay: Vec<f32>,
+    /// One relay frame with the gain applied
+    frame: Vec<f32>,
+    /// Whisper feed at the device rate
+    mono: Vec<f32>,
+    /// Whisper feed at 16kHz, copied out to the whisper thread
+    whisper: Vec<f32>,
+}
+
+/// Ring buffer between the capture and monitor callbacks. Playback starts once
+/// `target` samples are queued and restarts the wait after an underrun, so the
+/// target trades monitor latency against glitches. Never blocks either side.
+struct MonitorRing {
+    buffer: VecDeque<f32>,
+    target: usize,
+    primed: bool,
+}
+
+impl MonitorRing {
+    fn new(target: usize) -> Self {
+        Self {
+            buffer: VecDeque::with_capacity(target * 2),
+            target,
+            primed: false,
+        }
+    }
+
+    fn set_target(&mut self, target: usize) {
+        self.target = target;
+        self.primed = false;
+    }
+
+    /// Queue captured samples, dropping the oldest beyond twice the target so
+    /// latency can't creep up when the devices drift apart
+    fn push(&mut self, samples: &[f32]) {
+        self.buffer.extend(samples);
+        let max = (self.target * 2).max(samples.len());
+        if self.buffer.len() > max {
+            let excess = self.buffer.len() - max;
+            self.buffer.drain(..excess);
+        }
+    }
+
+    /// Fill `output`, with silence while priming or on underrun
+    fn fill(&mut self, output: &mut [f32]) {
+        if !self.primed && self.buffer.len() >= self.target.max(output.len()) {
+            self.primed = true;
+        }
+
+        if !self.primed {
+            output.fill(0.0);
+            return;
+        }
+
+        for sample in output.iter_mut() {
+            match self.buffer.pop_front() {
+                Some(value) => *sample = value,
+                None => {
+                    *sample = 0.0;
+                    self.primed = false;
+                }
+            }
+        }
+    }
+}
+
+/// Sample formats the capture stream accepts, normalized to [-1, 1]
+pub trait CaptureSample: SizedSample + Send + 'static {
+    fn to_f32(self) -> f32;
+}
+
+impl CaptureSample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl CaptureSample for i16 {
+    /// Full scale maps to exactly ±1.0; `i16::MIN` is clamped
+    fn to_f32(self) -> f32 {
+        (self as f32 / i16::MAX as f32).max(-1.0)
+    }
+}
+
+impl CaptureSample for u16 {
+    /// Centered on 32768, so `u16::MAX` maps to 1.0 and 0 is clamped to -1.0
+    fn to_f32(self) -> f32 {
+        ((self as f32 - 32768.0) / 32767.0).max(-1.0)
+    }
+}
+
+/// Level above which the boosted whisper feed is bent toward ±1 instead of clipped
+const SOFT_LIMIT_KNEE: f32 = 0.8;
+/// Peak the automatic gain brings the whisper feed to
+const AUTO_GAIN_TARGET: f32 = 0.5;
+/// Most the automatic gain boosts, so silence isn't amplified into hiss (+20dB)
+const AUTO_GAIN_MAX: f32 = 10.0;
+/// Time for the followed peak to fall to 1/e once the input gets quieter
+const AUTO_GAIN_RELEASE: Duration = Duration::from_secs(2);
+
+/// Automatic gain for the whisper feed: follows the input peak (instantly up,
+/// slowly down) and boosts quiet input toward `AUTO_GAIN_TARGET`
+struct AutoGain {
+    peak: f32,
+}
+
+impl Default for AutoGain {
+    fn default() -> Self {
+        Self {
+            peak: AUTO_GAIN_TARGET,
+        }
+    }
+}
+
+impl AutoGain {
+    /// Follow the peak over `samples` and return the gain for them
+    fn update(&mut self, samples: &[f32], sample_rate: u32) -> f32 {
+        let release = (-1.0 / (AUTO_GAIN_RELEASE.as_secs_f32() * sample_rate.max(1) as f32)).exp();
+        for sample in samples {
+            self.peak = sample.abs().max(self.peak * release);
+        }
+        (AUTO_GAIN_TARGET / self.peak.max(f32::EPSILON)).clamp(1.0, AUTO_GAIN_MAX)
+    }
+}
+
+/// Scale `samples` by `gain`, bending peaks above `SOFT_LIMIT_KNEE` smoothly
+/// toward ±1 so a boost never clips
+fn apply_soft_gain(samples: &mut [f32], gain: f32) {
+    if gain == 1.0 {
+        return;
+    }
+    let headroom = 1.0 - SOFT_LIMIT_KNEE;
+    for sample in samples.iter_mut() {
+        let boosted = *sample * gain;
+        let level = boosted.abs();
+        *sample = if level <= SOFT_LIMIT_KNEE {
+            boosted
+        } else {
+            boosted.signum()
+                * (SOFT_LIMIT_KNEE + headroom * ((level - SOFT_LIMIT_KNEE) / headroom).tanh())
+        };
+    }
+}
+
+/// Input level that counts as the latency probe's click coming back
+const LATENCY_PROBE_THRESHOLD: f32 = 0.1;
+/// Length of the probe click in output frames
+const LATENCY_PROBE_FRAMES: usize = 48;
+/// Give up on hearing the probe after this long
+const LATENCY_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Round trip of a click played on the monitor and picked up by the input
+#[derive(Default)]
+struct LatencyProbe {
+    armed: bool,
+    sent: Option<Instant>,
+    heard: Option<Instant>,
+}
 
-const OPUS_FRAME_SIZE: usize = 480; // 10ms @ 48kHz
+impl LatencyProbe {
+    /// Write the click over the start of `output` if a measurement is pending
+    fn play(&mut self, output: &mut [f32]) {
+        if !self.armed || self.sent.is_some() {
+            return;
+        }
+        let len = output.len().min(LATENCY_PROBE_FRAMES * 2);
+        output[..len].fill(0.9);
+        self.sent = Some(Instant::now());
+    }
+
+    /// Look for the click in captured audio
+    fn listen(&mut self, data: &[f32]) {
+        if self.sent.is_none() || self.heard.is_some() {
+            return;
+        }
+        if data.iter().any(|s| s.abs() >= LATENCY_PROBE_THRESHOLD) {
+            self.heard = Some(Instant::now());
+            self.armed = false;
+        }
+    }
+}
 
 pub struct Microphone {
     host: Host,
@@ -22,6 +278,52 @@ pub struct Microphone {
     output_config: Option<SupportedStreamConfig>,
     output_stream: Option<Stream>,
     debug: bool,
+    /// Channel count asked of the device, 0 for its default config
+    capture_channels: u16,
+    /// How the captured channels are folded into the whisper feed
+    downmix: Downmix,
+    paused: Arc<AtomicBool>,
+    /// Push-to-talk: captured audio is only forwarded while set
+    listening: Arc<AtomicBool>,
+    relay_enabled: Arc<AtomicBool>,
+    /// f32 bits, shared with the capture callback
+    relay_gain: Arc<AtomicU32>,
+    /// Gain of the whisper feed in dB, f32 bits
+    input_gain_db: Arc<AtomicU32>,
+    /// Follow the input level and boost quiet input on top of `input_gain_db`
+    auto_gain: Arc<AtomicBool>,
+    encode_options: EncodeOptions,
+    /// Numbers relay blobs across capture, WAV playback and keepalive
+    relay_clock: Arc<RelayClock>,
+    /// Frames per channel of each relay packet, at 48kHz
+    relay_frame_size: usize,
+    monitor: Arc<Mutex<MonitorRing>>,
+    monitor_latency_ms: u32,
+    latency_probe: Arc<Mutex<LatencyProbe>>,
+    /// Diagnostic recording of the device audio before any processing
+    raw_capture: Arc<Mutex<Option<RawCapture>>>,
+    /// 16kHz samples dropped because the whisper queue was full
+    dropped_frames: Arc<AtomicU64>,
+    /// RMS of the last capture callback, f32 bits
+    input_rms: Arc<AtomicU32>,
+    /// Highest absolute sample since `take_input_peak`, f32 bits
+    input_peak: Arc<AtomicU32>,
+    /// Set by the stream error callbacks when the device goes away
+    input_lost: Arc<AtomicBool>,
+    /// The input stream is playing; cleared when it stops or its device goes away
+    capturing: Arc<AtomicBool>,
+    output_lost: Arc<AtomicBool>,
+    /// Bit i keeps input channel i; 0 keeps them all
+    channel_mask: Arc<AtomicU32>,
+    echo_cancellation: Arc<AtomicBool>,
+    /// Works on the 16kHz whisper feed
+    echo: Arc<Mutex<EchoCanceller>>,
+    /// How the whisper feed is brought to 16kHz
+    whisper_resample: ResampleQuality,
+    /// Channels of the running capture, kept to rebuild the stream
+    senders: Option<(SyncSender<Vec<f32>>, Sender<Vec<u8>>)>,
+    /// WAV file played in place of the microphone
+    wav: Option<WavPlayer>,
 }
 
 impl Microphone {
@@ -38,16 +340,24 @@ impl Microphone {
 
         godot_print!("Default input config: {:?}", config);
 
-        let (output_device, output_config) = if debug {
-            let device = host.default_output_device().unwrap();
-            godot_print!("Using output device: {}", device.name()?);
-
-            let config = device.default_output_config().unwrap();
-            godot_print!("Default output config: {:?}", config);
-
-            (Some(device), Some(config))
+        // Headless machines have no output; the monitor just stays silent
+        let output = if debug {
+            host.default_output_device()
+                .and_then(|device| Some((device.default_output_config().ok()?, device)))
         } else {
-            (None, None)
+            None
+        };
+        let (output_device, output_config) = match output {
+            Some((config, device)) => {
+                godot_print!("Using output device: {}", device.name()?);
+                godot_print!("Default output config: {:?}", config);
+                (Some(device), Some(config))
+            }
+            None if debug => {
+                godot_print!("No output device, monitoring disabled");
+                (None, None)
+            }
+            None => (None, None),
         };
 
         Ok(Self {
@@ -59,6 +369,33 @@ impl Microphone {
             output_config,
             output_stream: None,
             debug,
+            capture_channels: 0,
+            downmix: Downmix::Average,
+            paused: Arc::new(AtomicBool::new(false)),
+            listening: Arc::new(AtomicBool::new(true)),
+            relay_enabled: Arc::new(AtomicBool::new(true)),
+            relay_gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            input_gain_db: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+            auto_gain: Arc::new(AtomicBool::new(false)),
+            encode_options: EncodeOptions::voice(),
+            relay_clock: Arc::new(RelayClock::default()),
+            relay_frame_size: OPUS_FRAME_SIZE,
+            monitor: Arc::new(Mutex::new(MonitorRing::new(0))),
+            monitor_latency_ms: DEFAULT_MONITOR_LATENCY_MS,
+            latency_probe: Arc::new(Mutex::new(LatencyProbe::default())),
+            raw_capture: Arc::new(Mutex::new(None)),
+            dropped_frames: Arc::new(AtomicU64::new(0)),
+            input_rms: Arc::new(AtomicU32::new(0)),
+            input_peak: Arc::new(AtomicU32::new(0)),
+            input_lost: Arc::new(AtomicBool::new(false)),
+            capturing: Arc::new(AtomicBool::new(false)),
+            output_lost: Arc::new(AtomicBool::new(false)),
+            channel_mask: Arc::new(AtomicU32::new(0)),
+            echo_cancellation: Arc::new(AtomicBool::new(false)),
+            echo: Arc::new(Mutex::new(EchoCanceller::default())),
+            whisper_resample: ResampleQuality::Sinc,
+            senders: None,
+            wav: None,
         })
     }
 
@@ -66,10 +403,30 @@ impl Microphone {
         self.device.clone().unwrap()
     }
 
+    /// Name of the cpal host (ALSA, JACK, WASAPI, CoreAudio, ...) in use
+    pub fn host_name(&self) -> &'static str {
+        self.host.id().name()
+    }
+
     pub fn get_sample_rate(&self) -> u32 {
         self.config.clone().unwrap().sample_rate().0
     }
 
+    pub fn get_channels(&self) -> u16 {
+        self.config
+            .as_ref()
+            .map(|config| config.channels())
+            .unwrap_or(0)
+    }
+
+    /// Sample format the device delivers, e.g. "f32"
+    pub fn sample_format_name(&self) -> String {
+        match &self.config {
+            Some(config) => config.sample_format().to_string(),
+            None => String::new(),
+        }
+    }
+
     pub fn list_inputs(&self) -> Vec<Device> {
         match self.host.input_devices() {
             Ok(devices) => devices.collect(),
@@ -77,260 +434,695 @@ impl Microphone {
         }
     }
 
+    pub fn list_outputs(&self) -> Vec<Device> {
+        match self.host.output_devices() {
+            Ok(devices) => devices.collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Device the monitor plays on, if any
+    pub fn get_current_output(&self) -> Option<&Device> {
+        self.output_device.as_ref()
+    }
+
+    /// Monitor on `device`, at its default output config
+    pub fn set_output(&mut self, device: Device) -> Result<(), Box<dyn Error>> {
+        self.output_config = Some(device.default_output_config()?);
+        self.output_device = Some(device);
+        Ok(())
+    }
+
     pub fn set_input(&mut self, device: Device) {
+        self.config = Self::select_input_config(&device, self.capture_channels);
         self.device = Some(device);
-        self.config = self.device.clone().unwrap().default_input_config().ok();
     }
 
-    pub fn rubato_resample(
-        stereo_samples: Vec<f32>,
-        sample_rate: f32,
-        to_sample_rate: f32,
-    ) -> Result<Vec<f32>, Box<dyn Error>> {
-        // Se as taxas são iguais, retorna direto
-        if (sample_rate - to_sample_rate).abs() < 0.01 {
-            return Ok(stereo_samples);
-        }
+    /// Hosts compiled into this build that can be used on this machine
+    pub fn list_hosts() -> Vec<&'static str> {
+        cpal::available_hosts()
+            .into_iter()
+            .map(|id| id.name())
+            .collect()
+    }
+
+    /// Switch to the cpal host named `name` (case insensitive), e.g. "ASIO"
+    /// or "JACK", and to its default input device. The monitor, when it has
+    /// an output device, moves to the host's default output.
+    pub fn set_host(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let Some(&id) = cpal::ALL_HOSTS
+            .iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+        else {
+            return Err(format!(
+                "host '{}' isn't compiled into this build, available: {:?}",
+                name,
+                Self::list_hosts()
+            )
+            .into());
+        };
+
+        let host = cpal::host_from_id(id)?;
+        let device = host
+            .default_input_device()
+            .ok_or_else(|| format!("host {} has no input device", id.name()))?;
+        let output_device = match self.output_device {
+            Some(_) => host.default_output_device(),
+            None => None,
+        };
 
-        // Separar canais interleaved -> [left_channel, right_channel]
-        let frames = stereo_samples.len() / 2;
-        let mut left: Vec<f64> = Vec::with_capacity(frames);
-        let mut right: Vec<f64> = Vec::with_capacity(frames);
+        self.output_config = output_device
+            .as_ref()
+            .and_then(|device| device.default_output_config().ok());
+        self.output_device = output_device;
+        self.host = host;
+        self.set_input(device);
+        Ok(())
+    }
+
+    /// Keep the stream open but stop forwarding captured audio
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Push-to-talk key state. Like a pause, but cheap enough to flip on
+    /// every key press and independent of `set_paused`.
+    pub fn set_listening(&mut self, listening: bool) {
+        self.listening.store(listening, Ordering::Relaxed);
+    }
 
-        for chunk in stereo_samples.chunks_exact(2) {
-            left.push(chunk[0] as f64);
-            right.push(chunk[1] as f64);
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::Relaxed)
+    }
+
+    /// Skip Opus encoding of the relay entirely, for keyword-spotting-only use
+    pub fn set_relay_enabled(&mut self, enabled: bool) {
+        self.relay_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_relay_enabled(&self) -> bool {
+        self.relay_enabled.load(Ordering::Relaxed)
+    }
+
+    /// Linear gain applied to the relay only; the whisper feed stays untouched
+    pub fn set_relay_gain(&mut self, gain: f32) {
+        self.relay_gain
+            .store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn relay_gain(&self) -> f32 {
+        f32::from_bits(self.relay_gain.load(Ordering::Relaxed))
+    }
+
+    /// Gain (dB) applied to the whisper feed, to help recognition on quiet
+    /// microphones. Boosted peaks are soft-limited rather than clipped; the
+    /// relay and the input level stay untouched.
+    pub fn set_input_gain_db(&mut self, db: f32) {
+        self.input_gain_db.store(db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn input_gain_db(&self) -> f32 {
+        f32::from_bits(self.input_gain_db.load(Ordering::Relaxed))
+    }
+
+    /// Boost quiet input automatically (up to +20dB), on top of the input gain
+    pub fn set_auto_gain(&mut self, enabled: bool) {
+        self.auto_gain.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn auto_gain(&self) -> bool {
+        self.auto_gain.load(Ordering::Relaxed)
+    }
+
+    /// Play the captured audio back on the output device, the default one
+    /// unless `set_output` picked another. Takes effect when the stream is
+    /// next started.
+    pub fn set_monitor_enabled(&mut self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        if enabled && self.output_device.is_none() {
+            let device = self
+                .host
+                .default_output_device()
+                .ok_or("No output device")?;
+            self.output_config = Some(device.default_output_config()?);
+            self.output_device = Some(device);
         }
+        self.debug = enabled;
+        Ok(())
+    }
 
-        // Calcular número de frames de saída
-        let ratio = to_sample_rate as f64 / sample_rate as f64;
-        let output_frames = (frames as f64 * ratio).round() as usize;
+    /// Audio held by the monitor before playback starts. Lower is more
+    /// immediate, higher survives scheduling hiccups without crackling.
+    pub fn set_monitor_latency_ms(&mut self, latency_ms: u32) {
+        self.monitor_latency_ms = latency_ms;
+        let target = self.monitor_target();
+        if let Ok(mut monitor) = self.monitor.lock() {
+            monitor.set_target(target);
+        }
+    }
 
-        // Configurar parâmetros de interpolação sinc
-        let params = SincInterpolationParameters {
-            sinc_len: 256,
-            f_cutoff: 0.95,
-            interpolation: SincInterpolationType::Linear,
-            oversampling_factor: 256,
-            window: WindowFunction::BlackmanHarris2,
+    pub fn monitor_latency_ms(&self) -> u32 {
+        self.monitor_latency_ms
+    }
+
+    /// Play a click on the monitor and time how long until the input hears it,
+    /// in milliseconds. Blocks for up to a second; needs the monitor running
+    /// and the speakers audible to the microphone (or a loopback cable).
+    pub fn measure_monitor_latency(&mut self) -> Result<f32, Box<dyn Error>> {
+        if self.output_stream.is_none() || self.stream.is_none() {
+            return Err("The monitor is not running".into());
+        }
+
+        *self.latency_probe.lock().unwrap() = LatencyProbe {
+            armed: true,
+            ..Default::default()
         };
 
-        // Criar resampler com tamanho de saída fixo
-        let mut resampler = SincFixedOut::<f64>::new(ratio, 2.0, params, output_frames, 2)?;
+        let deadline = Instant::now() + LATENCY_PROBE_TIMEOUT;
+        while Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+            let probe = self.latency_probe.lock().unwrap();
+            if let (Some(sent), Some(heard)) = (probe.sent, probe.heard) {
+                return Ok(heard.duration_since(sent).as_secs_f32() * 1000.0);
+            }
+        }
+
+        self.latency_probe.lock().unwrap().armed = false;
+        Err("The probe click was not heard back".into())
+    }
+
+    /// Monitor latency in interleaved output samples
+    fn monitor_target(&self) -> usize {
+        match &self.output_config {
+            Some(config) => {
+                config.sample_rate().0 as usize
+                    * config.channels() as usize
+                    * self.monitor_latency_ms as usize
+                    / 1000
+            }
+            None => 0,
+        }
+    }
 
-        // CRÍTICO: Verificar quantos frames de entrada são necessários
-        let input_frames_needed = resampler.input_frames_next();
+    /// Only capture the input channels whose bit is set in `mask` (0 = all).
+    /// The selected channels are averaged into the whisper feed; the relay gets
+    /// a lone channel on both sides, or the first two as left and right.
+    pub fn set_channel_mask(&mut self, mask: u32) {
+        self.channel_mask.store(mask, Ordering::Relaxed);
+    }
 
-        // Adicionar padding se necessário
-        left.resize(input_frames_needed, 0.0);
-        right.resize(input_frames_needed, 0.0);
+    pub fn channel_mask(&self) -> u32 {
+        self.channel_mask.load(Ordering::Relaxed)
+    }
 
-        // Preparar dados de entrada
-        let waves_in = vec![left, right];
+    /// Subtract the echo of the reference audio from the whisper feed
+    pub fn set_echo_cancellation(&mut self, enabled: bool) {
+        self.echo_cancellation.store(enabled, Ordering::Relaxed);
+        if let Ok(mut echo) = self.echo.lock() {
+            echo.reset();
+        }
+    }
 
-        // Processar
-        let waves_out = resampler.process(&waves_in, None)?;
+    pub fn is_echo_cancellation(&self) -> bool {
+        self.echo_cancellation.load(Ordering::Relaxed)
+    }
 
-        // Intercalar canais de volta: [L, R, L, R, ...]
-        let mut result = Vec::with_capacity(output_frames * 2);
-        for i in 0..output_frames {
-            result.push(waves_out[0][i] as f32);
-            result.push(waves_out[1][i] as f32);
+    /// Queue 16kHz mono playback audio as the echo reference
+    pub fn push_echo_reference(&mut self, samples: &[f32]) {
+        if !self.is_echo_cancellation() {
+            return;
+        }
+        if let Ok(mut echo) = self.echo.lock() {
+            echo.push_reference(samples);
         }
+    }
+
+    /// Total audio frames dropped because whisper fell behind
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// RMS level of the most recent captured audio, 0 to 1
+    pub fn input_rms(&self) -> f32 {
+        f32::from_bits(self.input_rms.load(Ordering::Relaxed))
+    }
 
-        Ok(result)
+    /// Peak level since the last call, 0 to 1
+    pub fn take_input_peak(&self) -> f32 {
+        f32::from_bits(self.input_peak.swap(0, Ordering::Relaxed))
     }
 
-    /// Linearly resample interleaved stereo audio
-    /// Simple linear resampling for stereo audio
-    fn resample_linear_stereo(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
-        if from_rate == to_rate {
-            return samples.to_vec();
+    /// RMS and absolute peak of `samples`, without allocating
+    fn levels(samples: &[f32]) -> (f32, f32) {
+        if samples.is_empty() {
+            return (0.0, 0.0);
         }
+        let (sum, peak) = samples.iter().fold((0.0f32, 0.0f32), |(sum, peak), &s| {
+            (sum + s * s, peak.max(s.abs()))
+        });
+        ((sum / samples.len() as f32).sqrt(), peak)
+    }
+
+    /// Whether the input device disappeared since the last call
+    pub fn take_input_lost(&self) -> bool {
+        self.input_lost.swap(false, Ordering::Relaxed)
+    }
 
-        const CHANNELS: usize = 2;
-        let ratio = from_rate as f64 / to_rate as f64;
-        let input_frames = samples.len() / CHANNELS;
-        let output_frames = (input_frames as f64 / ratio).round() as usize;
+    /// The microphone stream is running. False while a WAV file plays in its place.
+    pub fn is_capturing(&self) -> bool {
+        self.capturing.load(Ordering::Relaxed)
+    }
 
-        let mut output = Vec::with_capacity(output_frames * CHANNELS);
+    /// Whether the monitor output device disappeared since the last call
+    pub fn take_output_lost(&self) -> bool {
+        self.output_lost.swap(false, Ordering::Relaxed)
+    }
 
-        for i in 0..output_frames {
-            let pos = i as f64 * ratio;
-            let idx = pos.floor() as usize;
-            let frac = (pos - idx as f64) as f32;
+    /// Move capture to the default input device after the current one was lost
+    pub fn recover_input(&mut self) -> Result<(), Box<dyn Error>> {
+        let device = self.host.default_input_device().ok_or("No input device")?;
+        godot_print!("Falling back to input device: {}", device.name()?);
+        self.set_input(device);
+        self.restart()
+    }
 
-            for ch in 0..CHANNELS {
-                let s0 = samples.get(idx * CHANNELS + ch).copied().unwrap_or(0.0);
-                let s1 = samples
-                    .get((idx + 1) * CHANNELS + ch)
-                    .copied()
-                    .unwrap_or(s0);
+    /// Move the monitor to the default output device after the current one was lost
+    pub fn recover_output(&mut self) -> Result<(), Box<dyn Error>> {
+        let device = self
+            .host
+            .default_output_device()
+            .ok_or("No output device")?;
+        godot_print!("Falling back to output device: {}", device.name()?);
+        self.set_output(device)?;
+        self.restart()
+    }
 
-                output.push(s0 * (1.0 - frac) + s1 * frac);
-            }
+    /// Relay encoder tuning, applied when the stream is next started
+    pub fn set_encode_options(&mut self, options: EncodeOptions) {
+        self.encode_options = options;
+    }
+
+    pub fn encode_options(&self) -> EncodeOptions {
+        self.encode_options
+    }
+
+    /// Stamps relay blobs with their sequence number and timestamp; stamp
+    /// anything else sent on the relay with it too
+    pub fn relay_clock(&self) -> Arc<RelayClock> {
+        self.relay_clock.clone()
+    }
+
+    /// Frames per channel of each relay packet, one of the Opus frame sizes
+    /// at 48kHz (2.5 to 60ms). Shorter frames cut latency, longer ones spend
+    /// fewer bytes on headers. Applied when the stream is next started.
+    pub fn set_relay_frame_size(&mut self, frame_size: usize) -> Result<(), Box<dyn Error>> {
+        let valid = get_valid_frame_sizes(48000);
+        if !valid.contains(&frame_size) {
+            return Err(format!(
+                "{} is not an Opus frame size at 48000 Hz, expected one of {:?}",
+                frame_size, valid
+            )
+            .into());
         }
+        self.relay_frame_size = frame_size;
+        Ok(())
+    }
+
+    pub fn relay_frame_size(&self) -> usize {
+        self.relay_frame_size
+    }
+
+    /// Resampler for the whisper feed: sinc (default) keeps sibilants free of
+    /// aliasing, linear is cheaper for low-end machines. Applied when the
+    /// stream is next started.
+    pub fn set_whisper_resample(&mut self, quality: ResampleQuality) {
+        self.whisper_resample = quality;
+    }
+
+    pub fn whisper_resample(&self) -> ResampleQuality {
+        self.whisper_resample
+    }
 
-        output
+    pub fn prefers_mono(&self) -> bool {
+        self.capture_channels == 1
     }
 
-    /// Simple linear resampling
-    fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
-        if from_rate == to_rate {
-            return samples.to_vec();
+    pub fn capture_channels(&self) -> u16 {
+        self.capture_channels
+    }
+
+    pub fn downmix(&self) -> Downmix {
+        self.downmix
+    }
+
+    /// Takes effect when the capture stream is next built
+    pub fn set_downmix(&mut self, downmix: Downmix) {
+        self.downmix = downmix;
+    }
+
+    /// Scale `samples` by `gain`, clamped to [-1, 1] so boosting can't wrap around
+    fn apply_gain(samples: &mut [f32], gain: f32) {
+        if gain == 1.0 {
+            return;
+        }
+        for sample in samples.iter_mut() {
+            *sample = (*sample * gain).clamp(-1.0, 1.0);
         }
+    }
 
-        let ratio = from_rate as f32 / to_rate as f32;
-        let output_len = (samples.len() as f32 / ratio) as usize;
-        let mut output = Vec::with_capacity(output_len);
+    /// Capture in mono when the device offers it, so no downmix is needed
+    pub fn set_prefer_mono(&mut self, prefer_mono: bool) {
+        self.set_capture_channels(if prefer_mono { 1 } else { 0 });
+    }
 
-        for i in 0..output_len {
-            let pos = i as f32 * ratio;
-            let idx = pos as usize;
+    /// Ask the device for `channels` channels (0 = its default config), e.g. 1
+    /// on an 8-channel interface where only the first input is wired
+    pub fn set_capture_channels(&mut self, channels: u16) {
+        self.capture_channels = channels;
+        if let Some(device) = &self.device {
+            self.config = Self::select_input_config(device, channels);
+            godot_print!("Input config: {:?}", self.config);
+        }
+    }
 
-            if idx + 1 < samples.len() {
-                let frac = pos - idx as f32;
-                let sample = samples[idx] * (1.0 - frac) + samples[idx + 1] * frac;
-                output.push(sample);
-            } else if idx < samples.len() {
-                output.push(samples[idx]);
-            }
+    /// Default input config, or one with `channels` channels at the same rate
+    /// and format when the device supports it
+    fn select_input_config(device: &Device, channels: u16) -> Option<SupportedStreamConfig> {
+        let default = device.default_input_config().ok()?;
+        if channels == 0 || default.channels() == channels {
+            return Some(default);
         }
 
-        output
+        let ranges: Vec<SupportedStreamConfigRange> = match device.supported_input_configs() {
+            Ok(configs) => configs.collect(),
+            Err(_) => Vec::new(),
+        };
+        match Self::find_config(&ranges, &default, channels) {
+            Some(config) => Some(config),
+            None => {
+                // Fall back to the default config; extra channels are downmixed
+                godot_warn!(
+                    "Input device has no {}-channel config, capturing {} channels",
+                    channels,
+                    default.channels()
+                );
+                Some(default)
+            }
+        }
     }
 
-    fn build_stream(
-        &mut self,
-        tx: Sender<Vec<f32>>,
-        relay_audio: Sender<Vec<u8>>,
-    ) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
-        GodotThreadPrint::print(format!("Building Stream"));
-        let config: StreamConfig = self.config.clone().unwrap().into();
-        let channels = config.channels as usize;
-        let sample_rate = config.sample_rate.0;
-        godot_print!("sample_rate: {}", config.sample_rate.0);
-        let target_sample_rate = 16000; // Whisper expects 16kHz
+    /// A config with `channels` channels at the rate and format of `default`
+    fn find_config(
+        ranges: &[SupportedStreamConfigRange],
+        default: &SupportedStreamConfig,
+        channels: u16,
+    ) -> Option<SupportedStreamConfig> {
+        let sample_rate = default.sample_rate();
+        ranges
+            .iter()
+            .find(|range| {
+                range.channels() == channels
+                    && range.sample_format() == default.sample_format()
+                    && range.min_sample_rate() <= sample_rate
+                    && sample_rate <= range.max_sample_rate()
+            })
+            .map(|range| range.with_sample_rate(sample_rate))
+    }
 
-        let (dtx, drx) = std::sync::mpsc::channel::<Vec<f32>>();
+    /// Keep only the channels selected by `mask`, returning the new channel count.
+    /// Bits past the device's channels are ignored; selecting nothing keeps all.
+    /// The picked channels are written to `out`, reusing its allocation.
+    fn select_channels<'a>(
+        data: &'a [f32],
+        channels: usize,
+        mask: u32,
+        out: &'a mut Vec<f32>,
+    ) -> (&'a [f32], usize) {
+        let is_selected = |ch: usize| mask & (1 << ch) != 0;
+        let selected = (0..channels.min(32)).filter(|&ch| is_selected(ch)).count();
+        if selected == 0 || selected == channels {
+            return (data, channels);
+        }
 
-        if self.debug {
-            match &self.output_device {
-                Some(output_device) => {
-                    let oc = self.output_config.clone().unwrap();
-                    let config: StreamConfig = oc.into();
-                    let output_stream = output_device
-                        .build_output_stream(
-                            &config,
-                            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                                let mut mic_data: Vec<f32> = Vec::new();
-                                loop {
-                                    match drx.recv() {
-                                        Ok(buffer) => {
-                                            mic_data.extend(buffer);
-
-                                            if mic_data.len() >= output.len() {
-                                                break;
-                                            }
-                                            // data.copy_from_slice(&mic_data[..data.len()]);
-                                        }
-                                        Err(err) => GodotThreadPrint::print(format!(
-                                            "Reading Mic Data {:?}",
-                                            err
-                                        )),
-                                    }
-                                }
-                                for (i, v) in mic_data.iter().enumerate() {
-                                    if i < output.len() {
-                                        output[i] = v.clone();
-                                    }
-                                }
-                            },
-                            |err| GodotThreadPrint::print(format!("Stream error: {}", err)),
-                            None,
-                        )
-                        .unwrap();
-                    output_stream.play().unwrap();
-                    self.output_stream = Some(output_stream);
+        out.clear();
+        for frame in data.chunks_exact(channels) {
+            for (ch, &sample) in frame.iter().enumerate().take(32) {
+                if is_selected(ch) {
+                    out.push(sample);
                 }
-                None => GodotThreadPrint::print(format!("No output device")),
             }
         }
+        (&out[..], selected)
+    }
 
-        let debug = self.debug.clone();
-        let mut local_buffer: Vec<f32> = Vec::new();
-        let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap();
-        if let Some(device) = &mut self.device {
-            let stream = device.build_input_stream(
-                &config,
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    if debug {
-                        match dtx.send(data.to_vec()) {
-                            Err(err) => GodotThreadPrint::print(format!("Stream error: {}", err)),
-                            _ => {}
-                        }
-                    }
+    /// Fold interleaved frames into mono in `out`. Mono input is passed through as is.
+    fn downmix_to_mono<'a>(
+        data: &'a [f32],
+        channels: usize,
+        downmix: Downmix,
+        out: &'a mut Vec<f32>,
+    ) -> &'a [f32] {
+        if channels == 1 {
+            return data;
+        }
 
-                    let sampled =
-                        Self::resample_linear_stereo(&data[..], sample_rate as u32, 48000);
+        Self::mix_to_mono(data, channels, downmix, out);
+        &out[..]
+    }
 
-                    local_buffer.extend(sampled);
+    fn mix_to_mono(data: &[f32], channels: usize, downmix: Downmix, out: &mut Vec<f32>) {
+        out.clear();
+        let frames = data.chunks_exact(channels);
+        let average = |out: &mut Vec<f32>| {
+            let inv_channels = 1.0 / channels as f32;
+            out.extend(
+                data.chunks_exact(channels)
+                    .map(|frame| frame.iter().copied().sum::<f32>() * inv_channels),
+            );
+        };
+        match downmix {
+            Downmix::First => out.extend(frames.map(|frame| frame[0])),
+            Downmix::Average => average(out),
+            Downmix::Loudest => {
+                let loudest = (0..channels)
+                    .map(|channel| (channel, Self::channel_rms(data, channels, channel)))
+                    .fold(
+                        (0, 0.0),
+                        |best, current| {
+                            if current.1 > best.1 { current } else { best }
+                        },
+                    )
+                    .0;
+                out.extend(frames.map(|frame| frame[loudest]));
+            }
+            Downmix::Weighted => {
+                let total: f32 = (0..channels)
+                    .map(|channel| Self::channel_rms(data, channels, channel))
+                    .sum();
+                // Silence on every channel: no level to weigh by
+                if total < 1e-9 {
+                    average(out);
+                    return;
+                }
 
-                    let samples_per_frame = OPUS_FRAME_SIZE * channels;
+                out.resize(data.len() / channels, 0.0);
+                for channel in 0..channels {
+                    let weight = Self::channel_rms(data, channels, channel) / total;
+                    for (mixed, frame) in out.iter_mut().zip(data.chunks_exact(channels)) {
+                        *mixed += frame[channel] * weight;
+                    }
+                }
+            }
+        }
+    }
 
-                    // Processar todos os frames completos disponíveis
-                    while local_buffer.len() >= samples_per_frame {
-                        let frame: Vec<f32> = local_buffer.drain(..samples_per_frame).collect();
+    /// RMS level of one channel of interleaved frames
+    fn channel_rms(data: &[f32], channels: usize, channel: usize) -> f32 {
+        let frames = data.len() / channels;
+        if frames == 0 {
+            return 0.0;
+        }
+        let sum: f32 = data
+            .chunks_exact(channels)
+            .map(|frame| frame[channel] * frame[channel])
+            .sum();
+        (sum / frames as f32).sqrt()
+    }
 
-                        let duration_seconds =
-                            (frame.len() as f32 / (48000 as f32 * channels as f32)) * 1000.0;
+    /// Interleaved stereo for the Opus relay in `out`, duplicating mono input
+    /// (the first channel with `Downmix::First`, and the mono mix with
+    /// `Downmix::Loudest` and `Downmix::Weighted`)
+    fn to_stereo<'a>(
+        data: &'a [f32],
+        channels: usize,
+        downmix: Downmix,
+        out: &'a mut Vec<f32>,
+    ) -> &'a [f32] {
+        if channels == 2 && downmix != Downmix::First && !downmix.mixes_relay() {
+            return data;
+        }
 
-                        let frame_size = 48000 * duration_seconds as i32 / 1000;
+        if channels > 1 && downmix.mixes_relay() {
+            Self::mix_to_mono(data, channels, downmix, out);
+            // Spread in place, back to front so no sample is read after being overwritten
+            let frames = out.len();
+            out.resize(frames * 2, 0.0);
+            for frame in (0..frames).rev() {
+                out[frame * 2 + 1] = out[frame];
+                out[frame * 2] = out[frame];
+            }
+            return &out[..];
+        }
 
-                        GodotThreadPrint::print(format!(
-                            "frame_size: {}, duration: {}, sampled: {}",
-                            frame_size,
-                            duration_seconds,
-                            frame.len()
-                        ));
+        out.clear();
+        match channels {
+            1 => out.extend(data.iter().flat_map(|&s| [s, s])),
+            _ if downmix == Downmix::First => out.extend(
+                data.chunks_exact(channels)
+                    .flat_map(|frame| [frame[0], frame[0]]),
+            ),
+            _ => out.extend(
+                data.chunks_exact(channels)
+                    .flat_map(|frame| [frame[0], frame[1]]),
+            ),
+        }
+        &out[..]
+    }
 
-                        let opus_encoded = match encode_stereo_to_opus(
-                            &mut encoder,
-                            &frame[..],
-                            48000,
-                            OPUS_FRAME_SIZE,
-                        ) {
-                            Ok(a) => a,
-                            Err(err) => {
-                                let error = format!("{:?}", err);
-                                GodotThreadPrint::print(error);
-                                panic!("error on opus");
-                            }
-                        };
+    pub fn rubato_resample(
+        stereo_samples: Vec<f32>,
+        sample_rate: f32,
+        to_sample_rate: f32,
+    ) -> Result<Vec<f32>, Box<dyn Error>> {
+        // Se as taxas são iguais, retorna direto
+        if (sample_rate - to_sample_rate).abs() < 0.01 {
+            return Ok(stereo_samples);
+        }
 
-                        relay_audio.send(opus_encoded).unwrap();
-                    }
+        Ok(resample::sinc(
+            &stereo_samples,
+            2,
+            sample_rate as u32,
+            to_sample_rate as u32,
+        ))
+    }
+
+    /// Band-limited resampling of a whole mono buffer
+    pub fn rubato_resample_mono(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+        resample::sinc(samples, 1, from_rate, to_rate)
+    }
+
+    /// What a new capture callback takes from the microphone
+    fn capture_controls(&self) -> CaptureControls {
+        CaptureControls {
+            debug: self.debug,
+            downmix: self.downmix,
+            whisper_resample: self.whisper_resample,
+            encode_options: self.encode_options,
+            frame_size: self.relay_frame_size,
+            paused: self.paused.clone(),
+            listening: self.listening.clone(),
+            channel_mask: self.channel_mask.clone(),
+            monitor: self.monitor.clone(),
+            latency_probe: self.latency_probe.clone(),
+            raw_capture: self.raw_capture.clone(),
+            relay_enabled: self.relay_enabled.clone(),
+            relay_gain: self.relay_gain.clone(),
+            relay_clock: self.relay_clock.clone(),
+            input_gain_db: self.input_gain_db.clone(),
+            auto_gain: self.auto_gain.clone(),
+            echo_cancellation: self.echo_cancellation.clone(),
+            echo: self.echo.clone(),
+            input_rms: self.input_rms.clone(),
+            input_peak: self.input_peak.clone(),
+        }
+    }
 
-                    let inv_channels = 1.0 / channels as f32;
+    fn build_stream<T: CaptureSample>(
+        &mut self,
+        tx: SyncSender<Vec<f32>>,
+        relay_audio: Sender<Vec<u8>>,
+    ) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+        GodotThreadPrint::print(format!("Building Stream"));
+        let config: StreamConfig = self.config.clone().unwrap().into();
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+        godot_print!("sample_rate: {}", config.sample_rate.0);
 
-                    let mono_samples: Vec<f32> = data
-                        .chunks(channels)
-                        .map(|frame| frame.iter().copied().sum::<f32>() * inv_channels)
-                        .collect();
+        if self.debug {
+            let target = self.monitor_target();
+            if let Ok(mut ring) = self.monitor.lock() {
+                *ring = MonitorRing::new(target);
+            }
 
-                    // Resample if needed
-                    let resampled = if sample_rate != target_sample_rate {
-                        Self::resample_linear(&mono_samples, sample_rate, target_sample_rate)
-                    } else {
-                        mono_samples
-                    };
+            match (&self.output_device, &self.output_config) {
+                (Some(output_device), Some(output_config)) => {
+                    let config: StreamConfig = output_config.clone().into();
+                    let monitor = self.monitor.clone();
+                    let latency_probe = self.latency_probe.clone();
+                    let output_lost = self.output_lost.clone();
+                    let output_stream = output_device.build_output_stream(
+                        &config,
+                        move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                            // Never wait on the capture side from the output thread:
+                            // a contended ring plays one buffer of silence instead
+                            match monitor.try_lock() {
+                                Ok(mut ring) => ring.fill(output),
+                                Err(_) => output.fill(0.0),
+                            }
+                            if let Ok(mut probe) = latency_probe.try_lock() {
+                                probe.play(output);
+                            }
+                        },
+                        move |err| {
+                            if matches!(err, StreamError::DeviceNotAvailable) {
+                                output_lost.store(true, Ordering::Relaxed);
+                            }
+                            GodotThreadPrint::print(format!("Stream error: {}", err))
+                        },
+                        None,
+                    );
+                    // A broken monitor must not take capture down with it
+                    match output_stream {
+                        Ok(output_stream) => match output_stream.play() {
+                            Ok(()) => self.output_stream = Some(output_stream),
+                            Err(err) => {
+                                GodotThreadPrint::print(format!("Can't start the monitor: {}", err))
+                            }
+                        },
+                        Err(err) => GodotThreadPrint::print(format!(
+                            "Can't open the monitor output: {}",
+                            err
+                        )),
+                    }
+                }
+                _ => GodotThreadPrint::print(format!("No output device")),
+            }
+        }
 
-                    match tx.send(resampled) {
-                        Err(err) => GodotThreadPrint::print(format!("1: Stream error: {}", err)),
-                        _ => {}
+        let mut callback = CaptureCallback::new(self.capture_controls(), sample_rate, channels);
+        let mut senders = CaptureSenders {
+            tx,
+            relay_audio,
+            dropped_frames: self.dropped_frames.clone(),
+        };
+        let input_lost = self.input_lost.clone();
+        let capturing = self.capturing.clone();
+        if let Some(device) = &mut self.device {
+            let stream = device.build_input_stream(
+                &config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| callback.process(data, &mut senders),
+                move |err| {
+                    // Recovered from `Whisper::process`, off the audio thread
+                    if matches!(err, StreamError::DeviceNotAvailable) {
+                        capturing.store(false, Ordering::Relaxed);
+                        input_lost.store(true, Ordering::Relaxed);
                     }
+                    GodotThreadPrint::print(format!("2: Stream error: {}", err))
                 },
-                |err| GodotThreadPrint::print(format!("2: Stream error: {}", err)),
                 None,
             )?;
             return Ok(stream);
@@ -341,29 +1133,803 @@ impl Microphone {
 
     pub fn start(
         &mut self,
-        tx: Sender<Vec<f32>>,
+        tx: SyncSender<Vec<f32>>,
         relay_audio: Sender<Vec<u8>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        self.senders = Some((tx.clone(), relay_audio.clone()));
+        self.start_stream(tx, relay_audio)?;
+
+        godot_print!("\n🎤 Listening for keywords with Whisper ML... (Press Ctrl+C to stop)\n");
+        //godot_print!("Keywords: {:?}\n", keywords);
+        godot_print!("Speak into your microphone!\n");
+
+        Ok(())
+    }
+
+    /// Rebuild the running streams against the current device and config,
+    /// e.g. after `set_input`. The whisper feed and relay carry on through the
+    /// same channels, resampled from the new device's rate. Does nothing when
+    /// capture isn't running.
+    pub fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((tx, relay_audio)) = self.senders.clone() else {
+            return Ok(());
+        };
+        if self.wav.is_some() {
+            return Ok(());
+        }
+
+        self.pause_streams();
+        // The new device may deliver another format than the file was opened with
+        match self.stop_raw_capture() {
+            Ok(true) => godot_warn!("Raw capture stopped: the capture device changed"),
+            Ok(false) => {}
+            Err(err) => godot_error!("Raw capture failed: {}", err),
+        }
+        self.start_stream(tx, relay_audio)?;
+        godot_print!(
+            "Capture restarted at {} Hz, {} channels",
+            self.get_sample_rate(),
+            self.get_channels()
+        );
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        tx: SyncSender<Vec<f32>>,
+        relay_audio: Sender<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let config = self.config.clone().ok_or("No input config")?;
+
         // Start audio capture
-        let stream = match self.config.clone().unwrap().sample_format() {
-            cpal::SampleFormat::F32 => self.build_stream(tx, relay_audio)?,
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => self.build_stream::<f32>(tx, relay_audio)?,
+            cpal::SampleFormat::I16 => self.build_stream::<i16>(tx, relay_audio)?,
+            cpal::SampleFormat::U16 => self.build_stream::<u16>(tx, relay_audio)?,
             _ => return Err("Unsupported sample format".into()),
         };
 
         stream.play()?;
+        self.stream = Some(stream);
+        self.capturing.store(true, Ordering::Relaxed);
 
-        godot_print!("\n🎤 Listening for keywords with Whisper ML... (Press Ctrl+C to stop)\n");
-        //godot_print!("Keywords: {:?}\n", keywords);
-        godot_print!("Speak into your microphone!\n");
+        Ok(())
+    }
 
-        self.stream = Some(stream);
+    /// Play the WAV file at `path` into the whisper feed and the relay in
+    /// place of the microphone, which stops capturing. `realtime` paces it
+    /// like live input; otherwise it is fed as fast as whisper keeps up, so
+    /// nothing is dropped. Needs a running capture for its channels.
+    pub fn start_wav(&mut self, path: &Path, realtime: bool) -> Result<(), Box<dyn Error>> {
+        let (tx, relay_audio) = self.senders.clone().ok_or("Capture isn't running")?;
+        let audio = read_wav(path)?;
+
+        let mut out = Vec::new();
+        let mono = Self::downmix_to_mono(&audio.samples, audio.channels, self.downmix, &mut out);
+        let whisper = resample(
+            mono,
+            1,
+            audio.sample_rate,
+            WHISPER_SAMPLE_RATE,
+            self.whisper_resample,
+        );
+        let relay = if self.is_relay_enabled() {
+            let stereo = Self::to_stereo(&audio.samples, audio.channels, self.downmix, &mut out);
+            Some(resample(
+                stereo,
+                2,
+                audio.sample_rate,
+                48000,
+                self.whisper_resample,
+            ))
+        } else {
+            None
+        };
 
+        self.stop_wav();
+        self.pause_streams();
+        self.wav = Some(WavPlayer::start(
+            WavFeed { whisper, relay },
+            tx,
+            relay_audio,
+            self.relay_clock.clone(),
+            self.encode_options,
+            self.relay_frame_size,
+            realtime,
+        ));
         Ok(())
     }
 
+    /// Write the device audio exactly as captured, at the device's rate and
+    /// channel count, to the WAV file at `path` until `stop_raw_capture`.
+    /// Replaces a raw capture already running.
+    pub fn start_raw_capture(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let config = self.config.as_ref().ok_or("No input config")?;
+        let capture = RawCapture::start(path, config.sample_rate().0, config.channels())?;
+        let previous = self.raw_capture.lock().unwrap().replace(capture);
+        match previous {
+            Some(previous) => previous.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Finish the raw capture file. Returns false when none was running.
+    pub fn stop_raw_capture(&mut self) -> Result<bool, Box<dyn Error>> {
+        let capture = self.raw_capture.lock().unwrap().take();
+        match capture {
+            Some(capture) => capture.finish().map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// A WAV file started with `start_wav` is still being fed
+    pub fn is_playing_wav(&self) -> bool {
+        self.wav.as_ref().is_some_and(|wav| !wav.is_finished())
+    }
+
+    fn stop_wav(&mut self) {
+        if let Some(wav) = self.wav.take() {
+            wav.stop();
+        }
+    }
+
+    /// Stop capture and the monitor, and let go of the whisper and relay
+    /// channels so the whisper thread sees the capture end
     pub fn stop(&mut self) {
+        self.senders = None;
+        self.stop_wav();
+        self.pause_streams();
+    }
+
+    fn pause_streams(&mut self) {
+        self.capturing.store(false, Ordering::Relaxed);
         if let Some(stream) = self.stream.take() {
             let _ = stream.pause();
         }
+        if let Some(stream) = self.output_stream.take() {
+            let _ = stream.pause();
+        }
+    }
+}
+
+/// What the capture callback takes from the `Microphone`: the settings the
+/// stream was built with, and the switches and meters it shares while running
+struct CaptureControls {
+    debug: bool,
+    downmix: Downmix,
+    whisper_resample: ResampleQuality,
+    encode_options: EncodeOptions,
+    /// Frames per channel of each relay packet, at 48kHz
+    frame_size: usize,
+    paused: Arc<AtomicBool>,
+    listening: Arc<AtomicBool>,
+    channel_mask: Arc<AtomicU32>,
+    monitor: Arc<Mutex<MonitorRing>>,
+    latency_probe: Arc<Mutex<LatencyProbe>>,
+    raw_capture: Arc<Mutex<Option<RawCapture>>>,
+    relay_enabled: Arc<AtomicBool>,
+    relay_gain: Arc<AtomicU32>,
+    relay_clock: Arc<RelayClock>,
+    input_gain_db: Arc<AtomicU32>,
+    auto_gain: Arc<AtomicBool>,
+    echo_cancellation: Arc<AtomicBool>,
+    echo: Arc<Mutex<EchoCanceller>>,
+    input_rms: Arc<AtomicU32>,
+    input_peak: Arc<AtomicU32>,
+}
+
+/// Where the capture callback hands its audio on
+trait CaptureSink {
+    /// A piece of the 16kHz mono whisper feed
+    fn whisper(&mut self, samples: Vec<f32>);
+    /// A stamped Opus relay blob
+    fn relay(&mut self, blob: Vec<u8>);
+}
+
+/// The whisper queue and relay channel of a running capture
+struct CaptureSenders {
+    tx: SyncSender<Vec<f32>>,
+    relay_audio: Sender<Vec<u8>>,
+    dropped_frames: Arc<AtomicU64>,
+}
+
+impl CaptureSink for CaptureSenders {
+    fn whisper(&mut self, samples: Vec<f32>) {
+        // Never block the audio callback; drop and count when whisper is behind
+        match self.tx.try_send(samples) {
+            Err(TrySendError::Full(samples)) => {
+                self.dropped_frames
+                    .fetch_add(samples.len() as u64, Ordering::Relaxed);
+            }
+            Err(err) => GodotThreadPrint::print(format!("1: Stream error: {}", err)),
+            _ => {}
+        }
+    }
+
+    fn relay(&mut self, blob: Vec<u8>) {
+        // Nobody listening to the relay is fine, drop the packet
+        let _ = self.relay_audio.send(blob);
+    }
+}
+
+/// The body of the capture stream's data callback and everything it keeps
+/// between calls. Once its buffers have grown, the only allocations left are
+/// the pieces it hands to the whisper thread and the relay.
+struct CaptureCallback {
+    controls: CaptureControls,
+    /// Rate and channel count of the device stream
+    sample_rate: u32,
+    channels: usize,
+    buffers: CaptureBuffers,
+    encoder: Encoder,
+    encoded: EncodeBuffers,
+    relay_resampler: StreamingLinear,
+    whisper_resampler: Option<StreamingSinc>,
+    whisper_linear: StreamingLinear,
+    follower: AutoGain,
+}
+
+impl CaptureCallback {
+    fn new(controls: CaptureControls, sample_rate: u32, channels: usize) -> Self {
+        let whisper_resampler = match controls.whisper_resample {
+            ResampleQuality::Sinc if sample_rate != WHISPER_SAMPLE_RATE => {
+                match StreamingSinc::new(sample_rate, WHISPER_SAMPLE_RATE) {
+                    Ok(resampler) => Some(resampler),
+                    Err(err) => {
+                        GodotThreadPrint::print(format!(
+                            "Sinc resampler unavailable for the whisper feed ({}), using linear",
+                            err
+                        ));
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        Self {
+            controls,
+            sample_rate,
+            channels,
+            buffers: CaptureBuffers::default(),
+            encoder: Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap(),
+            encoded: EncodeBuffers::default(),
+            relay_resampler: StreamingLinear::new(2, sample_rate, 48000),
+            whisper_resampler,
+            whisper_linear: StreamingLinear::new(1, sample_rate, WHISPER_SAMPLE_RATE),
+            follower: AutoGain::default(),
+        }
+    }
+
+    /// Handle one buffer of device audio
+    fn process<T: CaptureSample>(&mut self, data: &[T], sink: &mut impl CaptureSink) {
+        let Self {
+            controls,
+            sample_rate,
+            channels,
+            buffers,
+            encoder,
+            encoded,
+            relay_resampler,
+            whisper_resampler,
+            whisper_linear,
+            follower,
+        } = self;
+        let sample_rate = *sample_rate;
+        let frame_size = controls.frame_size;
+        let downmix = controls.downmix;
+
+        buffers.samples.clear();
+        buffers.samples.extend(data.iter().map(|&s| s.to_f32()));
+        let data = &buffers.samples[..];
+
+        if controls.debug {
+            // Missing a piece of the click beats stalling capture
+            if let Ok(mut probe) = controls.latency_probe.try_lock() {
+                probe.listen(data);
+            }
+        }
+
+        // Never wait on the node while it starts or stops the recording
+        if let Ok(Some(capture)) = controls.raw_capture.try_lock().as_deref() {
+            capture.push(data);
+        }
+
+        if controls.paused.load(Ordering::Relaxed) || !controls.listening.load(Ordering::Relaxed) {
+            // Send the end of the last word instead of holding its partial frame
+            if controls.encode_options.pad_final_frame && !buffers.relay.is_empty() {
+                // Less than a frame is left over: pad it to one
+                buffers.frame.clear();
+                buffers.frame.extend_from_slice(&buffers.relay);
+                buffers.frame.resize(frame_size * 2, 0.0);
+                buffers.relay.clear();
+                Microphone::apply_gain(
+                    &mut buffers.frame,
+                    f32::from_bits(controls.relay_gain.load(Ordering::Relaxed)),
+                );
+                let _ = Self::relay_frame(encoder, encoded, &buffers.frame, controls, sink);
+            }
+            return;
+        }
+
+        let (data, channels) = Microphone::select_channels(
+            data,
+            *channels,
+            controls.channel_mask.load(Ordering::Relaxed),
+            &mut buffers.selected,
+        );
+
+        if controls.debug {
+            // Dropping a buffer from the monitor beats stalling capture
+            if let Ok(mut ring) = controls.monitor.try_lock() {
+                ring.push(data);
+            }
+        }
+
+        if controls.relay_enabled.load(Ordering::Relaxed) {
+            let stereo = Microphone::to_stereo(data, channels, downmix, &mut buffers.stereo);
+            if sample_rate == 48000 {
+                buffers.relay.extend_from_slice(stereo);
+            } else {
+                relay_resampler.process_into(stereo, &mut buffers.relay);
+            }
+
+            let samples_per_frame = frame_size * 2;
+            let gain = f32::from_bits(controls.relay_gain.load(Ordering::Relaxed));
+
+            // Encode every complete frame, then drop them in one go
+            let mut consumed = 0;
+            while buffers.relay.len() - consumed >= samples_per_frame {
+                buffers.frame.clear();
+                buffers
+                    .frame
+                    .extend_from_slice(&buffers.relay[consumed..consumed + samples_per_frame]);
+                consumed += samples_per_frame;
+                Microphone::apply_gain(&mut buffers.frame, gain);
+
+                if let Err(err) =
+                    Self::relay_frame(encoder, encoded, &buffers.frame, controls, sink)
+                {
+                    GodotThreadPrint::print(format!("{:?}", err));
+                    panic!("error on opus");
+                }
+            }
+            buffers.relay.drain(..consumed);
+        } else {
+            buffers.relay.clear();
+        }
+
+        let mono_samples = Microphone::downmix_to_mono(data, channels, downmix, &mut buffers.mono);
+
+        let (rms, peak) = Microphone::levels(mono_samples);
+        controls.input_rms.store(rms.to_bits(), Ordering::Relaxed);
+        // Non-negative floats order like their bits
+        controls
+            .input_peak
+            .fetch_max(peak.to_bits(), Ordering::Relaxed);
+
+        buffers.whisper.clear();
+        if sample_rate == WHISPER_SAMPLE_RATE {
+            buffers.whisper.extend_from_slice(mono_samples);
+        } else if let Some(resampler) = whisper_resampler {
+            resampler.process_into(mono_samples, &mut buffers.whisper);
+        } else {
+            whisper_linear.process_into(mono_samples, &mut buffers.whisper);
+        }
+        // The sinc resampler only emits whole chunks
+        if buffers.whisper.is_empty() {
+            return;
+        }
+
+        if controls.echo_cancellation.load(Ordering::Relaxed) {
+            // Let this piece through uncancelled rather than wait
+            // while the node queues reference audio
+            if let Ok(mut echo) = controls.echo.try_lock() {
+                echo.process(&mut buffers.whisper);
+            }
+        }
+
+        let db = f32::from_bits(controls.input_gain_db.load(Ordering::Relaxed));
+        let mut gain = 10f32.powf(db / 20.0);
+        if controls.auto_gain.load(Ordering::Relaxed) {
+            gain *= follower.update(&buffers.whisper, WHISPER_SAMPLE_RATE);
+        }
+        apply_soft_gain(&mut buffers.whisper, gain);
+
+        // The whisper thread takes ownership of its piece
+        sink.whisper(buffers.whisper.clone());
+    }
+
+    /// Encode one relay frame and hand it on, or only advance the relay clock
+    /// when DTX leaves it out
+    fn relay_frame(
+        encoder: &mut Encoder,
+        encoded: &mut EncodeBuffers,
+        frame: &[f32],
+        controls: &CaptureControls,
+        sink: &mut impl CaptureSink,
+    ) -> Result<(), CodecError> {
+        let frame_size = controls.frame_size;
+        encode_stereo_to_opus_into(
+            encoder,
+            frame,
+            48000,
+            frame_size,
+            &controls.encode_options,
+            encoded,
+        )?;
+
+        // DTX left the frame out (a lone empty packet): send nothing
+        if controls.encode_options.dtx && encoded.output == [0, 0] {
+            controls.relay_clock.skip(frame_size);
+            return Ok(());
+        }
+
+        let mut blob = Vec::with_capacity(RELAY_HEADER_LEN + encoded.output.len());
+        controls
+            .relay_clock
+            .stamp_into(&encoded.output, frame_size, &mut blob);
+        sink.relay(blob);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc_count::allocations;
+    use crate::codec::RelayHeader;
+
+    #[test]
+    fn test_mono_input_skips_downmix() {
+        let data = [0.1, -0.2, 0.3];
+        let mut out = Vec::new();
+        let mono = Microphone::downmix_to_mono(&data, 1, Downmix::Average, &mut out);
+
+        assert!(std::ptr::eq(mono, &data[..]));
+    }
+
+    #[test]
+    fn test_integer_samples_normalize_to_full_scale() {
+        assert_eq!(i16::MAX.to_f32(), 1.0);
+        assert_eq!(i16::MIN.to_f32(), -1.0);
+        assert_eq!(0i16.to_f32(), 0.0);
+
+        assert_eq!(u16::MAX.to_f32(), 1.0);
+        assert_eq!(u16::MIN.to_f32(), -1.0);
+        assert_eq!(32768u16.to_f32(), 0.0);
+
+        assert_eq!(0.25f32.to_f32(), 0.25);
+    }
+
+    #[test]
+    fn test_rubato_resample_survives_rates_rubato_refuses() {
+        // A 0 Hz target is a ratio rubato can't build: no audio, not an error
+        let stereo = vec![0.5f32; 441 * 2];
+        let resampled = Microphone::rubato_resample(stereo.clone(), 44100.0, 0.0).unwrap();
+        assert!(resampled.is_empty());
+
+        // Usable audio at rates it can serve, away from the zero-padded edges
+        let resampled = Microphone::rubato_resample(stereo, 44100.0, 48000.0).unwrap();
+        assert_eq!(resampled.len(), 480 * 2);
+        assert!(resampled[300..660].iter().all(|&s| (s - 0.5).abs() < 0.02));
+    }
+
+    #[test]
+    fn test_stereo_input_is_downmixed() {
+        let data = [0.2, 0.4, -1.0, 1.0];
+        let mut out = Vec::new();
+        let mono = Microphone::downmix_to_mono(&data, 2, Downmix::Average, &mut out);
+
+        assert_eq!(mono.len(), 2);
+        assert!((mono[0] - 0.3).abs() < 1e-6);
+        assert!(mono[1].abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_levels_of_a_square_wave() {
+        let (rms, peak) = Microphone::levels(&[0.5, -0.5, 0.5, -0.5]);
+        assert!((rms - 0.5).abs() < 1e-6);
+        assert_eq!(peak, 0.5);
+
+        let (rms, peak) = Microphone::levels(&[0.0, -0.8, 0.0, 0.0]);
+        assert!((rms - 0.4).abs() < 1e-6);
+        assert_eq!(peak, 0.8);
+
+        assert_eq!(Microphone::levels(&[]), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_first_channel_downmix_ignores_the_others() {
+        let data = [0.1, 0.9, 0.9, 0.2, -0.9, -0.9];
+        let mut out = Vec::new();
+        let mono = Microphone::downmix_to_mono(&data, 3, Downmix::First, &mut out);
+        assert_eq!(mono, &[0.1, 0.2]);
+
+        let mut out = Vec::new();
+        let stereo = Microphone::to_stereo(&data, 3, Downmix::First, &mut out);
+        assert_eq!(stereo, &[0.1, 0.1, 0.2, 0.2]);
+    }
+
+    #[test]
+    fn test_capture_config_matches_requested_channels() {
+        use cpal::{SampleFormat, SampleRate, SupportedBufferSize};
+
+        let range = |channels, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(44100),
+                SampleRate(48000),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let ranges = [
+            range(8, SampleFormat::F32),
+            range(1, SampleFormat::I16),
+            range(2, SampleFormat::F32),
+        ];
+        let default = SupportedStreamConfig::new(
+            8,
+            SampleRate(48000),
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        );
+
+        let stereo = Microphone::find_config(&ranges, &default, 2).unwrap();
+        assert_eq!(stereo.channels(), 2);
+        assert_eq!(stereo.sample_rate(), SampleRate(48000));
+
+        // Mono only comes as i16 here, which the default format doesn't match
+        assert!(Microphone::find_config(&ranges, &default, 1).is_none());
+        assert!(Microphone::find_config(&ranges, &default, 6).is_none());
+    }
+
+    #[test]
+    fn test_downmix_names_round_trip() {
+        for mode in [
+            Downmix::Average,
+            Downmix::First,
+            Downmix::Loudest,
+            Downmix::Weighted,
+        ] {
+            assert_eq!(Downmix::parse(mode.name()), Some(mode));
+        }
+        assert_eq!(Downmix::parse("surround"), None);
+    }
+
+    #[test]
+    fn test_dead_channel_keeps_the_full_level() {
+        // Right channel dead
+        let data = [0.5, 0.0, -0.5, 0.0, 0.25, 0.0];
+        let mut out = Vec::new();
+
+        let mono = Microphone::downmix_to_mono(&data, 2, Downmix::Average, &mut out);
+        assert_eq!(mono, &[0.25, -0.25, 0.125]);
+        let mono = Microphone::downmix_to_mono(&data, 2, Downmix::Loudest, &mut out);
+        assert_eq!(mono, &[0.5, -0.5, 0.25]);
+        let mono = Microphone::downmix_to_mono(&data, 2, Downmix::Weighted, &mut out);
+        assert_eq!(mono, &[0.5, -0.5, 0.25]);
+
+        let stereo = Microphone::to_stereo(&data, 2, Downmix::Loudest, &mut out);
+        assert_eq!(stereo, &[0.5, 0.5, -0.5, -0.5, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_weighted_downmix_of_balanced_or_silent_input_averages() {
+        let data = [0.4, 0.2, -0.4, -0.2];
+        let mut out = Vec::new();
+        let mono = Microphone::downmix_to_mono(&data, 2, Downmix::Weighted, &mut out);
+        // Weights 2/3 and 1/3
+        assert!((mono[0] - (0.4 * 2.0 / 3.0 + 0.2 / 3.0)).abs() < 1e-6);
+
+        let silence = [0.0; 8];
+        let mono = Microphone::downmix_to_mono(&silence, 2, Downmix::Weighted, &mut out);
+        assert_eq!(mono, &[0.0; 4]);
+    }
+
+    #[test]
+    fn test_relay_gain_clamps_instead_of_clipping() {
+        let mut samples = [0.1, -0.3, 0.6, -0.9];
+        Microphone::apply_gain(&mut samples, 2.0);
+
+        assert!((samples[0] - 0.2).abs() < 1e-6);
+        assert!((samples[1] + 0.6).abs() < 1e-6);
+        assert_eq!(samples[2], 1.0);
+        assert_eq!(samples[3], -1.0);
+    }
+
+    #[test]
+    fn test_monitor_waits_for_target_then_plays() {
+        let mut ring = MonitorRing::new(4);
+        let mut output = [1.0f32; 2];
+
+        ring.push(&[0.1, 0.2]);
+        ring.fill(&mut output);
+        assert_eq!(output, [0.0, 0.0]);
+
+        ring.push(&[0.3, 0.4]);
+        ring.fill(&mut output);
+        assert_eq!(output, [0.1, 0.2]);
+        ring.fill(&mut output);
+        assert_eq!(output, [0.3, 0.4]);
+
+        // Underrun goes back to waiting for the target
+        ring.fill(&mut output);
+        assert_eq!(output, [0.0, 0.0]);
+        ring.push(&[0.5, 0.6]);
+        ring.fill(&mut output);
+        assert_eq!(output, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_monitor_drops_oldest_beyond_twice_the_target() {
+        let mut ring = MonitorRing::new(2);
+        ring.push(&[0.1, 0.2, 0.3, 0.4]);
+        ring.push(&[0.5, 0.6]);
+
+        let mut output = [0.0f32; 4];
+        ring.fill(&mut output);
+        assert_eq!(output, [0.3, 0.4, 0.5, 0.6]);
+    }
+
+    #[test]
+    fn test_channel_selection_picks_masked_channels() {
+        // Two frames of a 4 channel interface
+        let data = [0.0, 0.1, 0.2, 0.3, 1.0, 1.1, 1.2, 1.3];
+
+        let mut out = Vec::new();
+        let (third, channels) = Microphone::select_channels(&data, 4, 0b0100, &mut out);
+        assert_eq!(channels, 1);
+        assert_eq!(third, &[0.2, 1.2]);
+
+        let mut out = Vec::new();
+        let (pair, channels) = Microphone::select_channels(&data, 4, 0b1010, &mut out);
+        assert_eq!(channels, 2);
+        assert_eq!(pair, &[0.1, 0.3, 1.1, 1.3]);
+
+        // Nothing selectable keeps every channel
+        let mut out = Vec::new();
+        let (all, channels) = Microphone::select_channels(&data, 4, 0b1_0000, &mut out);
+        assert!(std::ptr::eq(all, &data[..]));
+        assert_eq!(channels, 4);
+    }
+
+    #[test]
+    fn test_latency_probe_clicks_once_and_hears_it() {
+        let mut probe = LatencyProbe {
+            armed: true,
+            ..Default::default()
+        };
+
+        probe.listen(&[1.0; 4]);
+        assert!(probe.heard.is_none(), "heard before the click was played");
+
+        let mut output = [0.0f32; 8];
+        probe.play(&mut output);
+        assert_eq!(output, [0.9; 8]);
+
+        let mut again = [0.0f32; 8];
+        probe.play(&mut again);
+        assert_eq!(again, [0.0; 8]);
+
+        probe.listen(&[0.01, 0.02]);
+        assert!(probe.heard.is_none());
+        probe.listen(&[0.01, -0.5]);
+        assert!(probe.heard.is_some());
+    }
+
+    /// Capture controls as `Microphone::new` sets them up
+    fn controls() -> CaptureControls {
+        CaptureControls {
+            debug: false,
+            downmix: Downmix::Average,
+            whisper_resample: ResampleQuality::Sinc,
+            encode_options: EncodeOptions::voice(),
+            frame_size: OPUS_FRAME_SIZE,
+            paused: Arc::new(AtomicBool::new(false)),
+            listening: Arc::new(AtomicBool::new(true)),
+            channel_mask: Arc::new(AtomicU32::new(0)),
+            monitor: Arc::new(Mutex::new(MonitorRing::new(0))),
+            latency_probe: Arc::new(Mutex::new(LatencyProbe::default())),
+            raw_capture: Arc::new(Mutex::new(None)),
+            relay_enabled: Arc::new(AtomicBool::new(true)),
+            relay_gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            relay_clock: Arc::new(RelayClock::default()),
+            input_gain_db: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+            auto_gain: Arc::new(AtomicBool::new(false)),
+            echo_cancellation: Arc::new(AtomicBool::new(false)),
+            echo: Arc::new(Mutex::new(EchoCanceller::default())),
+            input_rms: Arc::new(AtomicU32::new(0)),
+            input_peak: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Counts what the capture callback hands on, then drops it
+    #[derive(Default)]
+    struct CountingSink {
+        whisper: usize,
+        relay: usize,
+    }
+
+    impl CaptureSink for CountingSink {
+        fn whisper(&mut self, _samples: Vec<f32>) {
+            self.whisper += 1;
+        }
+
+        fn relay(&mut self, blob: Vec<u8>) {
+            assert!(RelayHeader::parse(&blob).is_some());
+            self.relay += 1;
+        }
+    }
+
+    #[test]
+    fn test_capture_callback_only_allocates_its_handoffs_once_warm() {
+        // 10ms callbacks of a 44.1kHz, 4 channel device, relaying two of them
+        let data: Vec<i16> = (0..441 * 4).map(|i| (i * 37 % 2000) as i16).collect();
+        let controls = controls();
+        controls.channel_mask.store(0b0011, Ordering::Relaxed);
+        controls
+            .relay_gain
+            .store(1.5f32.to_bits(), Ordering::Relaxed);
+        let mut callback = CaptureCallback::new(controls, 44100, 4);
+
+        // The first callbacks size every buffer
+        let mut sink = CountingSink::default();
+        for _ in 0..50 {
+            callback.process(&data, &mut sink);
+        }
+
+        let mut sink = CountingSink::default();
+        let before = allocations();
+        for _ in 0..1000 {
+            callback.process(&data, &mut sink);
+        }
+        let steady = allocations() - before;
+
+        // Every callback relays about one 10ms frame and feeds whisper
+        assert!(sink.relay >= 990, "{} relay blobs", sink.relay);
+        assert!(sink.whisper > 0);
+        // Previously every callback allocated at least five fresh vectors,
+        // and each Opus encode two more
+        assert_eq!(
+            steady,
+            sink.whisper + sink.relay,
+            "{} allocations for {} whisper pieces and {} relay blobs",
+            steady,
+            sink.whisper,
+            sink.relay
+        );
+    }
+
+    #[test]
+    fn test_boosted_input_is_soft_limited() {
+        let mut samples = vec![0.01, -0.05, 0.3, -0.9, 1.0];
+        apply_soft_gain(&mut samples, 4.0);
+
+        assert!((samples[0] - 0.04).abs() < 1e-6);
+        assert!((samples[1] + 0.2).abs() < 1e-6);
+        for pair in samples[2..].windows(2) {
+            assert!(pair[0].abs() < pair[1].abs(), "limiter must keep the order");
+        }
+        assert!(samples.iter().all(|s| s.abs() < 1.0));
+    }
+
+    #[test]
+    fn test_auto_gain_boosts_quiet_input_up_to_its_cap() {
+        let rate = WHISPER_SAMPLE_RATE;
+        let quiet: Vec<f32> = (0..rate as usize * 10)
+            .map(|i| if i % 2 == 0 { 0.1 } else { -0.1 })
+            .collect();
+
+        let mut follower = AutoGain::default();
+        let gain = follower.update(&quiet, rate);
+        assert!((gain - AUTO_GAIN_TARGET / 0.1).abs() < 0.1, "gain {}", gain);
+
+        let silence = vec![0.0; rate as usize * 30];
+        assert_eq!(follower.update(&silence, rate), AUTO_GAIN_MAX);
+
+        // Loud input is left alone
+        assert_eq!(follower.update(&[0.9], rate), 1.0);
     }
 }
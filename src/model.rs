@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// First four bytes of a whisper.cpp ggml model ("ggml" as a little-endian u32)
+const GGML_MAGIC: u32 = 0x6767_6d6c;
+
+/// Model file found by `list_models`
+#[derive(Debug, Clone)]
+pub struct ModelFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Starts with the ggml magic
+    pub valid: bool,
+}
+
+/// Check that `path` is a readable ggml model and return its size in bytes
+pub fn validate_model(path: &Path) -> Result<u64, Box<dyn Error>> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()).into());
+    }
+
+    let mut magic = [0u8; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    if u32::from_le_bytes(magic) != GGML_MAGIC {
+        return Err(format!("{} is not a ggml model", path.display()).into());
+    }
+
+    Ok(metadata.len())
+}
+
+/// Check that `data` holds a ggml model, as loaded into memory
+pub fn validate_model_bytes(data: &[u8]) -> Result<(), Box<dyn Error>> {
+    match data.first_chunk::<4>() {
+        Some(magic) if u32::from_le_bytes(*magic) == GGML_MAGIC => Ok(()),
+        _ => Err(format!("{} bytes of data are not a ggml model", data.len()).into()),
+    }
+}
+
+/// Model candidates in `dir`: `.bin` files and anything named like `ggml-*`,
+/// sorted by name
+pub fn list_models(dir: &Path) -> Result<Vec<ModelFile>, Box<dyn Error>> {
+    let mut models = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let is_bin = path.extension().is_some_and(|ext| ext == "bin");
+        if !is_bin && !name.starts_with("ggml") {
+            continue;
+        }
+
+        let validated = validate_model(&path);
+        models.push(ModelFile {
+            size: match &validated {
+                Ok(size) => *size,
+                Err(_) => fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+            },
+            valid: validated.is_ok(),
+            name,
+            path,
+        });
+    }
+
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists_and_validates_model_files() {
+        let dir = std::env::temp_dir().join(format!("godot_whisper_models_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut model = GGML_MAGIC.to_le_bytes().to_vec();
+        model.extend_from_slice(&[0u8; 12]);
+        fs::write(dir.join("ggml-tiny.bin"), &model).unwrap();
+        fs::write(dir.join("broken.bin"), b"nope").unwrap();
+        fs::write(dir.join("notes.txt"), b"not a model").unwrap();
+
+        let models = list_models(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].name, "broken.bin");
+        assert!(!models[0].valid);
+        assert_eq!(models[1].name, "ggml-tiny.bin");
+        assert!(models[1].valid);
+        assert_eq!(models[1].size, 16);
+    }
+
+    #[test]
+    fn test_validates_model_bytes() {
+        let mut model = GGML_MAGIC.to_le_bytes().to_vec();
+        model.extend_from_slice(&[0u8; 12]);
+        assert!(validate_model_bytes(&model).is_ok());
+        assert!(validate_model_bytes(b"nope").is_err());
+        assert!(validate_model_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_missing_model_is_invalid() {
+        assert!(validate_model(Path::new("/definitely/not/a/model.bin")).is_err());
+    }
+}
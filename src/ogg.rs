@@ -0,0 +1,375 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use opus2::{Application, Channels, Encoder};
+
+use crate::codec::{
+    EncodeOptions, encode_stereo_to_opus_packets, encoder_lookahead, framed_packets,
+    split_relay_header,
+};
+
+/// Stream serial number; a file only ever holds the one logical stream
+const SERIAL: u32 = 0x5768_7370;
+/// Lacing values a single page can hold
+const MAX_SEGMENTS: usize = 255;
+
+/// Encode interleaved stereo audio and write it as an Ogg Opus file that
+/// regular players (VLC, Audacity, browsers) can open
+pub fn write_ogg_opus(
+    path: &Path,
+    stereo: &[f32],
+    sample_rate: u32,
+    frame_size: usize,
+) -> Result<(), Box<dyn Error>> {
+    let options = EncodeOptions {
+        pad_final_frame: true,
+        ..EncodeOptions::default()
+    };
+    let packets = encode_stereo_to_opus_packets(stereo, sample_rate, frame_size, &options)?;
+
+    let mut encoder = Encoder::new(sample_rate, Channels::Stereo, Application::Audio)?;
+    let pre_skip = encoder_lookahead(&mut encoder)?;
+
+    // The zero padding of the last frame is trimmed on playback
+    let frames = stereo.len() / 2;
+    write_ogg_opus_packets(path, &packets, 2, sample_rate, frame_size, pre_skip, frames)
+}
+
+/// Write already encoded Opus packets of `frame_size` frames each as an Ogg
+/// Opus file. `pre_skip` is the encoder lookahead and `frames` the length of
+/// the audio, both in frames at `sample_rate`; decoders trim the last packet
+/// down to `frames`.
+pub fn write_ogg_opus_packets(
+    path: &Path,
+    packets: &[Vec<u8>],
+    channels: u8,
+    sample_rate: u32,
+    frame_size: usize,
+    pre_skip: usize,
+    frames: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&ogg_opus_bytes(
+        packets,
+        channels,
+        sample_rate,
+        frame_size,
+        pre_skip,
+        frames,
+    ))?;
+    file.flush()?;
+    Ok(())
+}
+
+/// The Ogg Opus stream for `packets`, see `write_ogg_opus_packets`
+pub fn ogg_opus_bytes(
+    packets: &[Vec<u8>],
+    channels: u8,
+    sample_rate: u32,
+    frame_size: usize,
+    pre_skip: usize,
+    frames: usize,
+) -> Vec<u8> {
+    // Granule positions always count 48kHz samples, whatever the input rate
+    let to_48k = |frames: usize| frames as u64 * 48000 / sample_rate.max(1) as u64;
+    let pre_skip = to_48k(pre_skip);
+    let packet_duration = to_48k(frame_size);
+    // End trimming (RFC 7845, section 4.4): the last granule marks the end of
+    // the audio rather than of the last packet
+    let end = pre_skip + to_48k(frames).min(packets.len() as u64 * packet_duration);
+
+    let mut writer = PageWriter::default();
+    writer.page(
+        &[opus_head(channels, sample_rate, pre_skip as u16)],
+        0,
+        0x02,
+    );
+    writer.page(&[opus_tags()], 0, 0x00);
+
+    let mut granule = pre_skip;
+    let mut page: Vec<&[u8]> = Vec::new();
+    let mut segments = 0;
+
+    for (i, packet) in packets.iter().enumerate() {
+        let needed = packet.len() / 255 + 1;
+        if segments + needed > MAX_SEGMENTS {
+            writer.page(&page, granule, 0x00);
+            page.clear();
+            segments = 0;
+        }
+
+        page.push(packet);
+        segments += needed;
+        granule += packet_duration;
+
+        if i + 1 == packets.len() {
+            writer.page(&page, end, 0x04);
+        }
+    }
+
+    if packets.is_empty() {
+        writer.page::<&[u8]>(&[], granule, 0x04);
+    }
+
+    writer.bytes
+}
+
+/// Relay blobs kept for an Ogg file, laid out on the capture timeline. The
+/// frames DTX left out between blobs, seen from their timestamps, and the
+/// empty packets it sent become silent packets, so quiet stretches keep their
+/// length.
+pub struct RelayRecording {
+    frame_size: usize,
+    /// An Opus packet of one silent frame
+    silence: Vec<u8>,
+    packets: Vec<Vec<u8>>,
+    /// Timestamp the next blob has when nothing was left out
+    next: Option<u64>,
+}
+
+impl RelayRecording {
+    /// Record blobs of `frame_size` frame packets, filling gaps with `silence`
+    pub fn new(frame_size: usize, silence: Vec<u8>) -> Self {
+        Self {
+            frame_size,
+            silence,
+            packets: Vec::new(),
+            next: None,
+        }
+    }
+
+    /// Keep the packets of a relay blob. Gaps are only seen in blobs with a
+    /// relay header.
+    pub fn push(&mut self, blob: &[u8]) {
+        let (header, _) = split_relay_header(blob);
+        let packets = framed_packets(blob);
+
+        if let (Some(header), Some(next)) = (header, self.next) {
+            let missing = header.timestamp.saturating_sub(next) as usize / self.frame_size;
+            self.packets
+                .extend(std::iter::repeat_n(self.silence.clone(), missing));
+        }
+        if let Some(header) = header {
+            self.next = Some(header.timestamp + (packets.len() * self.frame_size) as u64);
+        }
+
+        for packet in packets {
+            // Not a valid Opus packet on its own: DTX left the frame out
+            if packet.is_empty() {
+                self.packets.push(self.silence.clone());
+            } else {
+                self.packets.push(packet.to_vec());
+            }
+        }
+    }
+
+    pub fn packets(&self) -> &[Vec<u8>] {
+        &self.packets
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Length of the recording in 48kHz frames
+    pub fn frames(&self) -> usize {
+        self.packets.len() * self.frame_size
+    }
+}
+
+/// Identification header (RFC 7845, section 5.1)
+fn opus_head(channels: u8, sample_rate: u32, pre_skip: u16) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(channels);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // mono/stereo channel mapping
+    head
+}
+
+/// Comment header with the vendor string and no tags (RFC 7845, section 5.2)
+fn opus_tags() -> Vec<u8> {
+    let vendor = concat!("godot_whisper ", env!("CARGO_PKG_VERSION"));
+
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&0u32.to_le_bytes());
+    tags
+}
+
+#[derive(Default)]
+struct PageWriter {
+    bytes: Vec<u8>,
+    sequence: u32,
+}
+
+impl PageWriter {
+    /// Append a page holding whole `packets`. `header_type` is 0x02 for the
+    /// first page of the stream and 0x04 for the last.
+    fn page<P: AsRef<[u8]>>(&mut self, packets: &[P], granule: u64, header_type: u8) {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            let len = packet.as_ref().len();
+            lacing.extend(std::iter::repeat_n(255u8, len / 255));
+            lacing.push((len % 255) as u8);
+        }
+
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(b"OggS");
+        self.bytes.push(0); // version
+        self.bytes.push(header_type);
+        self.bytes.extend_from_slice(&granule.to_le_bytes());
+        self.bytes.extend_from_slice(&SERIAL.to_le_bytes());
+        self.bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        self.bytes.extend_from_slice(&[0; 4]); // checksum, filled below
+        self.bytes.push(lacing.len() as u8);
+        self.bytes.extend_from_slice(&lacing);
+        for packet in packets {
+            self.bytes.extend_from_slice(packet.as_ref());
+        }
+
+        let checksum = crc32(&self.bytes[start..]);
+        self.bytes[start + 22..start + 26].copy_from_slice(&checksum.to_le_bytes());
+        self.sequence += 1;
+    }
+}
+
+/// Ogg's CRC-32: polynomial 0x04c11db7, no reflection, zero init and final xor
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::RelayClock;
+
+    /// (header type, granule, packets) of every page, checking the CRCs
+    fn read_pages(mut bytes: &[u8]) -> Vec<(u8, u64, Vec<Vec<u8>>)> {
+        let mut pages = Vec::new();
+
+        while !bytes.is_empty() {
+            assert_eq!(&bytes[..4], b"OggS");
+            let segments = bytes[26] as usize;
+            let lacing = &bytes[27..27 + segments];
+            let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+            let page_len = 27 + segments + body_len;
+
+            let mut page = bytes[..page_len].to_vec();
+            let checksum = u32::from_le_bytes(page[22..26].try_into().unwrap());
+            page[22..26].fill(0);
+            assert_eq!(crc32(&page), checksum);
+
+            let mut packets = Vec::new();
+            let mut packet = Vec::new();
+            let mut offset = 27 + segments;
+            for &len in lacing {
+                packet.extend_from_slice(&bytes[offset..offset + len as usize]);
+                offset += len as usize;
+                if len < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+
+            let granule = u64::from_le_bytes(bytes[6..14].try_into().unwrap());
+            pages.push((bytes[5], granule, packets));
+            bytes = &bytes[page_len..];
+        }
+
+        pages
+    }
+
+    #[test]
+    fn test_crc_matches_the_ogg_reference() {
+        // CRC-32/POSIX without its final inversion, as used by libogg
+        assert_eq!(crc32(b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn test_packets_round_trip_through_pages() {
+        // Enough packets, one longer than a lacing value, to need several pages
+        let packets: Vec<Vec<u8>> = (0..300u32)
+            .map(|n| vec![n as u8; if n == 7 { 600 } else { 40 }])
+            .collect();
+
+        let bytes = ogg_opus_bytes(&packets, 2, 48000, 960, 312, 300 * 960);
+        let pages = read_pages(&bytes);
+
+        assert_eq!(pages[0].0, 0x02);
+        assert_eq!(&pages[0].2[0][..8], b"OpusHead");
+        assert_eq!(
+            u16::from_le_bytes([pages[0].2[0][10], pages[0].2[0][11]]),
+            312
+        );
+        assert_eq!(&pages[1].2[0][..8], b"OpusTags");
+
+        let last = pages.last().unwrap();
+        assert_eq!(last.0, 0x04);
+        assert_eq!(last.1, 312 + 300 * 960);
+
+        let decoded: Vec<Vec<u8>> = pages[2..]
+            .iter()
+            .flat_map(|(_, _, packets)| packets.clone())
+            .collect();
+        assert_eq!(decoded, packets);
+    }
+
+    #[test]
+    fn test_granules_count_48k_samples() {
+        let packets = vec![vec![1u8; 10]; 4];
+        let bytes = ogg_opus_bytes(&packets, 2, 16000, 320, 104, 4 * 320);
+        let pages = read_pages(&bytes);
+
+        assert_eq!(pages.last().unwrap().1, 312 + 4 * 960);
+    }
+
+    #[test]
+    fn test_last_granule_trims_the_padding() {
+        let packets = vec![vec![1u8; 10]; 3];
+        // 2.5 frames of audio, the last one padded
+        let bytes = ogg_opus_bytes(&packets, 2, 48000, 960, 312, 2 * 960 + 480);
+        let pages = read_pages(&bytes);
+
+        assert_eq!(pages.last().unwrap().1, 312 + 2 * 960 + 480);
+    }
+
+    #[test]
+    fn test_recording_fills_the_frames_dtx_left_out() {
+        let clock = RelayClock::default();
+        let silence = vec![0xf8, 0xff, 0xfe];
+        let mut recording = RelayRecording::new(480, silence.clone());
+
+        recording.push(&clock.stamp(vec![1, 0, 7], 480));
+        // Two frames DTX didn't send, then a blob holding an empty packet
+        clock.skip(2 * 480);
+        recording.push(&clock.stamp(vec![0, 0, 1, 0, 8], 480));
+
+        assert_eq!(
+            recording.packets(),
+            &[
+                vec![7],
+                silence.clone(),
+                silence.clone(),
+                silence.clone(),
+                vec![8]
+            ]
+        );
+        assert_eq!(recording.frames(), 5 * 480);
+    }
+}
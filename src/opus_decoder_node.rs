@@ -1,7 +1,19 @@
 use godot::prelude::*;
-use opus2::{Channels, Decoder};
+use opus2::Decoder;
 
-use crate::codec::decode_opus_to_stereo;
+use crate::codec::{
+    CodecError, OpusReassembler, decode_after_loss, default_frame_size, default_lookahead,
+    framed_packets, get_valid_frame_sizes, new_decoder, trim_frames,
+};
+
+/// How the bytes handed to the node are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InputFraming {
+    /// `[u16 len][payload]` packets, as produced by `encode_stereo_to_opus`
+    Framed,
+    /// One bare Opus packet per call, as from `encode_stereo_to_opus_packets`
+    RawSingle,
+}
 
 #[derive(GodotClass)]
 #[class(base=Node)]
@@ -10,6 +22,17 @@ pub struct OpusDecoderNode {
     decoder: Decoder,
     sample_rate: u32,
     frame_size: usize,
+    delay_compensation: bool,
+    lookahead: usize,
+    pending_trim: usize,
+    chunk_frames: usize,
+    pending_chunk: Vec<f32>,
+    reassembler: OpusReassembler,
+    framing: InputFraming,
+    channels: usize,
+    fec: bool,
+    /// Packets reported lost since the last one decoded
+    lost_packets: usize,
 }
 
 #[godot_api]
@@ -18,9 +41,19 @@ impl INode for OpusDecoderNode {
         let sample_rate = 48000;
         Self {
             base,
-            decoder: Decoder::new(sample_rate, Channels::Stereo).unwrap(),
+            decoder: new_decoder(sample_rate, 2).unwrap(),
             sample_rate,
-            frame_size: 480,
+            frame_size: default_frame_size(sample_rate),
+            delay_compensation: false,
+            lookahead: default_lookahead(sample_rate),
+            pending_trim: 0,
+            chunk_frames: 0,
+            pending_chunk: Vec::new(),
+            reassembler: OpusReassembler::new(),
+            framing: InputFraming::Framed,
+            channels: 2,
+            fec: false,
+            lost_packets: 0,
         }
     }
 }
@@ -29,17 +62,252 @@ impl INode for OpusDecoderNode {
 impl OpusDecoderNode {
     #[func]
     pub fn decode_audio(&mut self, encoded: Vec<u8>) -> Vec<f32> {
-        decode_opus_to_stereo(
-            &mut self.decoder,
-            &encoded[..],
-            self.sample_rate,
-            self.frame_size,
-        )
-        .unwrap()
+        let pcm = match self.framing {
+            InputFraming::Framed => {
+                let mut pcm = Vec::new();
+                for packet in framed_packets(&encoded) {
+                    pcm.extend(self.decode_packet(packet));
+                }
+                self.trim(&mut pcm);
+                pcm
+            }
+            InputFraming::RawSingle => {
+                let mut pcm = self.decode_packet(&encoded);
+                self.trim(&mut pcm);
+                pcm
+            }
+        };
+
+        if self.chunk_frames > 0 {
+            self.emit_chunks(&pcm);
+        }
+
+        pcm
+    }
+
+    /// Like `decode_audio`, but one array of interleaved PCM per decoded
+    /// packet instead of one blob, e.g. to feed a jitter buffer. Loss markers
+    /// yield nothing; their concealment comes out with the next packet.
+    #[func]
+    pub fn decode_audio_frames(&mut self, encoded: PackedByteArray) -> Array<PackedFloat32Array> {
+        let encoded = encoded.to_vec();
+        let packets = match self.framing {
+            InputFraming::Framed => framed_packets(&encoded),
+            InputFraming::RawSingle => vec![&encoded[..]],
+        };
+
+        let mut frames: Array<PackedFloat32Array> = Array::new();
+        for packet in packets {
+            let mut pcm = self.decode_packet(packet);
+            self.trim(&mut pcm);
+            if pcm.is_empty() {
+                continue;
+            }
+
+            if self.chunk_frames > 0 {
+                self.emit_chunks(&pcm);
+            }
+            frames.push(&PackedFloat32Array::from(pcm.as_slice()));
+        }
+
+        frames
+    }
+
+    /// Emit decoded audio through `decoded_chunk` in pieces of `frames` stereo frames,
+    /// sized for `AudioStreamGenerator.push_buffer`. Leftover frames are held until
+    /// the next decode completes a chunk. 0 disables chunked emission.
+    #[func]
+    pub fn set_decode_chunk_frames(&mut self, frames: u32) {
+        self.chunk_frames = frames as usize;
+        self.pending_chunk.clear();
+    }
+
+    /// Decode a piece of a framed Opus stream that may have been split at any
+    /// byte. Incomplete packets are kept until the rest arrives.
+    #[func]
+    pub fn push_bytes(&mut self, bytes: PackedByteArray) -> Vec<f32> {
+        if self.framing == InputFraming::RawSingle {
+            return self.decode_audio(bytes.to_vec());
+        }
+
+        let mut pcm = Vec::new();
+        for packet in self.reassembler.push(bytes.as_slice()) {
+            pcm.extend(self.decode_packet(&packet));
+        }
+        self.trim(&mut pcm);
+
+        if self.chunk_frames > 0 {
+            self.emit_chunks(&pcm);
+        }
+
+        pcm
+    }
+
+    /// "framed" (default) for the `[u16 len][payload]` blobs of the `speak`
+    /// signal, or "raw" when every call carries exactly one bare Opus packet
+    #[func]
+    pub fn set_input_framing(&mut self, framing: GString) {
+        self.framing = match framing.to_string().to_ascii_lowercase().as_str() {
+            "framed" => InputFraming::Framed,
+            "raw" => InputFraming::RawSingle,
+            other => {
+                godot_error!("set_input_framing: expected framed or raw, got '{}'", other);
+                return;
+            }
+        };
+        self.reassembler.reset();
+    }
+
+    /// Channels of the incoming stream: 1 for mono Opus, 2 (default) for stereo.
+    /// Decoded audio is interleaved with that many channels; `decoded_chunk`
+    /// always carries stereo frames. Resets the decoder.
+    #[func]
+    pub fn set_channels(&mut self, channels: u32) {
+        match new_decoder(self.sample_rate, channels as usize) {
+            Ok(decoder) => {
+                self.decoder = decoder;
+                self.channels = channels as usize;
+                self.pending_chunk.clear();
+                self.reassembler.reset();
+                self.lost_packets = 0;
+            }
+            Err(CodecError::InvalidChannels(channels)) => {
+                godot_error!(
+                    "set_channels: expected 1 (mono) or 2 (stereo), got {}",
+                    channels
+                )
+            }
+            Err(err) => godot_error!("set_channels: {}", err),
+        }
+    }
+
+    /// Rebuild a lost packet from the redundancy carried by the next one, when
+    /// the sender encodes with inband FEC. Without it lost packets are only
+    /// concealed. Report losses with `notify_packets_lost`.
+    #[func]
+    pub fn set_fec_enabled(&mut self, enabled: bool) {
+        self.fec = enabled;
+    }
+
+    /// `count` packets went missing before the next one to decode (e.g. a gap
+    /// in the network sequence numbers). They are concealed, and recovered
+    /// through FEC when enabled, once that packet arrives. An empty packet in
+    /// the stream counts as one lost packet as well.
+    #[func]
+    pub fn notify_packets_lost(&mut self, count: u32) {
+        self.lost_packets += count as usize;
     }
 
+    /// Samples per channel of each packet, one of the Opus frame sizes at the
+    /// current sample rate (480 = 10ms at 48kHz)
     #[func]
     pub fn set_frame_size(&mut self, frame_size: u32) {
+        let valid = get_valid_frame_sizes(self.sample_rate);
+        if !valid.contains(&(frame_size as usize)) {
+            godot_error!(
+                "set_frame_size: {} is not an Opus frame size at {} Hz, expected one of {:?}",
+                frame_size,
+                self.sample_rate,
+                valid
+            );
+            return;
+        }
         self.frame_size = frame_size as usize;
     }
+
+    /// Rate the sender encoded at: 8000, 12000, 16000, 24000 or 48000 (default).
+    /// Resets the decoder and keeps the frame duration, so 480 frames at
+    /// 48kHz become 240 at 24kHz. The lookahead returns to the rate's default.
+    #[func]
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        match new_decoder(rate, self.channels) {
+            Ok(decoder) => {
+                self.decoder = decoder;
+                self.frame_size = self.frame_size * rate as usize / self.sample_rate as usize;
+                self.sample_rate = rate;
+                self.pending_chunk.clear();
+                self.reassembler.reset();
+                self.lost_packets = 0;
+                self.set_lookahead(default_lookahead(rate) as u32);
+            }
+            Err(CodecError::InvalidSampleRate(rate)) => godot_error!(
+                "set_sample_rate: {} Hz isn't an Opus rate, expected 8000, 12000, 16000, 24000 or 48000",
+                rate
+            ),
+            Err(err) => godot_error!("set_sample_rate: {}", err),
+        }
+    }
+
+    #[func]
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Drop the encoder lookahead from the start of the decoded stream so the
+    /// output lines up with the captured input. Re-arms the trim when enabled.
+    #[func]
+    pub fn set_delay_compensation(&mut self, enabled: bool) {
+        self.delay_compensation = enabled;
+        self.pending_trim = if enabled { self.lookahead } else { 0 };
+    }
+
+    /// Override the lookahead (frames per channel) trimmed by delay compensation
+    #[func]
+    pub fn set_lookahead(&mut self, frames: u32) {
+        self.lookahead = frames as usize;
+        if self.delay_compensation {
+            self.pending_trim = self.lookahead;
+        }
+    }
+
+    #[func]
+    pub fn get_lookahead(&self) -> u32 {
+        self.lookahead as u32
+    }
+
+    #[signal]
+    fn decoded_chunk(frames: PackedVector2Array);
+}
+
+impl OpusDecoderNode {
+    /// Decode one packet, concealing the losses reported before it. Empty
+    /// packets are loss markers and wait for the next real one.
+    fn decode_packet(&mut self, packet: &[u8]) -> Vec<f32> {
+        if packet.is_empty() {
+            self.lost_packets += 1;
+            return Vec::new();
+        }
+
+        let lost = std::mem::take(&mut self.lost_packets);
+        decode_after_loss(
+            &mut self.decoder,
+            packet,
+            lost,
+            self.fec,
+            self.channels,
+            self.frame_size,
+        )
+    }
+
+    fn trim(&mut self, pcm: &mut Vec<f32>) {
+        if self.delay_compensation {
+            trim_frames(pcm, self.channels, &mut self.pending_trim);
+        }
+    }
+
+    fn emit_chunks(&mut self, pcm: &[f32]) {
+        self.pending_chunk.extend_from_slice(pcm);
+
+        let channels = self.channels;
+        let chunk_len = self.chunk_frames * channels;
+        while self.pending_chunk.len() >= chunk_len {
+            let frames: PackedVector2Array = self.pending_chunk[..chunk_len]
+                .chunks_exact(channels)
+                .map(|frame| Vector2::new(frame[0], frame[channels - 1]))
+                .collect();
+            self.pending_chunk.drain(..chunk_len);
+
+            self.signals().decoded_chunk().emit(&frames);
+        }
+    }
 }
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+
+/// Writes device audio exactly as captured (device rate and channels, before
+/// channel selection, downmix or resampling) to a 32-bit float WAV file. The
+/// file is written on its own thread so the capture callback never touches
+/// the disk.
+pub struct RawCapture {
+    tx: Sender<Vec<f32>>,
+    thread: JoinHandle<Result<(), hound::Error>>,
+}
+
+impl RawCapture {
+    /// Create `path` for interleaved audio with `channels` channels at `sample_rate`
+    pub fn start(path: &Path, sample_rate: u32, channels: u16) -> Result<Self, Box<dyn Error>> {
+        if channels == 0 || sample_rate == 0 {
+            return Err("no channels or sample rate to record".into());
+        }
+
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<f32>>();
+        let thread = std::thread::spawn(move || {
+            for samples in rx {
+                for sample in samples {
+                    writer.write_sample(sample)?;
+                }
+            }
+            writer.finalize()
+        });
+
+        Ok(Self { tx, thread })
+    }
+
+    /// Queue samples for the file. Copies them, so call it from diagnostics only.
+    pub fn push(&self, samples: &[f32]) {
+        // The writer only hangs up after a write error, reported by `finish`
+        let _ = self.tx.send(samples.to_vec());
+    }
+
+    /// Write what is queued and close the file
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        drop(self.tx);
+        match self.thread.join() {
+            Ok(result) => Ok(result?),
+            Err(_) => Err("raw capture writer panicked".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav_source::read_wav;
+
+    #[test]
+    fn test_raw_capture_keeps_the_device_format() {
+        let path = std::env::temp_dir().join("godot_whisper_test_raw_capture.wav");
+        let capture = RawCapture::start(&path, 44100, 2).unwrap();
+        capture.push(&[0.5, -0.5, 0.25]);
+        capture.push(&[-0.25]);
+        capture.finish().unwrap();
+
+        let audio = read_wav(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(audio.sample_rate, 44100);
+        assert_eq!(audio.channels, 2);
+        assert_eq!(audio.samples, vec![0.5, -0.5, 0.25, -0.25]);
+    }
+}
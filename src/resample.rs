@@ -0,0 +1,442 @@
+use rubato::{
+    Resampler, SincFixedIn, SincFixedOut, SincInterpolationParameters, SincInterpolationType,
+    WindowFunction,
+};
+
+use crate::godot_thread_print::GodotThreadPrint;
+
+/// Resampling backend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Linear interpolation: cheap, some aliasing when downsampling
+    Linear,
+    /// Windowed sinc via rubato: band-limited, much more expensive
+    Sinc,
+}
+
+impl ResampleQuality {
+    /// Parse "linear" or "sinc" (case insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "linear" => Some(Self::Linear),
+            "sinc" => Some(Self::Sinc),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Sinc => "sinc",
+        }
+    }
+}
+
+/// Resample interleaved audio with `channels` channels from `from_rate` to `to_rate`
+pub fn resample(
+    samples: &[f32],
+    channels: usize,
+    from_rate: u32,
+    to_rate: u32,
+    quality: ResampleQuality,
+) -> Vec<f32> {
+    if channels == 0 || from_rate == 0 || to_rate == 0 {
+        return Vec::new();
+    }
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    match quality {
+        ResampleQuality::Linear => linear(samples, channels, from_rate, to_rate),
+        ResampleQuality::Sinc => sinc(samples, channels, from_rate, to_rate),
+    }
+}
+
+/// Linear interpolation of interleaved audio
+pub fn linear(samples: &[f32], channels: usize, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let mut output = Vec::new();
+    linear_into(samples, channels, from_rate, to_rate, &mut output);
+    output
+}
+
+/// `linear`, appending to `output` so a caller can reuse its allocation.
+/// Output frames past the last input frame hold its value.
+pub fn linear_into(
+    samples: &[f32],
+    channels: usize,
+    from_rate: u32,
+    to_rate: u32,
+    output: &mut Vec<f32>,
+) {
+    if channels == 0 || from_rate == 0 || to_rate == 0 {
+        return;
+    }
+    if from_rate == to_rate {
+        output.extend_from_slice(samples);
+        return;
+    }
+
+    let input_frames = samples.len() / channels;
+    let output_frames = (input_frames as f64 * to_rate as f64 / from_rate as f64).round() as usize;
+    let start = output.len();
+    output.reserve(output_frames * channels);
+
+    StreamingLinear::new(channels, from_rate, to_rate).process_into(samples, output);
+
+    output.truncate(start + output_frames * channels);
+    if input_frames > 0 {
+        let last = &samples[(input_frames - 1) * channels..input_frames * channels];
+        while output.len() < start + output_frames * channels {
+            output.extend_from_slice(last);
+        }
+    }
+}
+
+/// Linear resampler for interleaved audio arriving in arbitrary pieces, such
+/// as capture callbacks. The read position is an f64 carried across calls
+/// and the last frame of a piece is interpolated against the first of the
+/// next, so no frame is dropped or repeated at piece boundaries and the
+/// output doesn't drift from the ratio over long streams. Output lags the
+/// input by up to one frame.
+pub struct StreamingLinear {
+    channels: usize,
+    /// Input frames advanced per output frame
+    step: f64,
+    /// Position of the next output frame, counted from `last` (from the first
+    /// frame of the next piece before any input)
+    position: f64,
+    /// Final frame of the previous piece, empty before any input
+    last: Vec<f32>,
+}
+
+impl StreamingLinear {
+    pub fn new(channels: usize, from_rate: u32, to_rate: u32) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            position: 0.0,
+            last: Vec::with_capacity(channels),
+        }
+    }
+
+    /// Resample `samples`, appending what can be interpolated so far to `output`
+    pub fn process_into(&mut self, samples: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let new_frames = samples.len() / channels;
+        if new_frames == 0 {
+            return;
+        }
+
+        // The previous piece's last frame, when there is one, is frame 0
+        let carried = self.last.len() / channels;
+        let frames = carried + new_frames;
+        let last = &self.last;
+        let sample = |frame: usize, ch: usize| {
+            if frame < carried {
+                last[ch]
+            } else {
+                samples[(frame - carried) * channels + ch]
+            }
+        };
+
+        // Each output frame needs the input frame after its position
+        while self.position < (frames - 1) as f64 {
+            let idx = self.position as usize;
+            let frac = (self.position - idx as f64) as f32;
+            for ch in 0..channels {
+                let s0 = sample(idx, ch);
+                let s1 = sample(idx + 1, ch);
+                output.push(s0 * (1.0 - frac) + s1 * frac);
+            }
+            self.position += self.step;
+        }
+
+        // Re-base on this piece's last frame, which becomes frame 0 of the next
+        self.position -= (frames - 1) as f64;
+        self.last.clear();
+        self.last
+            .extend_from_slice(&samples[(new_frames - 1) * channels..new_frames * channels]);
+    }
+}
+
+/// Sinc resampling of a whole interleaved buffer, with the filter delay removed
+/// so the output lines up with the input. Falls back to linear when rubato
+/// can't handle the ratio.
+pub fn sinc(samples: &[f32], channels: usize, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if channels == 0 || from_rate == 0 || to_rate == 0 {
+        return Vec::new();
+    }
+    let frames = samples.len() / channels;
+    let ratio = to_rate as f64 / from_rate as f64;
+    let output_frames = (frames as f64 * ratio).round() as usize;
+    if output_frames == 0 {
+        return Vec::new();
+    }
+
+    let mut resampler =
+        match SincFixedOut::<f64>::new(ratio, 1.0, sinc_params(), output_frames, channels) {
+            Ok(resampler) => resampler,
+            Err(err) => {
+                GodotThreadPrint::print(format!(
+                    "Sinc resampler unavailable for {} -> {} Hz ({}), falling back to linear",
+                    from_rate, to_rate, err
+                ));
+                return linear(samples, channels, from_rate, to_rate);
+            }
+        };
+
+    let mut waves: Vec<Vec<f64>> = (0..channels).map(|_| Vec::with_capacity(frames)).collect();
+    for frame in samples.chunks_exact(channels) {
+        for (wave, sample) in waves.iter_mut().zip(frame) {
+            wave.push(*sample as f64);
+        }
+    }
+
+    // The filter delays the output, so keep feeding (zeros past the end)
+    // until the delayed frames are out, and drop the first `delay`
+    let delay = resampler.output_delay();
+    let mut output = Vec::with_capacity(output_frames * channels);
+    let mut skip = delay;
+    let mut consumed = 0;
+    while output.len() < output_frames * channels {
+        let needed = resampler.input_frames_next();
+        let chunk: Vec<Vec<f64>> = waves
+            .iter()
+            .map(|wave| {
+                let mut chunk =
+                    wave[consumed.min(frames)..(consumed + needed).min(frames)].to_vec();
+                chunk.resize(needed, 0.0);
+                chunk
+            })
+            .collect();
+        consumed += needed;
+
+        let waves_out = match resampler.process(&chunk, None) {
+            Ok(waves_out) => waves_out,
+            Err(err) => {
+                GodotThreadPrint::print(format!("Sinc resampling failed: {}", err));
+                return linear(samples, channels, from_rate, to_rate);
+            }
+        };
+
+        let written = waves_out[0].len();
+        for i in skip.min(written)..written {
+            if output.len() == output_frames * channels {
+                break;
+            }
+            for wave in waves_out.iter() {
+                output.push(wave[i] as f32);
+            }
+        }
+        skip -= skip.min(written);
+    }
+
+    output
+}
+
+/// Input frames handed to rubato per step by `StreamingSinc`
+const STREAM_CHUNK: usize = 1024;
+
+/// Sinc resampler for mono audio arriving in arbitrary pieces, such as capture
+/// callbacks. The filter state and leftover input carry over between calls,
+/// so piece boundaries don't click the way resampling each piece alone does.
+/// Output lags the input by the filter delay.
+pub struct StreamingSinc {
+    resampler: SincFixedIn<f32>,
+    pending: Vec<f32>,
+    /// rubato's output for one chunk, allocated once
+    chunk_out: Vec<Vec<f32>>,
+}
+
+impl StreamingSinc {
+    pub fn new(from_rate: u32, to_rate: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        if from_rate == 0 || to_rate == 0 {
+            return Err("Invalid sample rate".into());
+        }
+
+        let ratio = to_rate as f64 / from_rate as f64;
+        let resampler = SincFixedIn::<f32>::new(ratio, 1.0, sinc_params(), STREAM_CHUNK, 1)?;
+        let chunk_out = resampler.output_buffer_allocate(true);
+        Ok(Self {
+            resampler,
+            pending: Vec::with_capacity(STREAM_CHUNK * 2),
+            chunk_out,
+        })
+    }
+
+    /// Resample `samples`, returning the output completed so far
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        self.process_into(samples, &mut output);
+        output
+    }
+
+    /// `process`, appending to `output`. Doesn't allocate once `output` has
+    /// grown to the size of a callback's worth of audio.
+    pub fn process_into(&mut self, samples: &[f32], output: &mut Vec<f32>) {
+        self.pending.extend_from_slice(samples);
+
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.resampler.input_frames_next() {
+            let needed = self.resampler.input_frames_next();
+            let chunk = &self.pending[offset..offset + needed];
+            match self
+                .resampler
+                .process_into_buffer(&[chunk], &mut self.chunk_out, None)
+            {
+                Ok((_, written)) => output.extend_from_slice(&self.chunk_out[0][..written]),
+                Err(err) => {
+                    GodotThreadPrint::print(format!("Streaming resample failed: {}", err));
+                    break;
+                }
+            }
+            offset += needed;
+        }
+
+        self.pending.drain(..offset);
+    }
+}
+
+fn sinc_params() -> SincInterpolationParameters {
+    SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        interpolation: SincInterpolationType::Linear,
+        oversampling_factor: 256,
+        window: WindowFunction::BlackmanHarris2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, frames: usize, channels: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let v = (2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.5;
+                std::iter::repeat(v).take(channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_output_length_follows_ratio() {
+        for quality in [ResampleQuality::Linear, ResampleQuality::Sinc] {
+            for channels in [1, 2] {
+                let input = sine(44100, 4410, channels);
+                let output = resample(&input, channels, 44100, 16000, quality);
+
+                assert_eq!(output.len(), 1600 * channels, "{:?} x{}", quality, channels);
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_rate_is_passthrough() {
+        let input = sine(48000, 480, 2);
+        let output = resample(&input, 2, 48000, 48000, ResampleQuality::Sinc);
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_sinc_output_is_aligned_with_input() {
+        let input = sine(48000, 4800, 1);
+        let output = resample(&input, 1, 48000, 16000, ResampleQuality::Sinc);
+        let expected = sine(16000, 1600, 1);
+
+        // Skip the edges, where the filter sees the zero padding
+        let error = output[200..1400]
+            .iter()
+            .zip(&expected[200..1400])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0f32, f32::max);
+        assert!(error < 0.02, "max error {}", error);
+    }
+
+    #[test]
+    fn test_streaming_sinc_matches_the_signal_across_pieces() {
+        let input = sine(48000, 48000, 1);
+        let mut streaming = StreamingSinc::new(48000, 16000).unwrap();
+
+        // Uneven callback sizes, as a capture device would deliver them
+        let mut output = Vec::new();
+        let mut offset = 0;
+        for size in [441, 1000, 127, 4096].iter().cycle() {
+            if offset >= input.len() {
+                break;
+            }
+            let end = (offset + size).min(input.len());
+            output.extend(streaming.process(&input[offset..end]));
+            offset = end;
+        }
+
+        // Whole chunks only, so a chunk's worth may still be pending
+        assert!(output.len() > 16000 - 400 && output.len() <= 16000);
+
+        let delay = streaming.resampler.output_delay();
+        let expected = sine(16000, 16000, 1);
+        let error = output[delay + 200..output.len() - 200]
+            .iter()
+            .zip(&expected[200..])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0f32, f32::max);
+        assert!(error < 0.02, "max error {}", error);
+    }
+
+    #[test]
+    fn test_streaming_linear_ramp_is_gap_free_across_pieces() {
+        let frames = 44100 * 2;
+        let ramp: Vec<f32> = (0..frames).flat_map(|i| [i as f32, -(i as f32)]).collect();
+        let mut streaming = StreamingLinear::new(2, 44100, 48000);
+
+        let mut output = Vec::new();
+        let mut offset = 0;
+        for size in [441, 512, 127, 1024, 333].iter().cycle() {
+            if offset >= frames {
+                break;
+            }
+            let end = (offset + size).min(frames);
+            streaming.process_into(&ramp[offset * 2..end * 2], &mut output);
+            offset = end;
+        }
+
+        // Every position before the last input frame, none dropped at piece ends
+        let step = 44100.0 / 48000.0;
+        let expected = ((frames - 1) as f64 / step).ceil() as usize;
+        assert_eq!(output.len(), expected * 2);
+
+        for (i, frame) in output.chunks_exact(2).enumerate() {
+            let position = (i as f64 * step) as f32;
+            assert!(
+                (frame[0] - position).abs() < 0.05,
+                "frame {} at {}, expected {}",
+                i,
+                frame[0],
+                position
+            );
+            assert_eq!(frame[1], -frame[0]);
+        }
+        for pair in output.chunks_exact(2).collect::<Vec<_>>().windows(2) {
+            let advance = pair[1][0] - pair[0][0];
+            assert!((advance - step as f32).abs() < 0.05, "gap of {}", advance);
+        }
+    }
+
+    #[test]
+    fn test_invalid_rates_give_empty_output() {
+        let input = sine(48000, 480, 1);
+
+        assert!(resample(&input, 1, 0, 16000, ResampleQuality::Linear).is_empty());
+        assert!(resample(&input, 0, 48000, 16000, ResampleQuality::Linear).is_empty());
+        // The backends called directly as well
+        assert!(linear(&input, 0, 48000, 16000).is_empty());
+        assert!(linear(&input, 1, 0, 16000).is_empty());
+        assert!(sinc(&input, 0, 48000, 16000).is_empty());
+        assert!(sinc(&input, 1, 48000, 0).is_empty());
+    }
+}
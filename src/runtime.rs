@@ -1,37 +1,82 @@
 use std::sync::{
-    Arc, Mutex, OnceLock,
+    Arc, Mutex, MutexGuard, OnceLock, PoisonError, Weak,
     atomic::{AtomicBool, Ordering},
 };
 
 static RUNTIME: OnceLock<Mutex<Runtime>> = OnceLock::new();
 
-fn get_runtime() -> &'static Mutex<Runtime> {
-    RUNTIME.get_or_init(|| {
-        Mutex::new(Runtime {
-            running: Arc::new(AtomicBool::new(false)),
+fn get_runtime() -> MutexGuard<'static, Runtime> {
+    RUNTIME
+        .get_or_init(|| {
+            Mutex::new(Runtime {
+                instances: Vec::new(),
+            })
         })
-    })
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
 }
 
+/// Registry of the running flags of every `Whisper` node. A node's whisper
+/// thread keeps working while its flag is set and exits once it is cleared,
+/// so stopping one node leaves the others listening.
 pub struct Runtime {
-    running: Arc<AtomicBool>,
+    /// Weak, so a freed node drops out; a detached thread still holding its
+    /// flag is reached until it exits
+    instances: Vec<Weak<AtomicBool>>,
 }
 
 impl Runtime {
-    pub fn singleton() -> &'static Mutex<Runtime> {
-        return get_runtime();
+    /// New running flag for a node, initially cleared
+    pub fn register() -> Arc<AtomicBool> {
+        let running = Arc::new(AtomicBool::new(false));
+        let mut runtime = get_runtime();
+        runtime
+            .instances
+            .retain(|instance| instance.strong_count() > 0);
+        runtime.instances.push(Arc::downgrade(&running));
+        running
     }
 
-    pub fn running() -> Arc<AtomicBool> {
-        if let Ok(runtime) = get_runtime().lock() {
-            return runtime.running.clone();
-        }
-        panic!("Something went wrong");
+    /// Allow the thread owning `running` to run; called before it is spawned
+    pub fn start(running: &AtomicBool) {
+        running.store(true, Ordering::Relaxed);
+    }
+
+    /// Make the thread owning `running` exit
+    pub fn stop(running: &AtomicBool) {
+        running.store(false, Ordering::Relaxed);
     }
 
+    /// Make every whisper thread exit, e.g. when the extension unloads
     pub fn free() {
-        if let Ok(runtime) = get_runtime().lock() {
-            runtime.running.store(false, Ordering::Relaxed);
+        let mut runtime = get_runtime();
+        for running in runtime.instances.iter().filter_map(Weak::upgrade) {
+            Self::stop(&running);
         }
+        runtime
+            .instances
+            .retain(|instance| instance.strong_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances_stop_independently_and_free_stops_all() {
+        let first = Runtime::register();
+        let second = Runtime::register();
+        Runtime::start(&first);
+        Runtime::start(&second);
+
+        Runtime::stop(&first);
+        assert!(!first.load(Ordering::Relaxed));
+        assert!(second.load(Ordering::Relaxed));
+
+        Runtime::start(&first);
+        Runtime::free();
+        assert!(!first.load(Ordering::Relaxed));
+        assert!(!second.load(Ordering::Relaxed));
     }
 }
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Spell cast by a trigger phrase
+#[derive(Debug, Clone)]
+struct SpellTrigger {
+    spell: String,
+    /// Detections within this long of the last cast are ignored
+    cooldown: Duration,
+    last_cast: Option<SystemTime>,
+}
+
+/// Trigger phrases and the spells they cast
+#[derive(Debug, Default)]
+pub struct Spellbook {
+    triggers: HashMap<String, SpellTrigger>,
+}
+
+impl Spellbook {
+    /// Map `phrase` to `spell`, replacing an earlier registration of it
+    pub fn register(&mut self, phrase: String, spell: String, cooldown: Duration) {
+        self.triggers.insert(
+            phrase,
+            SpellTrigger {
+                spell,
+                cooldown,
+                last_cast: None,
+            },
+        );
+    }
+
+    /// Returns false when `phrase` wasn't registered
+    pub fn unregister(&mut self, phrase: &str) -> bool {
+        self.triggers.remove(phrase).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.triggers.clear();
+    }
+
+    /// Spell of `phrase`, regardless of its cooldown
+    pub fn get(&self, phrase: &str) -> Option<String> {
+        self.triggers
+            .get(phrase)
+            .map(|trigger| trigger.spell.clone())
+    }
+
+    /// Spell of `phrase` detected at `timestamp`, or None while the trigger is
+    /// cooling down from its last cast
+    pub fn cast(&mut self, phrase: &str, timestamp: SystemTime) -> Option<String> {
+        let trigger = self.triggers.get_mut(phrase)?;
+
+        if let Some(last_cast) = trigger.last_cast {
+            // A timestamp before the last cast (clock adjustments) counts as no time passed
+            let elapsed = timestamp.duration_since(last_cast).unwrap_or_default();
+            if elapsed < trigger.cooldown {
+                return None;
+            }
+        }
+
+        trigger.last_cast = Some(timestamp);
+        Some(trigger.spell.clone())
+    }
+
+    /// Registered trigger phrases, sorted
+    pub fn phrases(&self) -> Vec<String> {
+        let mut phrases: Vec<String> = self.triggers.keys().cloned().collect();
+        phrases.sort();
+        phrases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown_suppresses_repeated_casts() {
+        let mut book = Spellbook::default();
+        book.register(
+            "fire".to_owned(),
+            "fireball".to_owned(),
+            Duration::from_millis(500),
+        );
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        assert_eq!(book.cast("fire", start).as_deref(), Some("fireball"));
+        assert_eq!(book.cast("fire", start + Duration::from_millis(200)), None);
+        assert_eq!(
+            book.cast("fire", start + Duration::from_millis(600))
+                .as_deref(),
+            Some("fireball")
+        );
+        assert_eq!(book.cast("ice", start), None);
+    }
+
+    #[test]
+    fn test_no_cooldown_casts_every_time() {
+        let mut book = Spellbook::default();
+        book.register("heal".to_owned(), "heal".to_owned(), Duration::ZERO);
+
+        let now = SystemTime::now();
+        assert!(book.cast("heal", now).is_some());
+        assert!(book.cast("heal", now).is_some());
+    }
+
+    #[test]
+    fn test_unregister_and_clear() {
+        let mut book = Spellbook::default();
+        book.register("fire".to_owned(), "fireball".to_owned(), Duration::ZERO);
+        book.register("ice".to_owned(), "frost".to_owned(), Duration::ZERO);
+        assert_eq!(book.phrases(), vec!["fire", "ice"]);
+
+        assert!(book.unregister("fire"));
+        assert!(!book.unregister("fire"));
+        assert_eq!(book.get("fire"), None);
+        assert_eq!(book.phrases(), vec!["ice"]);
+
+        book.clear();
+        assert!(book.phrases().is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use opus2::{Application, Channels, Encoder};
+
+use crate::codec::{EncodeOptions, RelayClock, encode_stereo_to_opus};
+use crate::godot_thread_print::GodotThreadPrint;
+use crate::whisper::WHISPER_SAMPLE_RATE;
+
+/// Audio handed over per step
+const STEP: Duration = Duration::from_millis(10);
+/// Silence played after the file so the last utterance ends
+const TRAILING_SILENCE: Duration = Duration::from_secs(1);
+/// How long a faster-than-real-time feed waits for room in a full whisper queue
+const QUEUE_RETRY: Duration = Duration::from_millis(1);
+
+/// Interleaved samples of a WAV file, normalized to [-1, 1]
+#[derive(Debug, Clone)]
+pub struct WavAudio {
+    pub samples: Vec<f32>,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+/// Read an integer (8 to 32 bit) or float WAV file
+pub fn read_wav(path: &Path) -> Result<WavAudio, Box<dyn Error>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    if spec.channels == 0 || spec.sample_rate == 0 {
+        return Err(format!("{}: no channels or sample rate", path.display()).into());
+    }
+
+    Ok(WavAudio {
+        samples,
+        channels: spec.channels as usize,
+        sample_rate: spec.sample_rate,
+    })
+}
+
+/// Audio of a WAV file prepared for the capture channels: the 16kHz mono
+/// whisper feed and, when relayed, 48kHz stereo for the Opus relay
+pub struct WavFeed {
+    pub whisper: Vec<f32>,
+    pub relay: Option<Vec<f32>>,
+}
+
+/// Plays a `WavFeed` into the whisper and relay channels on its own thread,
+/// in place of the microphone
+pub struct WavPlayer {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl WavPlayer {
+    /// Start feeding. With `realtime` the audio is paced like a microphone;
+    /// otherwise it goes as fast as the whisper thread takes it, without
+    /// dropping any.
+    pub fn start(
+        feed: WavFeed,
+        tx: SyncSender<Vec<f32>>,
+        relay_audio: Sender<Vec<u8>>,
+        relay_clock: Arc<RelayClock>,
+        encode_options: EncodeOptions,
+        frame_size: usize,
+        realtime: bool,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let result = play(
+                    &feed,
+                    &tx,
+                    &relay_audio,
+                    &relay_clock,
+                    encode_options,
+                    frame_size,
+                    realtime,
+                    &stop,
+                );
+                if let Err(err) = result {
+                    GodotThreadPrint::print(format!("WAV playback stopped: {}", err));
+                }
+            })
+        };
+        Self { stop, thread }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Stop feeding; the thread lets go of the channels once it notices
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn play(
+    feed: &WavFeed,
+    tx: &SyncSender<Vec<f32>>,
+    relay_audio: &Sender<Vec<u8>>,
+    relay_clock: &RelayClock,
+    encode_options: EncodeOptions,
+    frame_size: usize,
+    realtime: bool,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    let whisper_step = (WHISPER_SAMPLE_RATE as f32 * STEP.as_secs_f32()) as usize;
+    let relay_step = (48000.0 * STEP.as_secs_f32()) as usize * 2;
+    let frame_len = frame_size * 2;
+    let mut pending = Vec::with_capacity(frame_len + relay_step);
+    let silence_steps = (TRAILING_SILENCE.as_millis() / STEP.as_millis()) as usize;
+    let steps = feed.whisper.len().div_ceil(whisper_step) + silence_steps;
+    let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Voip)?;
+    let started = Instant::now();
+
+    for step in 0..steps {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if realtime {
+            let due = started + STEP * step as u32;
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        let start = (step * whisper_step).min(feed.whisper.len());
+        let end = (start + whisper_step).min(feed.whisper.len());
+        let mut samples = feed.whisper[start..end].to_vec();
+        samples.resize(whisper_step, 0.0);
+        send_whisper(tx, samples, realtime, stop)?;
+
+        if let Some(relay) = &feed.relay {
+            let start = (step * relay_step).min(relay.len());
+            let end = (start + relay_step).min(relay.len());
+            pending.extend_from_slice(&relay[start..end]);
+            pending.resize(pending.len() + relay_step - (end - start), 0.0);
+
+            while pending.len() >= frame_len {
+                let packet = encode_stereo_to_opus(
+                    &mut encoder,
+                    &pending[..frame_len],
+                    48000,
+                    frame_size,
+                    &encode_options,
+                )?;
+                pending.drain(..frame_len);
+
+                // DTX left the frame out (a lone empty packet): send nothing
+                if encode_options.dtx && packet == [0, 0] {
+                    relay_clock.skip(frame_size);
+                    continue;
+                }
+                // Nobody listening to the relay is fine, drop the packet
+                let _ = relay_audio.send(relay_clock.stamp(packet, frame_size));
+            }
+        }
+    }
+
+    GodotThreadPrint::print("WAV playback finished".to_string());
+    Ok(())
+}
+
+/// Queue `samples` for whisper. Real-time playback drops them when whisper
+/// is behind, like the microphone; otherwise it waits for room.
+fn send_whisper(
+    tx: &SyncSender<Vec<f32>>,
+    mut samples: Vec<f32>,
+    realtime: bool,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        match tx.try_send(samples) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(_)) if realtime => return Ok(()),
+            Err(TrySendError::Full(rejected)) => {
+                if stop.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                samples = rejected;
+                std::thread::sleep(QUEUE_RETRY);
+            }
+            Err(TrySendError::Disconnected(_)) => return Err("whisper stopped listening".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::RelayHeader;
+
+    #[test]
+    fn test_read_wav_normalizes_integer_samples() {
+        let path = std::env::temp_dir().join("godot_whisper_test_read.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [0i16, i16::MIN, 16384, -16384] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let audio = read_wav(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(audio.channels, 2);
+        assert_eq!(audio.sample_rate, 22050);
+        assert_eq!(audio.samples, vec![0.0, -1.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_fast_playback_delivers_every_sample() {
+        let (tx, rx) = std::sync::mpsc::sync_channel(4);
+        let (relay_tx, relay_rx) = std::sync::mpsc::channel();
+        let feed = WavFeed {
+            whisper: vec![0.25; WHISPER_SAMPLE_RATE as usize / 2],
+            relay: Some(vec![0.25; 48000]),
+        };
+
+        let _player = WavPlayer::start(
+            feed,
+            tx,
+            relay_tx,
+            Arc::new(RelayClock::default()),
+            EncodeOptions::voice(),
+            960,
+            false,
+        );
+        // Both iterators end once the finished thread drops its senders
+        let received: Vec<f32> = rx.iter().flatten().collect();
+        let blobs: Vec<Vec<u8>> = relay_rx.iter().collect();
+
+        // Half a second of audio plus the trailing silence, in 10ms steps
+        assert_eq!(received.len(), WHISPER_SAMPLE_RATE as usize * 3 / 2);
+        assert!(
+            received[..WHISPER_SAMPLE_RATE as usize / 2]
+                .iter()
+                .all(|&s| s == 0.25)
+        );
+        // 20ms relay frames, numbered in order
+        assert_eq!(blobs.len(), 75);
+        for (i, blob) in blobs.iter().enumerate() {
+            let header = RelayHeader::parse(blob).unwrap();
+            assert_eq!(header.sequence, i as u32);
+            assert_eq!(header.timestamp, i as u64 * 960);
+        }
+    }
+}
@@ -1,14 +1,33 @@
 use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
     sync::{
         Arc, Mutex,
-        atomic::{AtomicBool, Ordering},
-        mpsc::Receiver,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
     },
     thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use whisper_rs::{
+    FullParams, SamplingStrategy, SegmentCallbackData, WhisperContext, WhisperContextParameters,
+    WhisperState,
 };
-use whisper_rs::{FullParams, WhisperContextParameters, WhisperState};
 
 use crate::godot_thread_print::GodotThreadPrint;
+use crate::microphone::Microphone;
+
+/// whisper.cpp only understands 16kHz mono; anything else transcribes as garbage
+pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+/// `WHISPER_SAMPLE_RATE` for buffer-length math
+const SAMPLES_PER_SECOND: usize = WHISPER_SAMPLE_RATE as usize;
+
+// Segmentation works in whole 20ms frames
+const _: () = assert!(
+    WHISPER_SAMPLE_RATE > 0 && WHISPER_SAMPLE_RATE % 50 == 0,
+    "WHISPER_SAMPLE_RATE must be a multiple of 50Hz"
+);
 
 #[derive(Debug, Clone)]
 pub struct KeywordDetection {
@@ -16,69 +35,964 @@ pub struct KeywordDetection {
     pub transcription: String,
     pub confidence: f32,
     pub timestamp: std::time::SystemTime,
+    pub segments: Vec<TranscribedSegment>,
+    /// Tokens that spelled the keyword as (text, t0, t1), centiseconds from the window start
+    pub words: Vec<(String, i64, i64)>,
+}
+
+/// Text token of a transcription, timestamps in centiseconds from the window start
+#[derive(Debug, Clone)]
+pub struct TranscribedToken {
+    pub text: String,
+    pub t0: i64,
+    pub t1: i64,
+    pub probability: f32,
+}
+
+/// Segment of a transcription, timestamps in centiseconds from the window start
+#[derive(Debug, Clone)]
+pub struct TranscribedSegment {
+    pub text: String,
+    pub t0: i64,
+    pub t1: i64,
+    /// Whisper's estimate that the segment holds no speech at all
+    pub no_speech_prob: f32,
+    /// Mean log-probability of the text tokens
+    pub avg_logprob: f32,
+}
+
+/// Output of a single whisper run
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    pub text: String,
+    /// Mean probability of the text tokens
+    pub confidence: f32,
+    pub segments: Vec<TranscribedSegment>,
+    pub tokens: Vec<TranscribedToken>,
+}
+
+/// How long the spotter thread waits for audio before checking its control flags
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+/// Temperature step of a fallback decode, whisper's default
+const TEMPERATURE_INC: f32 = 0.2;
+/// Most audio (16kHz samples) held while the model loads; older audio is dropped
+const PRELOAD_HOLD: usize = SAMPLES_PER_SECOND * 10;
+/// Piece size (16kHz samples) the held audio is replayed in once the model is up
+const PRELOAD_PIECE: usize = SAMPLES_PER_SECOND / 10;
+
+const SILENCE_THRESHOLD: f32 = 0.015;
+const SILENCE_HOLD: usize = 2048 * 2;
+const SILENCE_CHECK_TAIL: usize = 512;
+/// Longest utterance (16kHz samples) buffered before transcribing anyway
+const MIN_UTTERANCE: usize = SAMPLES_PER_SECOND * 3;
+/// Default cap on any buffered utterance (16kHz samples), whisper's own window
+const MAX_UTTERANCE: usize = SAMPLES_PER_SECOND * 30;
+
+/// Utterance segmentation, tunable while the spotter runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceSettings {
+    /// RMS below which audio counts as silence (unless the VAD is adaptive)
+    pub threshold: f32,
+    /// Silence (16kHz samples) that ends an utterance
+    pub hold: usize,
+    /// Audio (16kHz samples) buffered before transcribing even without a pause
+    pub min_utterance: usize,
+    /// Hard cap (16kHz samples) on a buffered utterance in any segmentation,
+    /// bounding how long a rambling speaker delays detection
+    pub max_utterance: usize,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        Self {
+            threshold: SILENCE_THRESHOLD,
+            hold: SILENCE_HOLD,
+            min_utterance: MIN_UTTERANCE,
+            max_utterance: MAX_UTTERANCE,
+        }
+    }
+}
+
+/// How a segmented (`vad_enabled`) spotter decides where an utterance ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Segmentation {
+    /// Cut after a silence gap, or once `min_utterance` is buffered
+    #[default]
+    Fixed,
+    /// Cut only where `VoiceDetector` hears speech stop
+    Vad,
+}
+
+impl Segmentation {
+    /// Parse "fixed" or "vad" (case insensitive)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "fixed" => Some(Self::Fixed),
+            "vad" => Some(Self::Vad),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fixed => "fixed",
+            Self::Vad => "vad",
+        }
+    }
+}
+
+/// Analysis frame of the voice detector, 20ms at 16kHz
+const VAD_FRAME: usize = SAMPLES_PER_SECOND / 50;
+/// Zero-crossing rate above which a frame is unvoiced (fricatives, hiss)
+const VAD_VOICED_ZCR: f32 = 0.25;
+/// Silence (16kHz samples) kept ahead of speech so its onset isn't clipped
+const VAD_PRE_ROLL: usize = SAMPLES_PER_SECOND * 3 / 10;
+
+/// Fraction of adjacent samples that change sign
+fn zero_crossing_rate(samples: &[f32]) -> f32 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let crossings = samples
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    crossings as f32 / (samples.len() - 1) as f32
+}
+
+/// Frame-level voice activity from energy and zero-crossing rate, held for a
+/// hangover after the last speech frame so short pauses inside a sentence
+/// don't end it
+#[derive(Debug, Clone)]
+struct VoiceDetector {
+    hangover: usize,
+    /// Speech frames left to hold before declaring the end of speech
+    remaining: usize,
+    speaking: bool,
+    frame: Vec<f32>,
+}
+
+impl VoiceDetector {
+    /// `hangover` in 16kHz samples
+    fn new(hangover: usize) -> Self {
+        Self {
+            hangover: hangover.div_ceil(VAD_FRAME),
+            remaining: 0,
+            speaking: false,
+            frame: Vec::with_capacity(VAD_FRAME),
+        }
+    }
+
+    /// Voiced frames are loud with few zero crossings. Unvoiced consonants are
+    /// much quieter but cross zero often, so they count at half the threshold.
+    fn is_speech(frame: &[f32], threshold: f32) -> bool {
+        let rms = WhisperKeywordSpotter::rms(frame);
+        if zero_crossing_rate(frame) <= VAD_VOICED_ZCR {
+            rms >= threshold
+        } else {
+            rms >= threshold * 0.5
+        }
+    }
+
+    /// Feed audio; true when speech ended somewhere in it
+    fn push(&mut self, samples: &[f32], threshold: f32) -> bool {
+        let mut ended = false;
+        for &sample in samples {
+            self.frame.push(sample);
+            if self.frame.len() < VAD_FRAME {
+                continue;
+            }
+
+            if Self::is_speech(&self.frame, threshold) {
+                self.speaking = true;
+                self.remaining = self.hangover;
+            } else if self.speaking {
+                if self.remaining == 0 {
+                    self.speaking = false;
+                    ended = true;
+                } else {
+                    self.remaining -= 1;
+                }
+            }
+            self.frame.clear();
+        }
+        ended
+    }
+
+    fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    fn reset(&mut self) {
+        self.speaking = false;
+        self.remaining = 0;
+        self.frame.clear();
+    }
+}
+
+/// Window length and overlap (16kHz samples) when the VAD is disabled
+const CONTINUOUS_WINDOW: usize = SAMPLES_PER_SECOND * 3;
+const CONTINUOUS_OVERLAP: usize = SAMPLES_PER_SECOND / 2;
+
+/// New audio (16kHz samples) between two interim transcriptions of an utterance
+const PROVISIONAL_STEP: usize = SAMPLES_PER_SECOND;
+
+/// Number of recent transcriptions averaged by `SpotterStats`
+const STATS_WINDOW: usize = 20;
+
+/// Processing time vs audio duration of the most recent transcriptions
+#[derive(Debug, Default)]
+pub struct SpotterStats {
+    recent: VecDeque<(Duration, Duration)>,
+}
+
+impl SpotterStats {
+    pub fn record(&mut self, processing: Duration, samples: usize) {
+        if self.recent.len() == STATS_WINDOW {
+            self.recent.pop_front();
+        }
+        let audio = Duration::from_secs_f64(samples as f64 / WHISPER_SAMPLE_RATE as f64);
+        self.recent.push_back((processing, audio));
+    }
+
+    /// Wall-time spent transcribing per second of audio. Below 1.0 is faster than real time.
+    pub fn realtime_factor(&self) -> f32 {
+        let (processing, audio) = self
+            .recent
+            .iter()
+            .fold((Duration::ZERO, Duration::ZERO), |(p, a), (dp, da)| {
+                (p + *dp, a + *da)
+            });
+
+        if audio.is_zero() {
+            return 0.0;
+        }
+        processing.as_secs_f32() / audio.as_secs_f32()
+    }
+}
+
+/// Transcription options captured when the spotter thread is spawned
+#[derive(Debug, Clone)]
+pub struct SpotterConfig {
+    /// Ignore punctuation when matching keywords ("fireball." matches "fireball")
+    pub strip_punctuation: bool,
+    /// Transcribe utterances while they are still being spoken and report keywords early
+    pub provisional_casting: bool,
+    /// Minimum confidence of an interim match to be reported as provisional
+    pub provisional_threshold: f32,
+    /// Derive the silence threshold from a running noise floor estimate
+    pub adaptive_vad: bool,
+    /// How far above the noise floor (dB) the adaptive threshold sits
+    pub adaptive_vad_margin_db: f32,
+    /// Seconds of captured audio kept for `retro_transcribe`
+    pub history_seconds: f32,
+    /// Segment on silence. When off, overlapping fixed windows are transcribed continuously.
+    pub vad_enabled: bool,
+    /// Where segmented utterances end, see `Segmentation`
+    pub segmentation: Segmentation,
+    /// Silence (seconds) after speech before a `Segmentation::Vad` utterance ends
+    pub vad_hangover: f32,
+    /// Audio (seconds) kept as the start of the next utterance when one is cut
+    /// at its maximum length mid-speech, so words on the cut aren't lost
+    pub segment_overlap: f32,
+    /// Longest segment whisper emits, in characters; longer ones are split
+    /// at a word boundary. 0 leaves segment length to whisper.
+    pub max_segment_len: i32,
+    /// Bias decoding toward the registered keywords
+    pub constrain_to_keywords: bool,
+    /// Text whisper is primed with, e.g. game jargon and names
+    pub initial_prompt: String,
+    /// Append the registered keywords to the initial prompt
+    pub prompt_keywords: bool,
+    /// In continuous mode, only match words that weren't in the previous window
+    pub stabilization: bool,
+    /// Lowest similarity (0-1) at which a misheard keyword still matches.
+    /// 1.0 only accepts the keyword spelled exactly.
+    pub keyword_similarity: f32,
+    /// Segments whisper rates as more likely than this to hold no speech are
+    /// discarded before keyword matching. 1.0 keeps them all.
+    pub no_speech_threshold: f32,
+    /// Segments whose mean token log-probability is below this are discarded
+    /// before keyword matching
+    pub logprob_threshold: f32,
+    /// Whisper language code, or "auto" to detect it per utterance
+    pub language: String,
+    /// Translate the speech into English instead of transcribing it as spoken
+    pub translate: bool,
+    /// Report each segment of a final transcription while whisper is still
+    /// decoding the rest
+    pub stream_segments: bool,
+    pub sampling: Sampling,
+    /// Sampling temperature of the first decode; 0 always picks the likeliest token
+    pub temperature: f32,
+    /// Decode again at a higher temperature, `TEMPERATURE_INC` at a time, when
+    /// the result looks like a failure (repetitive or improbable text)
+    pub temperature_fallback: bool,
+    /// CPU threads whisper decodes with
+    pub threads: i32,
+    /// Run the model on the GPU backend whisper-rs was built with
+    pub use_gpu: bool,
+    /// Reload the model and keep listening when the spotter thread panics
+    pub auto_recover: bool,
+    /// Panics recovered from before giving up
+    pub max_recoveries: u32,
+}
+
+impl Default for SpotterConfig {
+    fn default() -> Self {
+        Self {
+            strip_punctuation: true,
+            provisional_casting: false,
+            provisional_threshold: 0.8,
+            adaptive_vad: false,
+            adaptive_vad_margin_db: 10.0,
+            history_seconds: 30.0,
+            vad_enabled: true,
+            segmentation: Segmentation::Fixed,
+            vad_hangover: 0.3,
+            segment_overlap: 0.0,
+            max_segment_len: 0,
+            constrain_to_keywords: false,
+            initial_prompt: String::new(),
+            prompt_keywords: true,
+            stabilization: true,
+            keyword_similarity: 1.0,
+            no_speech_threshold: 0.6,
+            logprob_threshold: -1.0,
+            language: "en".to_owned(),
+            translate: false,
+            stream_segments: false,
+            sampling: Sampling::Greedy { best_of: 1 },
+            temperature: 0.0,
+            temperature_fallback: true,
+            threads: available_threads(),
+            use_gpu: true,
+            auto_recover: false,
+            max_recoveries: 3,
+        }
+    }
+}
+
+/// Decoder search, see `SpotterConfig::sampling`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Keep the best of `best_of` candidates at each token. Fastest.
+    Greedy { best_of: i32 },
+    /// Follow `beam_size` hypotheses in parallel. More robust on noisy input,
+    /// but each transcription costs roughly `beam_size` times as much.
+    BeamSearch { beam_size: i32 },
+}
+
+impl Sampling {
+    fn strategy(&self) -> SamplingStrategy {
+        match *self {
+            Sampling::Greedy { best_of } => SamplingStrategy::Greedy { best_of },
+            Sampling::BeamSearch { beam_size } => SamplingStrategy::BeamSearch {
+                beam_size,
+                // whisper.cpp's default, patience is not implemented there
+                patience: -1.0,
+            },
+        }
+    }
+}
+
+/// Notifications from the spotter thread, drained by the node in `process`
+#[derive(Debug, Clone)]
+pub enum SpotterEvent {
+    /// Keyword heard in an interim transcription, awaiting the final result
+    Provisional(String),
+    /// Provisional keyword that the final transcription did not confirm
+    Retracted(String),
+    /// Text of a final transcription, keyword or not
+    Transcribed(String),
+    /// Segment of a final transcription as soon as whisper decoded it, with
+    /// its start and end in centiseconds from the utterance start
+    Segment(String, i64, i64),
+    /// Language whisper heard, as a code and its probability, in "auto" mode
+    /// when it changed
+    LanguageDetected(String, f32),
+    /// The model loaded and the thread is transcribing
+    Ready,
+    /// Thread panicked and was brought back up, with the attempt number
+    Recovered(u32),
+    /// Thread stopped for good after a panic, with the reason
+    Failed(String),
+    /// The model could not be loaded, with the path and the error
+    LoadFailed(String),
+}
+
+/// Lowercase `text`, turn punctuation into spaces and collapse runs of whitespace
+pub fn normalize_text(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c.is_whitespace() {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Lifecycle of the spotter thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhisperStatus {
+    #[default]
+    Stopped,
+    Initializing,
+    LoadingModel,
+    Ready,
+    Transcribing,
+    Error,
+}
+
+impl WhisperStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WhisperStatus::Stopped => "Stopped",
+            WhisperStatus::Initializing => "Initializing",
+            WhisperStatus::LoadingModel => "LoadingModel",
+            WhisperStatus::Ready => "Ready",
+            WhisperStatus::Transcribing => "Transcribing",
+            WhisperStatus::Error => "Error",
+        }
+    }
+}
+
+/// Slow-moving estimate of the background noise level (RMS)
+#[derive(Debug, Clone)]
+struct NoiseFloor {
+    level: f32,
+}
+
+impl NoiseFloor {
+    fn new() -> Self {
+        Self { level: 0.005 }
+    }
+
+    /// Follow quiet chunks quickly and loud ones slowly, so speech barely
+    /// moves the estimate while a fan spinning up does over a few seconds
+    fn update(&mut self, rms: f32) {
+        let rate = if rms < self.level { 0.1 } else { 0.002 };
+        self.level += (rms - self.level) * rate;
+    }
+
+    fn threshold(&self, margin_db: f32) -> f32 {
+        self.level * 10f32.powf(margin_db / 20.0)
+    }
+}
+
+/// Drops the words an overlapping window repeats from the previous one, so
+/// continuous transcription only reports each word once
+#[derive(Default)]
+pub struct Stabilizer {
+    /// Normalized words of the previous window
+    previous: Vec<String>,
+}
+
+impl Stabilizer {
+    /// Text of `transcription` not already heard at the end of the previous window
+    pub fn new_words(&mut self, transcription: &str) -> String {
+        let words: Vec<&str> = transcription
+            .split_whitespace()
+            .filter(|word| !normalize_text(word).is_empty())
+            .collect();
+        let normalized: Vec<String> = words.iter().map(|word| normalize_text(word)).collect();
+
+        // Longest run that ends the previous window and starts this one
+        let longest = self.previous.len().min(normalized.len());
+        let repeated = (1..=longest)
+            .rev()
+            .find(|&k| self.previous[self.previous.len() - k..] == normalized[..k])
+            .unwrap_or(0);
+
+        self.previous = normalized;
+        words[repeated..].join(" ")
+    }
+
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/// Tokens that spell `needle` (already normalized with `normalize`), found by
+/// rebuilding the normalized text from the tokens and keeping the ones that
+/// overlap the first occurrence
+pub fn matched_tokens<'a>(
+    tokens: &'a [TranscribedToken],
+    needle: &str,
+    normalize: impl Fn(&str) -> String,
+) -> Vec<&'a TranscribedToken> {
+    let mut haystack = String::new();
+    let mut spans = Vec::new();
+
+    for token in tokens {
+        let text = normalize(&token.text);
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        // Tokens starting a new word carry a leading space; the others continue one
+        if token.text.starts_with(char::is_whitespace) && !haystack.is_empty() {
+            haystack.push(' ');
+        }
+        let start = haystack.len();
+        haystack.push_str(text);
+        spans.push((start, haystack.len(), token));
+    }
+
+    let Some(start) = haystack.find(needle) else {
+        return Vec::new();
+    };
+    let end = start + needle.len();
+
+    spans
+        .into_iter()
+        .filter(|(token_start, token_end, _)| *token_start < end && *token_end > start)
+        .map(|(_, _, token)| token)
+        .collect()
+}
+
+/// Edit distance between `a` and `b`, counted in chars
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+/// Run of whole words in `haystack` that most resembles `needle`, with its
+/// similarity from 0 to 1. Spaces are ignored when comparing, so "fire ball"
+/// matches "fireball" fully and "fire bal" nearly.
+pub fn fuzzy_find(haystack: &str, needle: &str) -> Option<(String, f32)> {
+    let words: Vec<&str> = haystack.split_whitespace().collect();
+    let needle_words = needle.split_whitespace().count();
+    let compact_needle: String = needle.split_whitespace().collect();
+    if words.is_empty() || compact_needle.is_empty() {
+        return None;
+    }
+
+    let mut best: Option<(String, f32)> = None;
+    for len in needle_words.saturating_sub(1).max(1)..=needle_words + 1 {
+        for window in words.windows(len) {
+            let compact = window.concat();
+            let longest = compact.chars().count().max(compact_needle.chars().count());
+            let score = 1.0 - levenshtein(&compact, &compact_needle) as f32 / longest as f32;
+
+            if best.as_ref().is_none_or(|(_, best)| score > *best) {
+                best = Some((window.join(" "), score));
+            }
+        }
+    }
+
+    best
+}
+
+/// Logical CPUs this process may use, 1 when unknown
+pub fn available_threads() -> i32 {
+    std::thread::available_parallelism()
+        .map(|n| n.get() as i32)
+        .unwrap_or(1)
+}
+
+/// Whether whisper knows `language` ("auto" included)
+pub fn is_valid_language(language: &str) -> bool {
+    language == "auto" || whisper_rs::get_lang_id(language).is_some()
+}
+
+/// Initial prompt listing the commands, used to bias whisper toward them
+pub fn keyword_prompt(keywords: &[String]) -> String {
+    format!("Commands: {}.", keywords.join(", "))
+}
+
+/// Text whisper is primed with: the user's prompt followed by the command list.
+/// Whisper has no grammar support here, but priming the decoder with the
+/// commands strongly biases it toward those spellings.
+pub fn initial_prompt(config: &SpotterConfig, keywords: &[String]) -> Option<String> {
+    let mut parts = Vec::new();
+    let custom = config.initial_prompt.trim();
+    if !custom.is_empty() {
+        parts.push(custom.to_owned());
+    }
+    if (config.prompt_keywords || config.constrain_to_keywords) && !keywords.is_empty() {
+        parts.push(keyword_prompt(keywords));
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+/// Opt-in copy of the whisper feed (16kHz mono), for replaying what was said
+#[derive(Debug, Default)]
+pub struct PcmRecording {
+    pub enabled: bool,
+    /// Samples kept at most; audio past the cap is not recorded
+    pub max_samples: usize,
+    pub samples: Vec<f32>,
+}
+
+impl PcmRecording {
+    pub fn push(&mut self, samples: &[f32]) {
+        if !self.enabled {
+            return;
+        }
+        let room = self.max_samples.saturating_sub(self.samples.len());
+        self.samples
+            .extend_from_slice(&samples[..room.min(samples.len())]);
+    }
+}
+
+/// State shared between the `Whisper` node and the spotter thread
+#[derive(Clone)]
+pub struct SpotterShared {
+    pub matches: Arc<Mutex<Option<KeywordDetection>>>,
+    pub stats: Arc<Mutex<SpotterStats>>,
+    pub events: Sender<SpotterEvent>,
+    /// Tokens of the most recent final transcription
+    pub tokens: Arc<Mutex<Vec<TranscribedToken>>>,
+    /// Segments of the most recent final transcription
+    pub segments: Arc<Mutex<Vec<TranscribedSegment>>>,
+    /// Loaded model, for on-demand transcription outside the spotter thread
+    pub context: Arc<Mutex<Option<Arc<WhisperContext>>>>,
+    /// Most recent captured audio (16kHz mono), capped at `history_seconds`
+    pub history: Arc<Mutex<VecDeque<f32>>>,
+    pub pcm_recording: Arc<Mutex<PcmRecording>>,
+    /// Set by the node to have the thread finalize the utterance in progress
+    pub flush: Arc<AtomicBool>,
+    /// Capture is paused: received audio is discarded instead of buffered
+    pub paused: Arc<AtomicBool>,
+    /// Push-to-talk is released: received audio is discarded as when paused
+    pub listening: Arc<AtomicBool>,
+    /// Set by the node to have the thread finish up and exit
+    pub stop: Arc<AtomicBool>,
+    /// The model is loaded and the thread is taking audio
+    pub initialized: Arc<AtomicBool>,
+    pub status: Arc<Mutex<WhisperStatus>>,
+    /// Interim transcriptions skipped because captured audio was queued up
+    pub skipped_detections: Arc<AtomicU64>,
+    pub silence: Arc<Mutex<SilenceSettings>>,
+}
+
+impl SpotterShared {
+    pub fn new() -> (Self, Receiver<SpotterEvent>) {
+        let (events, rx) = std::sync::mpsc::channel::<SpotterEvent>();
+        let shared = Self {
+            matches: Arc::new(Mutex::new(None)),
+            stats: Arc::new(Mutex::new(SpotterStats::default())),
+            events,
+            tokens: Arc::new(Mutex::new(Vec::new())),
+            segments: Arc::new(Mutex::new(Vec::new())),
+            context: Arc::new(Mutex::new(None)),
+            history: Arc::new(Mutex::new(VecDeque::new())),
+            pcm_recording: Arc::new(Mutex::new(PcmRecording {
+                max_samples: SAMPLES_PER_SECOND * 60,
+                ..PcmRecording::default()
+            })),
+            flush: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            listening: Arc::new(AtomicBool::new(true)),
+            stop: Arc::new(AtomicBool::new(false)),
+            initialized: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new(WhisperStatus::default())),
+            skipped_detections: Arc::new(AtomicU64::new(0)),
+            silence: Arc::new(Mutex::new(SilenceSettings::default())),
+        };
+        (shared, rx)
+    }
+
+    pub fn set_status(&self, status: WhisperStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    pub fn status(&self) -> WhisperStatus {
+        self.status.lock().map(|status| *status).unwrap_or_default()
+    }
+
+    /// Stop reporting a model: the thread lost it and isn't taking audio
+    fn unload(&self) {
+        self.initialized.store(false, Ordering::Relaxed);
+        if let Ok(mut context) = self.context.lock() {
+            *context = None;
+        }
+    }
+
+    pub fn silence(&self) -> SilenceSettings {
+        self.silence
+            .lock()
+            .map(|silence| *silence)
+            .unwrap_or_default()
+    }
+}
+
+/// Where the spotter loads its ggml model from
+#[derive(Clone)]
+pub enum ModelSource {
+    /// Model file on disk
+    Path(String),
+    /// Model already in memory, e.g. read out of the game's .pck
+    Buffer(Arc<Vec<u8>>),
+}
+
+impl std::fmt::Display for ModelSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelSource::Path(path) => write!(f, "{}", path),
+            ModelSource::Buffer(data) => write!(f, "<{} byte buffer>", data.len()),
+        }
+    }
 }
 
 /// ML-based Keyword Spotter using Whisper
 pub struct WhisperKeywordSpotter {
-    pub ctx: whisper_rs::WhisperContext,
-    keywords: Vec<String>,
+    pub ctx: Arc<WhisperContext>,
+    /// Shared with the node, so keywords registered while running apply to
+    /// the next transcription
+    keywords: Arc<Mutex<Vec<String>>>,
+    config: SpotterConfig,
 }
 
 impl WhisperKeywordSpotter {
     pub fn new(
-        model_path: &str,
-        keywords: Vec<String>,
+        model: &ModelSource,
+        keywords: Arc<Mutex<Vec<String>>>,
+        config: SpotterConfig,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        // Load Whisper model
-        let mut params = WhisperContextParameters::default();
-        params.use_gpu(true);
-        let ctx = whisper_rs::WhisperContext::new_with_params(model_path, params)?;
+        let ctx = load_context(model, config.use_gpu)?;
+
+        // whisper-rs doesn't say which backend the context ended up on
+        GodotThreadPrint::print(format!(
+            "Whisper model loaded, {} requested",
+            if config.use_gpu { "GPU" } else { "CPU" }
+        ));
 
-        Ok(Self { ctx, keywords })
+        Ok(Self {
+            ctx: Arc::new(ctx),
+            keywords,
+            config,
+        })
+    }
+
+    /// Snapshot of the registered keywords
+    pub fn keywords(&self) -> Vec<String> {
+        self.keywords
+            .lock()
+            .map(|keywords| keywords.clone())
+            .unwrap_or_default()
     }
 
     /// Transcribe audio and detect keywords
     pub fn detect(
-        &mut self,
+        &self,
         state: &mut WhisperState,
         params: FullParams,
         samples: &[f32],
     ) -> Result<Option<KeywordDetection>, Box<dyn std::error::Error>> {
+        let transcript = self.transcribe(state, params, samples)?;
+        Ok(self.match_keywords(&transcript))
+    }
+
+    /// Run whisper over `samples` and collect the text and its tokens
+    pub fn transcribe(
+        &self,
+        state: &mut WhisperState,
+        params: FullParams,
+        samples: &[f32],
+    ) -> Result<Transcript, Box<dyn std::error::Error>> {
+        Self::transcribe_with(&self.ctx, &self.config, state, params, samples)
+    }
+
+    fn transcribe_with(
+        ctx: &WhisperContext,
+        config: &SpotterConfig,
+        state: &mut WhisperState,
+        params: FullParams,
+        samples: &[f32],
+    ) -> Result<Transcript, Box<dyn std::error::Error>> {
         // Transcribe
         let result = state.full(params, samples)?;
         assert!(result == 0, "stat.full error");
 
         let num_segments = state.full_n_segments();
         let mut transcription = String::new();
+        let mut segments = Vec::new();
+        let mut tokens = Vec::new();
 
         for i in 0..num_segments {
             let segment = state.get_segment(i).unwrap();
-            transcription.push_str(&segment.to_str().unwrap());
+            let text = segment.to_str().unwrap();
+
+            let mut segment_tokens = Vec::new();
+            let mut logprob_sum = 0.0;
+            for j in 0..segment.n_tokens() {
+                if let Some(token) = segment.get_token(j) {
+                    let data = token.token_data();
+                    // Skip special tokens ([_BEG_], timestamps, ...)
+                    if data.id >= ctx.token_eot() {
+                        continue;
+                    }
+                    logprob_sum += data.plog;
+                    segment_tokens.push(TranscribedToken {
+                        text: token
+                            .to_str_lossy()
+                            .map(|text| text.into_owned())
+                            .unwrap_or_default(),
+                        t0: data.t0,
+                        t1: data.t1,
+                        probability: token.token_probability(),
+                    });
+                }
+            }
+
+            let no_speech_prob = segment.no_speech_probability();
+            let avg_logprob = if segment_tokens.is_empty() {
+                0.0
+            } else {
+                logprob_sum / segment_tokens.len() as f32
+            };
+
+            // Whisper makes up text ("Thank you.") on silence and noise
+            if !is_confident(no_speech_prob, avg_logprob, config) {
+                GodotThreadPrint::print(format!(
+                    "Discarded \"{}\" (no speech {:.2}, avg logprob {:.2})",
+                    text.trim(),
+                    no_speech_prob,
+                    avg_logprob
+                ));
+                continue;
+            }
+
+            transcription.push_str(text);
             transcription.push(' ');
+
+            segments.push(TranscribedSegment {
+                text: text.trim().to_owned(),
+                t0: segment.start_timestamp(),
+                t1: segment.end_timestamp(),
+                no_speech_prob,
+                avg_logprob,
+            });
+            tokens.extend(segment_tokens);
         }
 
-        let transcription = transcription.trim().to_lowercase();
+        let confidence = if tokens.is_empty() {
+            0.0
+        } else {
+            tokens.iter().map(|t| t.probability).sum::<f32>() / tokens.len() as f32
+        };
+
+        Ok(Transcript {
+            text: transcription.trim().to_owned(),
+            confidence,
+            segments,
+            tokens,
+        })
+    }
+
+    /// Registered keyword found in the transcript, spelled exactly or, with a
+    /// similarity threshold below 1, the closest one. `confidence` is scaled
+    /// by the similarity.
+    pub fn match_keywords(&self, transcript: &Transcript) -> Option<KeywordDetection> {
+        let transcription = &transcript.text;
 
         if transcription.is_empty() {
             GodotThreadPrint::print(format!("is_empty"));
-            return Ok(None);
+            return None;
         }
 
         GodotThreadPrint::print(format!("📝 Transcribed: \"{}\"", transcription));
 
-        // Check for keywords
-        for keyword in &self.keywords {
-            if transcription.contains(&keyword.to_lowercase()) {
-                return Ok(Some(KeywordDetection {
-                    keyword: keyword.clone(),
-                    transcription: transcription.clone(),
-                    confidence: 0.9, // Whisper doesn't provide per-word confidence easily
-                    timestamp: std::time::SystemTime::now(),
-                }));
+        // Whisper likes to end utterances with punctuation ("Fireball."), so match
+        // against a normalized copy and keep the original for display
+        let normalize = |text: &str| {
+            if self.config.strip_punctuation {
+                normalize_text(text)
+            } else {
+                text.to_lowercase()
+            }
+        };
+        let haystack = normalize(transcription);
+
+        // Exact matches win in registration order; otherwise the closest
+        // keyword above the similarity threshold
+        let keywords = self.keywords();
+        let mut best: Option<(&String, String, f32)> = None;
+        for keyword in &keywords {
+            let needle = normalize(keyword);
+            if needle.is_empty() {
+                continue;
+            }
+            if haystack.contains(&needle) {
+                best = Some((keyword, needle, 1.0));
+                break;
+            }
+            if self.config.keyword_similarity >= 1.0 {
+                continue;
+            }
+            if let Some((window, score)) = fuzzy_find(&haystack, &needle) {
+                let better = best.as_ref().is_none_or(|(_, _, best)| score > *best);
+                if score >= self.config.keyword_similarity && better {
+                    best = Some((keyword, window, score));
+                }
             }
         }
 
-        Ok(None)
+        let (keyword, matched, score) = best?;
+        let words = matched_tokens(&transcript.tokens, &matched, normalize);
+
+        // Judge the keyword by its own tokens rather than the whole utterance
+        let confidence = if words.is_empty() {
+            transcript.confidence
+        } else {
+            words.iter().map(|token| token.probability).sum::<f32>() / words.len() as f32
+        };
+
+        Some(KeywordDetection {
+            keyword: keyword.clone(),
+            transcription: transcription.clone(),
+            confidence: confidence * score,
+            timestamp: std::time::SystemTime::now(),
+            segments: transcript.segments.clone(),
+            words: words
+                .iter()
+                .map(|token| (token.text.trim().to_owned(), token.t0, token.t1))
+                .collect(),
+        })
+    }
+
+    /// Language `state.full` detected for the audio it last transcribed, as
+    /// its whisper id and code
+    fn detected_language(state: &WhisperState) -> Option<(i32, &'static str)> {
+        let id = state.full_lang_id_from_state().ok()?;
+        Some((id, whisper_rs::get_lang_str(id)?))
+    }
+
+    /// Probability of language `id` in the audio last transcribed with
+    /// `state`. `full` keeps only the id it picked, so this runs the detector
+    /// over the mel spectrogram it left in the state.
+    fn language_probability(&self, state: &mut WhisperState, id: i32) -> Option<f32> {
+        let (_, probabilities) = state
+            .lang_detect(0, self.config.threads.max(1) as usize)
+            .ok()?;
+        probabilities.get(usize::try_from(id).ok()?).copied()
     }
 
     fn is_silence(samples: &[f32], threshold: f32) -> bool {
@@ -86,106 +1000,981 @@ impl WhisperKeywordSpotter {
             return true;
         }
 
-        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
-        rms < threshold
+        Self::rms(samples) < threshold
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt()
+    }
+
+    /// Decoding parameters for real-time spotting
+    fn full_params<'a>(config: &'a SpotterConfig, keywords: &[String]) -> FullParams<'a, 'a> {
+        let mut params = whisper_rs::FullParams::new(config.sampling.strategy());
+
+        // Configure for real-time
+        params.set_language(Some(&config.language));
+        params.set_translate(config.translate);
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        params.set_token_timestamps(true);
+        params.set_n_threads(config.threads);
+        params.set_temperature(config.temperature);
+        params.set_temperature_inc(if config.temperature_fallback {
+            TEMPERATURE_INC
+        } else {
+            0.0
+        });
+        if config.max_segment_len > 0 {
+            params.set_max_len(config.max_segment_len);
+            params.set_split_on_word(true);
+        }
+
+        if let Some(prompt) = initial_prompt(config, keywords) {
+            params.set_initial_prompt(&prompt);
+        }
+
+        if config.constrain_to_keywords && !keywords.is_empty() {
+            params.set_no_context(true);
+            params.set_suppress_blank(true);
+        }
+
+        params
+    }
+
+    /// Decoding parameters for this spotter's config and keywords
+    fn params(&self) -> FullParams<'_, '_> {
+        Self::full_params(&self.config, &self.keywords())
     }
 
     pub fn start(
-        model_path: String,
+        model: ModelSource,
         rx: Receiver<Vec<f32>>,
         running: Arc<AtomicBool>,
-        keywords: Vec<String>,
-        matches: Arc<Mutex<Option<String>>>,
+        keywords: Arc<Mutex<Vec<String>>>,
+        config: SpotterConfig,
+        shared: SpotterShared,
     ) -> JoinHandle<()> {
         return std::thread::spawn(move || {
-            GodotThreadPrint::print("Initializing Whisper".to_owned());
-            let mut spotter = match WhisperKeywordSpotter::new(&model_path, keywords) {
-                Ok(s) => s,
-                Err(e) => {
-                    GodotThreadPrint::print(format!(
-                        "Failed to initialize Whisper: {}, model: {}",
-                        e, model_path
-                    ));
-                    return;
-                }
-            };
+            let mut attempt = 0;
+            loop {
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    Self::run(
+                        &model,
+                        &rx,
+                        &running,
+                        keywords.clone(),
+                        config.clone(),
+                        shared.clone(),
+                        attempt,
+                    )
+                }));
 
-            // Create parameters for transcription
-            let mut params =
-                whisper_rs::FullParams::new(whisper_rs::SamplingStrategy::Greedy { best_of: 1 });
-
-            // Configure for real-time, English only
-            params.set_language(Some("en"));
-            params.set_print_special(false);
-            params.set_print_progress(false);
-            params.set_print_realtime(false);
-            params.set_print_timestamps(false);
-            params.set_n_threads(2);
-
-            // Create a mutable state
-            let mut state = spotter.ctx.create_state().unwrap();
-
-            let mut buffer: Vec<f32> = Vec::new();
-
-            let silence_threshold = 0.015;
-            let silence_hold = 2048 * 2;
-            let silence_check_tail = 512; // NEW
-            let mut silence_samples = 0;
-
-            while !running.load(Ordering::Relaxed) {
-                match rx.recv() {
-                    Ok(bytes) => {
-                        buffer.extend(bytes.clone());
-
-                        let check = if bytes.len() > silence_check_tail {
-                            &bytes[bytes.len() - silence_check_tail..]
-                        } else {
-                            &bytes
-                        };
-
-                        let silent = Self::is_silence(check, silence_threshold);
-
-                        if silent {
-                            silence_samples += bytes.len();
-                        } else {
-                            silence_samples = 0;
-                        }
-
-                        if silence_samples >= silence_hold && !buffer.is_empty() {
-                            silence_samples = 0;
-                        } else {
-                            if buffer.len() < (16000 * 3) as usize {
-                                continue;
-                            }
-                            // continue;
-                        }
-
-                        let silent = Self::is_silence(&buffer[..], silence_threshold);
-
-                        if silent {
-                            silence_samples = 0;
-                            buffer.clear();
-                            continue;
-                        }
-
-                        match spotter.detect(&mut state, params.clone(), &buffer) {
-                            Ok(Some(detection)) => {
-                                GodotThreadPrint::print(format!(
-                                    "🔊 Keyword detected: '{}' in \"{}\"",
-                                    detection.keyword, detection.transcription
-                                ));
-                                *matches.lock().unwrap() = Some(detection.keyword.clone());
-                            }
-                            _ => {}
-                        }
-
-                        buffer.clear();
-                    }
-                    Err(_err) => {
+                let reason = match outcome {
+                    Ok(Ok(())) => break,
+                    // A model that loaded before failing to reload is not coming back
+                    Ok(Err(err)) if attempt > 0 => err.to_string(),
+                    Ok(Err(err)) => {
+                        shared.unload();
+                        let _ = shared
+                            .events
+                            .send(SpotterEvent::LoadFailed(err.to_string()));
                         break;
                     }
+                    Err(err) => panic_message(&*err),
+                };
+
+                GodotThreadPrint::print(format!("Whisper thread failed: {}", reason));
+                shared.set_status(WhisperStatus::Error);
+                shared.unload();
+
+                if !config.auto_recover || attempt >= config.max_recoveries {
+                    let _ = shared.events.send(SpotterEvent::Failed(reason));
+                    break;
+                }
+                attempt += 1;
+            }
+        });
+    }
+
+    /// Load the model and transcribe until stopped. `attempt` counts the
+    /// recoveries so far; a successful reload is reported as `Recovered`.
+    fn run(
+        model: &ModelSource,
+        rx: &Receiver<Vec<f32>>,
+        running: &AtomicBool,
+        keywords: Arc<Mutex<Vec<String>>>,
+        config: SpotterConfig,
+        shared: SpotterShared,
+        attempt: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        GodotThreadPrint::print("Initializing Whisper".to_owned());
+        shared.set_status(WhisperStatus::LoadingModel);
+        // Load on a helper thread while this one keeps the capture queue
+        // drained, so what the player says during the load isn't dropped
+        let (load, held) = std::thread::scope(|scope| {
+            let loader = scope.spawn(|| {
+                WhisperKeywordSpotter::new(model, keywords, config)
+                    .and_then(|spotter| {
+                        let state = spotter.ctx.create_state()?;
+                        Ok((spotter, state))
+                    })
+                    .map_err(|err| err.to_string())
+            });
+            let held = hold_audio(rx, running, &shared, || loader.is_finished());
+            let load = loader
+                .join()
+                .unwrap_or_else(|err| Err(panic_message(&*err)));
+            (load, held)
+        });
+        let (spotter, state) = match load {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                let message = format!("Failed to load whisper model {}: {}", model, e);
+                GodotThreadPrint::print(message.clone());
+                shared.set_status(WhisperStatus::Error);
+                return Err(message.into());
+            }
+        };
+
+        *shared.context.lock().unwrap() = Some(spotter.ctx.clone());
+
+        shared.set_status(WhisperStatus::Ready);
+        shared.initialized.store(true, Ordering::Relaxed);
+        let _ = shared.events.send(SpotterEvent::Ready);
+        if attempt > 0 {
+            let _ = shared.events.send(SpotterEvent::Recovered(attempt));
+        }
+        let mut session = SpotterSession::new(spotter, state, shared.clone());
+        // Catch up on the held audio in capture-sized pieces, so utterances
+        // in it are cut where they would have been live
+        let held = Vec::from(held);
+        let mut pieces = held.chunks(PRELOAD_PIECE).peekable();
+        while let Some(piece) = pieces.next() {
+            AudioSink::push(&mut session, piece.to_vec(), pieces.peek().is_some());
+        }
+        listen(rx, running, &shared, &mut session);
+
+        session.shared.initialized.store(false, Ordering::Relaxed);
+        session.shared.set_status(WhisperStatus::Stopped);
+        Ok(())
+    }
+}
+
+/// Consumer of the audio received by `listen`
+trait AudioSink {
+    /// `behind` is set when more audio is already queued behind `samples`
+    fn push(&mut self, samples: Vec<f32>, behind: bool);
+    /// Finalize whatever is buffered
+    fn flush(&mut self);
+}
+
+/// Keep the most recent `PRELOAD_HOLD` of the audio captured until `loaded`,
+/// dropping it while capture is paused like `listen` does
+fn hold_audio(
+    rx: &Receiver<Vec<f32>>,
+    running: &AtomicBool,
+    shared: &SpotterShared,
+    loaded: impl Fn() -> bool,
+) -> VecDeque<f32> {
+    let mut held = VecDeque::new();
+    while running.load(Ordering::Relaxed) && !loaded() {
+        match rx.recv_timeout(RECV_TIMEOUT) {
+            Ok(_)
+                if shared.paused.load(Ordering::Relaxed)
+                    || !shared.listening.load(Ordering::Relaxed) => {}
+            Ok(samples) => {
+                held.extend(samples);
+                let excess = held.len().saturating_sub(PRELOAD_HOLD);
+                held.drain(..excess);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    held
+}
+
+/// Hand captured audio to `sink` for as long as `running` is set, until the
+/// node asks to stop or the capture side hangs up
+fn listen(
+    rx: &Receiver<Vec<f32>>,
+    running: &AtomicBool,
+    shared: &SpotterShared,
+    sink: &mut impl AudioSink,
+) {
+    while running.load(Ordering::Relaxed) {
+        match rx.recv_timeout(RECV_TIMEOUT) {
+            // Audio still in flight when capture was paused is dropped
+            Ok(_)
+                if shared.paused.load(Ordering::Relaxed)
+                    || !shared.listening.load(Ordering::Relaxed) => {}
+            Ok(bytes) => {
+                // While more audio is already queued, whisper is behind the
+                // microphone: catch up before spending time on interim results
+                let mut next = Some(bytes);
+                while let Some(bytes) = next.take() {
+                    next = rx.try_recv().ok();
+                    sink.push(bytes, next.is_some());
                 }
             }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if shared.flush.swap(false, Ordering::Relaxed) {
+            sink.flush();
+        }
+
+        if shared.stop.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+}
+
+/// Readable message out of a panic payload
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+/// Load the whisper model from `model`, on the GPU backend when `use_gpu` is set
+pub fn load_context(
+    model: &ModelSource,
+    use_gpu: bool,
+) -> Result<WhisperContext, Box<dyn std::error::Error>> {
+    let mut params = WhisperContextParameters::default();
+    params.use_gpu(use_gpu);
+    let ctx = match model {
+        ModelSource::Path(path) => WhisperContext::new_with_params(path, params)?,
+        ModelSource::Buffer(data) => WhisperContext::new_from_buffer_with_params(data, params)?,
+    };
+    Ok(ctx)
+}
+
+/// Samples in `cs` centiseconds of 16kHz audio
+fn cs_to_samples(cs: i64) -> usize {
+    cs.max(0) as usize * SAMPLES_PER_SECOND / 100
+}
+
+/// Whether `detection` is the keyword `carried` says was already reported
+/// from the head of the buffer carried over from the previous utterance
+fn repeats_carried(detection: &KeywordDetection, carried: &Option<(String, usize)>) -> bool {
+    let Some((keyword, head)) = carried else {
+        return false;
+    };
+    let Some((_, t0, _)) = detection.words.first() else {
+        return false;
+    };
+    detection.keyword == *keyword && cs_to_samples(*t0) < *head
+}
+
+/// Whether a segment with these scores is kept for keyword matching, see
+/// `SpotterConfig::no_speech_threshold` and `SpotterConfig::logprob_threshold`
+pub fn is_confident(no_speech_prob: f32, avg_logprob: f32, config: &SpotterConfig) -> bool {
+    no_speech_prob <= config.no_speech_threshold && avg_logprob >= config.logprob_threshold
+}
+
+/// One-off transcription of mono audio on the calling thread.
+/// `sample_rate` is the rate of `samples`; it is resampled to 16kHz when needed.
+pub fn transcribe_pcm(
+    ctx: &WhisperContext,
+    config: &SpotterConfig,
+    samples: &[f32],
+    sample_rate: u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if sample_rate == 0 {
+        return Err("Invalid sample rate".into());
+    }
+
+    let resampled;
+    let samples = if sample_rate != WHISPER_SAMPLE_RATE {
+        GodotThreadPrint::print(format!(
+            "Resampling {} samples from {} Hz to {} Hz for whisper",
+            samples.len(),
+            sample_rate,
+            WHISPER_SAMPLE_RATE
+        ));
+        resampled = Microphone::rubato_resample_mono(samples, sample_rate, WHISPER_SAMPLE_RATE);
+        &resampled[..]
+    } else {
+        samples
+    };
+
+    let mut state = ctx.create_state()?;
+    let transcript = WhisperKeywordSpotter::transcribe_with(
+        ctx,
+        config,
+        &mut state,
+        WhisperKeywordSpotter::full_params(config, &[]),
+        samples,
+    )?;
+    Ok(transcript.text)
+}
+
+/// Utterance segmentation of a running spotter thread
+struct SpotterSession {
+    spotter: WhisperKeywordSpotter,
+    state: WhisperState,
+    shared: SpotterShared,
+    buffer: Vec<f32>,
+    silence_samples: usize,
+    /// Keyword reported early from an interim transcription of the current utterance
+    provisional: Option<String>,
+    interim_len: usize,
+    noise_floor: NoiseFloor,
+    voice: VoiceDetector,
+    /// More captured audio is waiting in the queue
+    behind: bool,
+    stabilizer: Stabilizer,
+    /// Samples carried over into the next utterance after a cut
+    overlap: usize,
+    /// Keyword already reported from the audio carried over into the
+    /// buffer, and the length of that head in samples
+    carried: Option<(String, usize)>,
+    /// Language last reported in "auto" mode
+    language: Option<&'static str>,
+}
+
+impl AudioSink for SpotterSession {
+    fn push(&mut self, samples: Vec<f32>, behind: bool) {
+        self.behind = behind;
+        SpotterSession::push(self, samples);
+    }
+
+    fn flush(&mut self) {
+        SpotterSession::flush(self);
+    }
+}
+
+impl SpotterSession {
+    fn new(spotter: WhisperKeywordSpotter, state: WhisperState, shared: SpotterShared) -> Self {
+        let hangover = (spotter.config.vad_hangover.max(0.0) * WHISPER_SAMPLE_RATE as f32) as usize;
+        let overlap =
+            (spotter.config.segment_overlap.max(0.0) * WHISPER_SAMPLE_RATE as f32) as usize;
+        Self {
+            spotter,
+            state,
+            shared,
+            buffer: Vec::new(),
+            silence_samples: 0,
+            provisional: None,
+            interim_len: 0,
+            noise_floor: NoiseFloor::new(),
+            voice: VoiceDetector::new(hangover),
+            behind: false,
+            stabilizer: Stabilizer::default(),
+            overlap,
+            carried: None,
+            language: None,
+        }
+    }
+
+    fn silence_threshold(&self) -> f32 {
+        let config = &self.spotter.config;
+        if config.adaptive_vad {
+            self.noise_floor.threshold(config.adaptive_vad_margin_db)
+        } else {
+            self.shared.silence().threshold
+        }
+    }
+
+    /// Buffer captured audio, transcribing once the utterance ends or gets too long
+    fn push(&mut self, bytes: Vec<f32>) {
+        self.remember(&bytes);
+
+        if !self.spotter.config.vad_enabled {
+            self.push_window(&bytes);
+            return;
+        }
+
+        self.buffer.extend_from_slice(&bytes);
+
+        if !bytes.is_empty() {
+            self.noise_floor.update(WhisperKeywordSpotter::rms(&bytes));
+        }
+        let threshold = self.silence_threshold();
+
+        if self.spotter.config.segmentation == Segmentation::Vad {
+            self.push_speech(&bytes, threshold);
+            return;
+        }
+
+        let check = if bytes.len() > SILENCE_CHECK_TAIL {
+            &bytes[bytes.len() - SILENCE_CHECK_TAIL..]
+        } else {
+            &bytes
+        };
+
+        let silent = WhisperKeywordSpotter::is_silence(check, threshold);
+
+        if silent {
+            self.silence_samples += bytes.len();
+        } else {
+            self.silence_samples = 0;
+        }
+
+        let silence = self.shared.silence();
+        if self.silence_samples >= silence.hold && !self.buffer.is_empty() {
+            self.silence_samples = 0;
+            self.finalize(false);
+        } else if self.buffer.len() < silence.min_utterance.min(silence.max_utterance) {
+            self.interim();
+        } else {
+            // Still talking at the length limit
+            self.finalize(true);
+        }
+    }
+
+    /// VAD segmentation: an utterance runs from speech onset until the voice
+    /// detector's hangover expires, so words are never cut in the middle
+    fn push_speech(&mut self, bytes: &[f32], threshold: f32) {
+        let ended = self.voice.push(bytes, threshold);
+
+        if ended {
+            self.finalize(false);
+        } else if self.buffer.len() >= self.shared.silence().max_utterance {
+            self.finalize(true);
+        } else if self.voice.is_speaking() {
+            self.interim();
+        } else {
+            // Nothing said yet, only keep enough to catch the onset
+            let excess = self.buffer.len().saturating_sub(VAD_PRE_ROLL);
+            self.buffer.drain(..excess);
+        }
+    }
+
+    /// Keep a rolling window of recent audio, independent of segmentation,
+    /// and the PCM recording when it is on
+    fn remember(&mut self, bytes: &[f32]) {
+        let capacity = (self.spotter.config.history_seconds * WHISPER_SAMPLE_RATE as f32) as usize;
+        if let Ok(mut history) = self.shared.history.lock() {
+            history.extend(bytes.iter().copied());
+            let excess = history.len().saturating_sub(capacity);
+            history.drain(..excess);
+        }
+        if let Ok(mut recording) = self.shared.pcm_recording.lock() {
+            recording.push(bytes);
+        }
+    }
+
+    /// Finalize whatever is buffered, e.g. when capture is paused mid-utterance
+    fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            self.finalize(false);
+        }
+    }
+
+    /// Transcribe the utterance so far and report a keyword provisionally
+    fn interim(&mut self) {
+        if !self.spotter.config.provisional_casting
+            || self.provisional.is_some()
+            || self.buffer.len() < self.interim_len + PROVISIONAL_STEP
+        {
+            return;
+        }
+
+        if self.behind {
+            self.shared
+                .skipped_detections
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.interim_len = self.buffer.len();
+        self.shared.set_status(WhisperStatus::Transcribing);
+        let detection = self
+            .spotter
+            .detect(&mut self.state, self.spotter.params(), &self.buffer);
+        self.shared.set_status(WhisperStatus::Ready);
+
+        if let Ok(Some(detection)) = detection {
+            if repeats_carried(&detection, &self.carried) {
+                return;
+            }
+            if detection.confidence >= self.spotter.config.provisional_threshold {
+                let _ = self
+                    .shared
+                    .events
+                    .send(SpotterEvent::Provisional(detection.keyword.clone()));
+                self.provisional = Some(detection.keyword);
+            }
+        }
+    }
+
+    /// Transcribe the buffered utterance and start a new one. When it was
+    /// `cut` mid-speech, its last `segment_overlap` seconds start the next.
+    fn finalize(&mut self, cut: bool) {
+        self.interim_len = 0;
+
+        let silent = WhisperKeywordSpotter::is_silence(&self.buffer[..], self.silence_threshold());
+
+        if silent {
+            if let Some(keyword) = self.provisional.take() {
+                let _ = self.shared.events.send(SpotterEvent::Retracted(keyword));
+            }
+            self.silence_samples = 0;
+            self.buffer.clear();
+            self.voice.reset();
+            self.carried = None;
+            return;
+        }
+
+        let reported = self.transcribe_buffer();
+
+        let keep = if cut { self.overlap } else { 0 }.min(self.buffer.len());
+        let start = self.buffer.len() - keep;
+        // A keyword ending in the carried audio will be heard again there
+        self.carried = reported
+            .filter(|(_, end)| *end > start)
+            .map(|(keyword, _)| (keyword, keep));
+        self.buffer.drain(..start);
+        self.voice.reset();
+    }
+
+    /// Continuous mode: transcribe fixed windows back to back, ignoring silence
+    fn push_window(&mut self, bytes: &[f32]) {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() < CONTINUOUS_WINDOW {
+            return;
+        }
+
+        self.transcribe_buffer();
+
+        // Carry the tail over so words on the window boundary are heard whole
+        let keep = CONTINUOUS_OVERLAP.min(self.buffer.len());
+        self.buffer.drain(..self.buffer.len() - keep);
+    }
+
+    /// Report the language whisper picked for the buffer when it differs from
+    /// the one reported last
+    fn report_language(&mut self) {
+        let Some((id, code)) = WhisperKeywordSpotter::detected_language(&self.state) else {
+            return;
+        };
+        if self.language == Some(code) {
+            return;
+        }
+        let Some(probability) = self.spotter.language_probability(&mut self.state, id) else {
+            return;
+        };
+        self.language = Some(code);
+        let _ = self
+            .shared
+            .events
+            .send(SpotterEvent::LanguageDetected(code.to_owned(), probability));
+    }
+
+    /// Run whisper over the buffer and report keywords. Returns the reported
+    /// keyword and the sample its words end at.
+    fn transcribe_buffer(&mut self) -> Option<(String, usize)> {
+        self.shared.set_status(WhisperStatus::Transcribing);
+        let started = Instant::now();
+        let mut params = self.spotter.params();
+        if self.spotter.config.stream_segments {
+            // Called from inside `full`; it only queues an event, so it never
+            // waits on a lock the node or this thread may be holding
+            let events = self.shared.events.clone();
+            params.set_segment_callback_safe_lossy(move |segment: SegmentCallbackData| {
+                let _ = events.send(SpotterEvent::Segment(
+                    segment.text.trim().to_owned(),
+                    segment.start_timestamp,
+                    segment.end_timestamp,
+                ));
+            });
+        }
+        let transcript = self
+            .spotter
+            .transcribe(&mut self.state, params, &self.buffer);
+        if let Ok(mut stats) = self.shared.stats.lock() {
+            stats.record(started.elapsed(), self.buffer.len());
+        }
+
+        let mut detection = transcript.map(|mut transcript| {
+            if !self.spotter.config.vad_enabled && self.spotter.config.stabilization {
+                transcript.text = self.stabilizer.new_words(&transcript.text);
+            }
+            let text = transcript.text.trim();
+            if !text.is_empty() {
+                let _ = self
+                    .shared
+                    .events
+                    .send(SpotterEvent::Transcribed(text.to_owned()));
+            }
+            let detection = self.spotter.match_keywords(&transcript);
+            *self.shared.tokens.lock().unwrap() = transcript.tokens;
+            *self.shared.segments.lock().unwrap() = transcript.segments;
+            detection
         });
+
+        if self.spotter.config.language == "auto" {
+            self.report_language();
+        }
+        self.shared.set_status(WhisperStatus::Ready);
+
+        if let Ok(Some(repeat)) = &detection {
+            if repeats_carried(repeat, &self.carried) {
+                GodotThreadPrint::print(format!(
+                    "Skipping '{}', already reported before the cut",
+                    repeat.keyword
+                ));
+                detection = Ok(None);
+            }
+        }
+
+        if let Some(keyword) = self.provisional.take() {
+            let confirmed = match &detection {
+                Ok(Some(detection)) => detection.keyword == keyword,
+                _ => false,
+            };
+            if !confirmed {
+                let _ = self.shared.events.send(SpotterEvent::Retracted(keyword));
+            }
+        }
+
+        match detection {
+            Ok(Some(detection)) => {
+                GodotThreadPrint::print(format!(
+                    "🔊 Keyword detected: '{}' in \"{}\"",
+                    detection.keyword, detection.transcription
+                ));
+                let end = detection
+                    .words
+                    .last()
+                    .map_or(self.buffer.len(), |(_, _, t1)| cs_to_samples(*t1));
+                let reported = (detection.keyword.clone(), end);
+                *self.shared.matches.lock().unwrap() = Some(detection);
+                Some(reported)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str, t0: i64, probability: f32) -> TranscribedToken {
+        TranscribedToken {
+            text: text.to_owned(),
+            t0,
+            t1: t0 + 10,
+            probability,
+        }
+    }
+
+    #[test]
+    fn test_matched_tokens_cover_a_split_keyword() {
+        let tokens = [
+            token(" Cast", 0, 0.9),
+            token(" fire", 20, 0.8),
+            token("ball", 30, 0.6),
+            token(".", 40, 0.99),
+            token(" now", 50, 0.9),
+        ];
+
+        let words = matched_tokens(&tokens, "fireball", normalize_text);
+        let texts: Vec<&str> = words.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, [" fire", "ball"]);
+        assert_eq!((words[0].t0, words[1].t1), (20, 40));
+
+        let words = matched_tokens(&tokens, "cast fireball now", normalize_text);
+        assert_eq!(words.len(), 4);
+
+        assert!(matched_tokens(&tokens, "ice", normalize_text).is_empty());
+    }
+
+    #[test]
+    fn test_stabilizer_drops_words_repeated_from_overlap() {
+        let mut stabilizer = Stabilizer::default();
+
+        assert_eq!(stabilizer.new_words("I cast a fire"), "I cast a fire");
+        assert_eq!(stabilizer.new_words("a Fire, ball now"), "ball now");
+        assert_eq!(stabilizer.new_words("ball now."), "");
+    }
+
+    #[test]
+    fn test_stabilizer_keeps_unrelated_windows_whole() {
+        let mut stabilizer = Stabilizer::default();
+
+        stabilizer.new_words("open the door");
+        assert_eq!(stabilizer.new_words("fireball"), "fireball");
+
+        stabilizer.reset();
+        assert_eq!(stabilizer.new_words("fireball"), "fireball");
+    }
+
+    #[test]
+    fn test_levenshtein_counts_edits() {
+        assert_eq!(levenshtein("fireball", "fireball"), 0);
+        assert_eq!(levenshtein("firebal", "fireball"), 1);
+        assert_eq!(levenshtein("fyreball", "fireball"), 1);
+        assert_eq!(levenshtein("", "ice"), 3);
+    }
+
+    #[test]
+    fn test_fuzzy_find_matches_split_and_misheard_keywords() {
+        let (window, score) = fuzzy_find("i cast fire ball now", "fireball").unwrap();
+        assert_eq!(window, "fire ball");
+        assert_eq!(score, 1.0);
+
+        let (window, score) = fuzzy_find("cast fire bowl", "fireball").unwrap();
+        assert_eq!(window, "fire bowl");
+        assert!((score - 0.75).abs() < 1e-6);
+
+        let (_, score) = fuzzy_find("open the door", "fireball").unwrap();
+        assert!(score < 0.5);
+
+        assert!(fuzzy_find("", "fireball").is_none());
+    }
+
+    #[derive(Default)]
+    struct CountingSink {
+        samples: usize,
+    }
+
+    impl AudioSink for CountingSink {
+        fn push(&mut self, samples: Vec<f32>, _behind: bool) {
+            self.samples += samples.len();
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    #[test]
+    fn test_listen_runs_until_the_running_flag_is_cleared() {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<f32>>(4);
+        let running = Arc::new(AtomicBool::new(true));
+        let (shared, _events) = SpotterShared::new();
+
+        let thread = {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let mut sink = CountingSink::default();
+                listen(&rx, &running, &shared, &mut sink);
+                sink.samples
+            })
+        };
+
+        tx.send(vec![0.0; 160]).unwrap();
+        std::thread::sleep(RECV_TIMEOUT * 2);
+        assert!(!thread.is_finished(), "stopped while running was set");
+
+        running.store(false, Ordering::Relaxed);
+        let deadline = Instant::now() + RECV_TIMEOUT * 5;
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(
+            thread.is_finished(),
+            "still running after the flag was cleared"
+        );
+        assert_eq!(thread.join().unwrap(), 160);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_a_model_that_fails_to_load_leaves_the_spotter_uninitialized() {
+        let (_tx, rx) = std::sync::mpsc::sync_channel::<Vec<f32>>(4);
+        let (shared, events) = SpotterShared::new();
+        // As left behind by an earlier model
+        shared.initialized.store(true, Ordering::Relaxed);
+
+        let thread = WhisperKeywordSpotter::start(
+            ModelSource::Path("/nonexistent/ggml-missing.bin".to_string()),
+            rx,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(Mutex::new(Vec::new())),
+            SpotterConfig::default(),
+            shared.clone(),
+        );
+        thread.join().unwrap();
+
+        assert!(matches!(events.try_recv(), Ok(SpotterEvent::LoadFailed(_))));
+        assert!(!shared.initialized.load(Ordering::Relaxed));
+        assert!(shared.context.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_listen_drops_audio_while_push_to_talk_is_released() {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<f32>>(4);
+        let running = AtomicBool::new(true);
+        let (shared, _events) = SpotterShared::new();
+        let listening = shared.listening.clone();
+        listening.store(false, Ordering::Relaxed);
+
+        let thread = std::thread::spawn(move || {
+            let mut sink = CountingSink::default();
+            listen(&rx, &running, &shared, &mut sink);
+            sink.samples
+        });
+
+        tx.send(vec![0.0; 160]).unwrap();
+        std::thread::sleep(RECV_TIMEOUT * 2);
+        listening.store(true, Ordering::Relaxed);
+        tx.send(vec![0.0; 320]).unwrap();
+        // Hanging up ends the thread once it has taken everything queued
+        drop(tx);
+
+        assert_eq!(thread.join().unwrap(), 320);
+    }
+
+    #[test]
+    fn test_audio_held_during_the_model_load_is_bounded() {
+        let (tx, rx) = std::sync::mpsc::channel::<Vec<f32>>();
+        let running = AtomicBool::new(true);
+        let (shared, _events) = SpotterShared::new();
+
+        for second in 0..12 {
+            tx.send(vec![second as f32; SAMPLES_PER_SECOND]).unwrap();
+        }
+        // The loader never finishes here; hanging up ends the wait
+        drop(tx);
+        let held = hold_audio(&rx, &running, &shared, || false);
+
+        // The last 10 seconds, oldest first
+        assert_eq!(held.len(), PRELOAD_HOLD);
+        assert_eq!(held.front(), Some(&2.0));
+        assert_eq!(held.back(), Some(&11.0));
+    }
+
+    fn tone(frames: usize, amplitude: f32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (i as f32 * 2.0 * std::f32::consts::PI * 200.0 / 16000.0).sin() * amplitude)
+            .collect()
+    }
+
+    #[test]
+    fn test_zero_crossing_rate() {
+        assert_eq!(zero_crossing_rate(&[0.5; 100]), 0.0);
+        let alternating: Vec<f32> = (0..101)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect();
+        assert_eq!(zero_crossing_rate(&alternating), 1.0);
+    }
+
+    #[test]
+    fn test_voice_detector_holds_through_short_pauses() {
+        let mut voice = VoiceDetector::new(16000 * 3 / 10);
+
+        assert!(!voice.push(&tone(16000, 0.3), 0.015));
+        assert!(voice.is_speaking());
+
+        // A 100ms pause between words is within the hangover
+        assert!(!voice.push(&vec![0.0; 1600], 0.015));
+        assert!(voice.is_speaking());
+        assert!(!voice.push(&tone(8000, 0.3), 0.015));
+
+        // Half a second of silence ends the utterance
+        assert!(voice.push(&vec![0.0; 8000], 0.015));
+        assert!(!voice.is_speaking());
+    }
+
+    #[test]
+    fn test_voice_detector_ignores_silence() {
+        let mut voice = VoiceDetector::new(4800);
+        assert!(!voice.push(&vec![0.001; 32000], 0.015));
+        assert!(!voice.is_speaking());
+    }
+
+    #[test]
+    fn test_segmentation_names_round_trip() {
+        for mode in [Segmentation::Fixed, Segmentation::Vad] {
+            assert_eq!(Segmentation::parse(mode.name()), Some(mode));
+        }
+        assert_eq!(Segmentation::parse("VAD"), Some(Segmentation::Vad));
+        assert_eq!(Segmentation::parse("window"), None);
+    }
+
+    #[test]
+    fn test_initial_prompt_combines_text_and_keywords() {
+        let keywords = vec!["fireball".to_owned(), "mana potion".to_owned()];
+        let mut config = SpotterConfig {
+            initial_prompt: " The realm of Valoran. ".to_owned(),
+            ..SpotterConfig::default()
+        };
+
+        assert_eq!(
+            initial_prompt(&config, &keywords).as_deref(),
+            Some("The realm of Valoran. Commands: fireball, mana potion.")
+        );
+
+        config.prompt_keywords = false;
+        assert_eq!(
+            initial_prompt(&config, &keywords).as_deref(),
+            Some("The realm of Valoran.")
+        );
+
+        config.initial_prompt.clear();
+        assert_eq!(initial_prompt(&config, &keywords), None);
+
+        config.prompt_keywords = true;
+        assert_eq!(initial_prompt(&config, &[]), None);
+    }
+
+    #[test]
+    fn test_pcm_recording_is_opt_in_and_capped() {
+        let mut recording = PcmRecording {
+            max_samples: 5,
+            ..PcmRecording::default()
+        };
+
+        recording.push(&[0.1, 0.2]);
+        assert!(recording.samples.is_empty());
+
+        recording.enabled = true;
+        recording.push(&[0.1, 0.2, 0.3]);
+        recording.push(&[0.4, 0.5, 0.6, 0.7]);
+        assert_eq!(recording.samples, vec![0.1, 0.2, 0.3, 0.4, 0.5]);
+
+        recording.push(&[0.8]);
+        assert_eq!(recording.samples.len(), 5);
+    }
+
+    #[test]
+    fn test_unconfident_segments_are_discarded() {
+        let config = SpotterConfig::default();
+
+        assert!(is_confident(0.1, -0.3, &config));
+        // Silence hallucination: whisper itself doubts there was speech
+        assert!(!is_confident(0.9, -0.3, &config));
+        // Garbled guess
+        assert!(!is_confident(0.1, -2.5, &config));
+
+        let lenient = SpotterConfig {
+            no_speech_threshold: 1.0,
+            logprob_threshold: f32::NEG_INFINITY,
+            ..SpotterConfig::default()
+        };
+        assert!(is_confident(0.9, -2.5, &lenient));
+    }
+
+    #[test]
+    fn test_keywords_in_the_carried_overlap_are_reported_once() {
+        let detection = |t0: i64| KeywordDetection {
+            keyword: "fireball".to_owned(),
+            transcription: "fireball".to_owned(),
+            confidence: 1.0,
+            timestamp: std::time::SystemTime::now(),
+            segments: Vec::new(),
+            words: vec![("fireball".to_owned(), t0, t0 + 40)],
+        };
+        // Half a second carried over after "fireball" was reported near the cut
+        let carried = Some(("fireball".to_owned(), SAMPLES_PER_SECOND / 2));
+
+        assert!(repeats_carried(&detection(10), &carried));
+        // Said again after the overlap
+        assert!(!repeats_carried(&detection(80), &carried));
+        assert!(!repeats_carried(&detection(10), &None));
+        let other = Some(("frostbolt".to_owned(), SAMPLES_PER_SECOND / 2));
+        assert!(!repeats_carried(&detection(10), &other));
     }
 }
@@ -1,34 +1,82 @@
 use cpal::traits::DeviceTrait;
-use godot::classes::Node;
+use godot::classes::{Node, ProjectSettings};
 use godot::prelude::*;
-use opus2::{Channels, Decoder};
-use std::collections::HashMap;
+use opus2::{Application, Channels, Decoder, Encoder};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::codec::decode_opus_to_stereo;
+use crate::calibration;
+use crate::codec::{
+    BitrateMode, CodecError, MAX_COMPLEXITY, decode_opus_to_stereo, default_lookahead,
+    encode_stereo_to_opus, framed_packets, new_decoder, parse_signal, signal_name,
+};
+use crate::detection_result::DetectionResult;
 use crate::godot_thread_print::GodotThreadPrint;
-use crate::microphone::Microphone;
+use crate::microphone::{CAPTURE_QUEUE_CAPACITY, Downmix, Microphone};
+use crate::model::{list_models, validate_model, validate_model_bytes};
+use crate::ogg::{RelayRecording, write_ogg_opus_packets};
+use crate::resample::{ResampleQuality, resample};
 use crate::runtime::Runtime;
-use crate::whisper::WhisperKeywordSpotter;
+use crate::spellbook::Spellbook;
+use crate::whisper::{
+    ModelSource, Sampling, Segmentation, SpotterConfig, SpotterEvent, SpotterShared,
+    WHISPER_SAMPLE_RATE, WhisperKeywordSpotter, WhisperStatus, available_threads,
+    is_valid_language, load_context, panic_message, transcribe_pcm,
+};
+
+/// Relay gap (in Opus frames) after which keepalive silence is sent
+const KEEPALIVE_GAP_FRAMES: u32 = 3;
+/// Relay packets emitted through `speak` per frame unless configured otherwise
+const DEFAULT_RELAY_PACKETS_PER_FRAME: usize = 16;
+/// Most silence frames sent to cover a single gap
+const MAX_KEEPALIVE_FRAMES: u32 = 50;
+/// How long `stop_whisper` waits for the whisper thread to exit
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time between two `input_level` signals unless configured otherwise
+const DEFAULT_INPUT_LEVEL_INTERVAL_MS: u32 = 100;
+
+/// Minimum time between two `recognition_overloaded` signals
+const OVERLOAD_REPORT_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(GodotClass)]
 #[class(base=Node)]
 struct Whisper {
     running: Arc<AtomicBool>,
-    keywords: Vec<String>,
-    spellbook: HashMap<String, String>,
+    /// Shared with the whisper thread, which matches against it live
+    keywords: Arc<Mutex<Vec<String>>>,
+    spellbook: Spellbook,
     base: Base<Node>,
     microphone: Microphone,
     whisper_thread: Option<JoinHandle<()>>,
-    matches: Arc<Mutex<Option<String>>>,
+    config: SpotterConfig,
+    shared: SpotterShared,
+    events: Receiver<SpotterEvent>,
     reander: Receiver<Vec<u8>>,
     sender: Option<Sender<Vec<u8>>>,
     decoder: Decoder,
+    /// Rate `decoder` was created for
+    decoder_rate: u32,
+    relay_keepalive: bool,
+    keepalive_encoder: Encoder,
+    last_relay: Option<Instant>,
+    relay_packets_per_frame: usize,
+    last_overload_report: Instant,
+    /// Time between `input_level` signals, 0 when they are off
+    input_level_interval_ms: u32,
+    last_input_level: Instant,
+    reported_dropped_frames: u64,
+    reported_skipped_detections: u64,
+    /// Directory of the last `list_available_models` scan
+    model_dir: Option<PathBuf>,
+    /// Start and length of a running calibration
+    calibration: Option<(Instant, f32)>,
+    /// Relay packets buffered since `start_recording`
+    recording: Option<RelayRecording>,
 }
 
 #[godot_api]
@@ -36,26 +84,42 @@ impl INode for Whisper {
     fn init(base: Base<Node>) -> Self {
         godot_print!("Hello, world!"); // Prints to the Godot console
         let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let (shared, events) = SpotterShared::new();
         Self {
-            running: Runtime::running(),
-            keywords: Vec::new(),
-            spellbook: HashMap::new(),
+            running: Runtime::register(),
+            keywords: Arc::new(Mutex::new(Vec::new())),
+            spellbook: Spellbook::default(),
             base,
             whisper_thread: None,
             microphone: Microphone::new(false).unwrap(),
-            matches: Arc::new(Mutex::new(None)),
+            config: SpotterConfig::default(),
+            shared,
+            events,
             reander: rx,
             sender: Some(tx),
-            decoder: Decoder::new(48000, Channels::Stereo).unwrap(),
+            decoder: new_decoder(48000, 2).unwrap(),
+            decoder_rate: 48000,
+            relay_keepalive: false,
+            keepalive_encoder: Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap(),
+            last_relay: None,
+            relay_packets_per_frame: DEFAULT_RELAY_PACKETS_PER_FRAME,
+            last_overload_report: Instant::now(),
+            input_level_interval_ms: DEFAULT_INPUT_LEVEL_INTERVAL_MS,
+            last_input_level: Instant::now(),
+            reported_dropped_frames: 0,
+            reported_skipped_detections: 0,
+            model_dir: None,
+            calibration: None,
+            recording: None,
         }
     }
 
     fn process(&mut self, _delta: f64) {
-        let magic = if let Ok(mut matches) = self.matches.try_lock() {
+        let detection = if let Ok(mut matches) = self.shared.matches.try_lock() {
             let mut ret = None;
-            if let Some(magic) = matches.take() {
-                if magic != "" {
-                    ret = Some(magic);
+            if let Some(detection) = matches.take() {
+                if detection.keyword != "" {
+                    ret = Some(detection);
                 }
             }
             ret
@@ -63,16 +127,102 @@ impl INode for Whisper {
             None
         };
 
-        match self.reander.recv_timeout(Duration::from_millis(1)) {
-            Ok(audio) => {
-                self.signals().speak().emit(audio);
+        // Drain without blocking the main thread, bounded so a backlog can't stall a frame
+        for _ in 0..self.relay_packets_per_frame {
+            match self.reander.try_recv() {
+                Ok(audio) => {
+                    self.last_relay = Some(Instant::now());
+                    self.record_relay(&audio);
+                    self.signals().speak().emit(audio);
+                }
+                Err(_) => break,
+            }
+        }
+
+        if self.relay_keepalive {
+            self.send_keepalive();
+        }
+
+        if let Some(detection) = detection {
+            if let Some(spell) = self.spellbook.cast(&detection.keyword, detection.timestamp) {
+                let result = DetectionResult::from_detection(&detection, &spell);
+                self.signals().cast().emit(spell);
+                self.signals().detected().emit(&result);
+            }
+        }
+
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                SpotterEvent::Provisional(keyword) => {
+                    if let Some(spell) = self.spellbook.get(&keyword) {
+                        self.signals().cast_provisional().emit(spell);
+                    }
+                }
+                SpotterEvent::Retracted(keyword) => {
+                    if let Some(spell) = self.spellbook.get(&keyword) {
+                        self.signals().cast_retracted().emit(spell);
+                    }
+                }
+                SpotterEvent::Transcribed(text) => {
+                    self.signals().transcribed().emit(text);
+                }
+                SpotterEvent::Segment(text, t0, t1) => {
+                    self.signals().segment_transcribed().emit(text, t0, t1);
+                }
+                SpotterEvent::LanguageDetected(code, probability) => {
+                    self.signals().language_detected().emit(code, probability);
+                }
+                SpotterEvent::Ready => self.signals().whisper_ready().emit(),
+                SpotterEvent::Recovered(attempt) => {
+                    godot_warn!("Whisper recovered from a failure (attempt {})", attempt);
+                    self.signals().whisper_recovered().emit(attempt);
+                }
+                SpotterEvent::Failed(reason) => {
+                    godot_error!("Whisper stopped: {}", reason);
+                    self.signals().whisper_failed().emit(reason.clone());
+                    self.signals()
+                        .whisper_error()
+                        .emit(format!("Whisper stopped: {}", reason));
+                }
+                SpotterEvent::LoadFailed(message) => self.report_error(message),
+            }
+        }
+
+        if let Some((started, seconds)) = self.calibration {
+            if started.elapsed().as_secs_f32() >= seconds {
+                self.calibration = None;
+                self.finish_calibration(seconds);
+            }
+        }
+
+        if self.last_overload_report.elapsed() >= OVERLOAD_REPORT_INTERVAL {
+            self.report_overload();
+        }
+
+        if self.input_level_interval_ms > 0
+            && self.last_input_level.elapsed()
+                >= Duration::from_millis(self.input_level_interval_ms as u64)
+        {
+            self.last_input_level = Instant::now();
+            let rms = self.microphone.input_rms();
+            let peak = self.microphone.take_input_peak();
+            self.signals().input_level().emit(rms, peak);
+        }
+
+        if self.microphone.take_input_lost() {
+            let name = self.get_current_input_device();
+            godot_warn!("Input device '{}' was disconnected", name);
+            self.signals().input_device_lost().emit(&name);
+            if let Err(err) = self.microphone.recover_input() {
+                godot_error!("Can't fall back to the default input device: {}", err);
             }
-            Err(_) => {}
         }
 
-        if let Some(magic) = magic {
-            let spell = self.spellbook[&magic].clone();
-            self.signals().cast().emit(spell);
+        if self.microphone.take_output_lost() {
+            godot_warn!("Monitor output device was disconnected");
+            if let Err(err) = self.microphone.recover_output() {
+                godot_error!("Can't fall back to the default output device: {}", err);
+            }
         }
 
         if let Some(thread) = self.whisper_thread.take() {
@@ -80,13 +230,7 @@ impl INode for Whisper {
                 match &thread.join() {
                     Ok(_) => godot_print!("Thread finished normally."),
                     Err(err) => {
-                        if let Some(msg) = err.downcast_ref::<&str>() {
-                            godot_print!("Thread panicked with message: {}", msg);
-                        } else if let Some(msg) = err.downcast_ref::<String>() {
-                            godot_print!("Thread panicked with message: {}", msg);
-                        } else {
-                            godot_print!("Thread panicked with unknown message type.");
-                        }
+                        godot_print!("Thread panicked with message: {}", panic_message(&**err))
                     }
                 }
             } else {
@@ -98,24 +242,180 @@ impl INode for Whisper {
 
 #[godot_api]
 impl Whisper {
+    /// Start capturing and load the model at `model_path` on the whisper thread.
+    /// Returns false, and emits `whisper_error`, when the model file is unusable
+    /// or capture can't start. A model that fails to load on the thread is
+    /// reported through `whisper_error` from `process`.
     #[func]
-    fn init_whisper(&mut self, model_path: String) {
-        let (tx, rx) = std::sync::mpsc::channel::<Vec<f32>>();
+    fn init_whisper(&mut self, model_path: String) -> bool {
+        self.stop_whisper();
 
-        self.whisper_thread = Some(WhisperKeywordSpotter::start(
-            model_path,
-            rx,
-            self.running.clone(),
-            self.keywords.clone(),
-            self.matches.clone(),
-        ));
+        if let Err(err) = validate_model(Path::new(&model_path)) {
+            self.shared.set_status(WhisperStatus::Error);
+            self.report_error(format!(
+                "Failed to load whisper model {}: {}",
+                model_path, err
+            ));
+            return false;
+        }
+
+        self.start_whisper(ModelSource::Path(model_path))
+    }
+
+    /// Like `init_whisper`, with the ggml model already in memory, e.g. from
+    /// `FileAccess.get_file_as_bytes` on a model packed in the game's .pck
+    #[func]
+    fn init_whisper_from_buffer(&mut self, data: PackedByteArray) -> bool {
+        self.stop_whisper();
+
+        if let Err(err) = validate_model_bytes(data.as_slice()) {
+            self.shared.set_status(WhisperStatus::Error);
+            self.report_error(format!("Failed to load whisper model: {}", err));
+            return false;
+        }
+
+        self.start_whisper(ModelSource::Buffer(Arc::new(data.to_vec())))
+    }
+
+    /// Feed the WAV file at `path` (res://, user:// or absolute; integer or
+    /// float samples) through the pipeline in place of the microphone, e.g.
+    /// for scripted voice lines or tests without audio hardware. With
+    /// `realtime` it plays at its natural pace, otherwise as fast as whisper
+    /// transcribes it. Needs `init_whisper` first; the microphone stays off
+    /// until the next `init_whisper`.
+    #[func]
+    fn start_from_wav(&mut self, path: GString, realtime: bool) -> bool {
+        if self.whisper_thread.is_none() {
+            godot_error!("start_from_wav: call init_whisper first");
+            return false;
+        }
+
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        match self.microphone.start_wav(Path::new(&path), realtime) {
+            Ok(()) => true,
+            Err(err) => {
+                self.report_error(format!("Failed to play {}: {}", path, err));
+                false
+            }
+        }
+    }
+
+    /// Stop capturing and shut the whisper thread down, e.g. when voice
+    /// features are turned off in a menu. `init_whisper` starts them again.
+    /// Waits up to two seconds for a transcription in progress to finish.
+    #[func]
+    fn stop_whisper(&mut self) {
+        self.microphone.stop();
+        self.calibration = None;
+
+        let Some(thread) = self.whisper_thread.take() else {
+            return;
+        };
+        self.shared.stop.store(true, Ordering::Relaxed);
+        Runtime::stop(&self.running);
+
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        if thread.is_finished() {
+            if let Err(err) = thread.join() {
+                godot_error!("Whisper thread panicked: {}", panic_message(&*err));
+            }
+        } else {
+            // Dropping the handle detaches the thread; it exits after its current run
+            godot_warn!("stop_whisper: the whisper thread did not stop in time");
+        }
+        self.shared.set_status(WhisperStatus::Stopped);
+    }
+
+    /// Model files in `dir` (res://, user:// or absolute) as dictionaries with
+    /// `name`, `path`, `size` in bytes and `valid`, for a model picker
+    #[func]
+    fn list_available_models(&mut self, dir: GString) -> Array<Dictionary> {
+        let mut models: Array<Dictionary> = Array::new();
+        let dir = PathBuf::from(
+            ProjectSettings::singleton()
+                .globalize_path(&dir)
+                .to_string(),
+        );
+
+        match list_models(&dir) {
+            Ok(found) => {
+                for model in found {
+                    let mut dict = Dictionary::new();
+                    dict.set("name", model.name.as_str());
+                    dict.set("path", model.path.to_string_lossy().as_ref());
+                    dict.set("size", model.size as i64);
+                    dict.set("valid", model.valid);
+                    models.push(&dict);
+                }
+            }
+            Err(err) => godot_error!("Can't list models in {}: {:?}", dir.display(), err),
+        }
+
+        self.model_dir = Some(dir);
+        models
+    }
+
+    /// Record `seconds` of microphone audio (ask the user to speak) and report
+    /// levels through `calibration_result`. Needs `init_whisper` to have run.
+    #[func]
+    fn start_calibration(&mut self, seconds: f32) -> bool {
+        if !matches!(
+            self.shared.status(),
+            WhisperStatus::Ready | WhisperStatus::Transcribing
+        ) {
+            godot_error!("start_calibration: whisper is not listening");
+            return false;
+        }
+
+        let seconds = seconds.clamp(0.5, self.config.history_seconds.max(0.5));
+        self.calibration = Some((Instant::now(), seconds));
+        true
+    }
+
+    /// Whether `path` is a readable whisper ggml model
+    #[func]
+    fn validate_model(&self, path: GString) -> bool {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        match validate_model(Path::new(&path)) {
+            Ok(_) => true,
+            Err(err) => {
+                godot_warn!("{}", err);
+                false
+            }
+        }
+    }
 
-        // TODO Handle NONE sender
+    /// Switch to model `name` from the last `list_available_models` directory,
+    /// restarting recognition with it. Returns false if the model is unusable.
+    #[func]
+    fn select_model(&mut self, name: GString) -> bool {
+        let Some(dir) = &self.model_dir else {
+            godot_error!("select_model: call list_available_models first");
+            return false;
+        };
 
-        match self.microphone.start(tx, self.sender.take().unwrap()) {
-            Ok(_) => GodotThreadPrint::print("started".to_owned()),
-            Err(err) => godot_error!("{:?}", err),
+        let path = dir.join(name.to_string());
+        if let Err(err) = validate_model(&path) {
+            godot_error!("select_model: {}", err);
+            return false;
         }
+
+        self.init_whisper(path.to_string_lossy().into_owned())
+    }
+
+    /// Where debug builds append their log, see `GodotThreadPrint`. Call before
+    /// `init_whisper` to capture the startup messages.
+    #[func]
+    fn set_debug_log_path(&mut self, path: GString) {
+        GodotThreadPrint::set_debug_log_path(path);
     }
 
     #[func]
@@ -123,10 +423,414 @@ impl Whisper {
         self.microphone.get_sample_rate()
     }
 
+    /// Spotter thread state: Stopped, Initializing, LoadingModel, Ready,
+    /// Transcribing or Error
+    #[func]
+    fn get_status(&self) -> GString {
+        GString::from(self.shared.status().as_str())
+    }
+
+    /// The model is loaded and the spotter thread is taking audio. Cheap
+    /// enough to poll every frame.
+    #[func]
+    fn is_initialized(&self) -> bool {
+        self.shared.initialized.load(Ordering::Relaxed)
+    }
+
+    /// The microphone stream is running: false before `init_whisper`, after
+    /// `stop_whisper`, while a WAV file plays in its place or once the input
+    /// device went away
+    #[func]
+    fn is_capturing(&self) -> bool {
+        self.microphone.is_capturing()
+    }
+
+    /// Average transcription wall-time per second of audio over recent detections.
+    /// Below 1.0 means the model keeps up with the microphone on this machine.
+    #[func]
+    fn get_realtime_factor(&self) -> f32 {
+        match self.shared.stats.lock() {
+            Ok(stats) => stats.realtime_factor(),
+            Err(_) => 0.0,
+        }
+    }
+
+    /// Tokens of the last transcription as dictionaries with `text`, `t0`, `t1`
+    /// (centiseconds from the start of the utterance) and `probability`
+    #[func]
+    fn get_last_tokens(&self) -> Array<Dictionary> {
+        let mut tokens: Array<Dictionary> = Array::new();
+
+        if let Ok(last_tokens) = self.shared.tokens.lock() {
+            for token in last_tokens.iter() {
+                let mut dict = Dictionary::new();
+                dict.set("text", token.text.clone());
+                dict.set("t0", token.t0);
+                dict.set("t1", token.t1);
+                dict.set("probability", token.probability);
+                tokens.push(&dict);
+            }
+        }
+
+        tokens
+    }
+
+    /// Parameters of the loaded model: `is_multilingual` (false for `.en`
+    /// models, which only understand English), `type` ("tiny", "base", ...),
+    /// `n_vocab`, `n_text_ctx`, `n_audio_ctx`, `n_mels` and `sample_rate`.
+    /// Empty until `init_whisper` or `load_model` loaded one.
+    #[func]
+    fn get_model_info(&self) -> Dictionary {
+        let mut info = Dictionary::new();
+        let Some(ctx) = self.shared.context.lock().unwrap().clone() else {
+            return info;
+        };
+
+        info.set("is_multilingual", ctx.is_multilingual());
+        info.set("type", ctx.model_type_readable().unwrap_or_default());
+        info.set("n_vocab", ctx.n_vocab());
+        info.set("n_text_ctx", ctx.n_text_ctx());
+        info.set("n_audio_ctx", ctx.n_audio_ctx());
+        info.set("n_mels", ctx.model_n_mels());
+        info.set("sample_rate", WHISPER_SAMPLE_RATE);
+        info
+    }
+
+    /// Segments of the last transcription as dictionaries with `text`, `t0`
+    /// and `t1` (centiseconds from the start of the utterance), `no_speech_prob`
+    /// and `avg_logprob`
+    #[func]
+    fn get_last_segments(&self) -> Array<Dictionary> {
+        let mut segments: Array<Dictionary> = Array::new();
+
+        if let Ok(last_segments) = self.shared.segments.lock() {
+            for segment in last_segments.iter() {
+                let mut dict = Dictionary::new();
+                dict.set("text", segment.text.clone());
+                dict.set("t0", segment.t0);
+                dict.set("t1", segment.t1);
+                dict.set("no_speech_prob", segment.no_speech_prob);
+                dict.set("avg_logprob", segment.avg_logprob);
+                segments.push(&dict);
+            }
+        }
+
+        segments
+    }
+
+    /// Transcribe the last `seconds` of captured audio right away, without
+    /// waiting for the utterance to end. Blocks until whisper is done.
+    #[func]
+    fn retro_transcribe(&mut self, seconds: f32) -> GString {
+        let Some(ctx) = self.shared.context.lock().unwrap().clone() else {
+            godot_error!("retro_transcribe: whisper is not initialized");
+            return GString::new();
+        };
+
+        let samples: Vec<f32> = {
+            let history = self.shared.history.lock().unwrap();
+            let wanted = (seconds.max(0.0) * WHISPER_SAMPLE_RATE as f32) as usize;
+            let skip = history.len().saturating_sub(wanted);
+            history.iter().skip(skip).copied().collect()
+        };
+
+        match transcribe_pcm(&ctx, &self.config, &samples, WHISPER_SAMPLE_RATE) {
+            Ok(text) => GString::from(text.as_str()),
+            Err(err) => {
+                godot_error!("retro_transcribe failed: {:?}", err);
+                GString::new()
+            }
+        }
+    }
+
+    /// Load the model at `model_path` on the calling thread, for
+    /// `transcribe_samples` without capturing from the microphone. Blocks while
+    /// the model loads. Not needed after `init_whisper`, which loads its own.
+    #[func]
+    fn load_model(&mut self, model_path: String) -> bool {
+        if let Err(err) = validate_model(Path::new(&model_path)) {
+            self.report_error(format!(
+                "Failed to load whisper model {}: {}",
+                model_path, err
+            ));
+            return false;
+        }
+
+        match load_context(&ModelSource::Path(model_path.clone()), self.config.use_gpu) {
+            Ok(ctx) => {
+                *self.shared.context.lock().unwrap() = Some(Arc::new(ctx));
+                true
+            }
+            Err(err) => {
+                self.report_error(format!(
+                    "Failed to load whisper model {}: {}",
+                    model_path, err
+                ));
+                false
+            }
+        }
+    }
+
+    /// Transcribe mono audio recorded at `sample_rate`, e.g. a decoded WAV,
+    /// and return the text. Blocks until whisper is done. Needs a model from
+    /// `init_whisper` or `load_model`, but no microphone.
+    #[func]
+    fn transcribe_samples(&mut self, pcm: PackedFloat32Array, sample_rate: i32) -> GString {
+        let Some(ctx) = self.shared.context.lock().unwrap().clone() else {
+            godot_error!("transcribe_samples: no model loaded, call load_model first");
+            return GString::new();
+        };
+
+        if sample_rate <= 0 {
+            godot_error!("transcribe_samples: invalid sample rate {}", sample_rate);
+            return GString::new();
+        }
+
+        match transcribe_pcm(&ctx, &self.config, pcm.as_slice(), sample_rate as u32) {
+            Ok(text) => GString::from(text.as_str()),
+            Err(err) => {
+                godot_error!("transcribe_samples failed: {:?}", err);
+                GString::new()
+            }
+        }
+    }
+
+    /// Disable silence gating and transcribe overlapping 3s windows back to back,
+    /// for live captioning. Whisper then runs constantly, even on silence, so
+    /// expect a much higher CPU/GPU load. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_vad_enabled(&mut self, enabled: bool) {
+        self.config.vad_enabled = enabled;
+    }
+
+    /// "fixed" (default) ends an utterance after `silence_hold_ms` of silence or
+    /// once `min_utterance_ms` is buffered. "vad" ends it only when a voice
+    /// detector (energy and zero-crossing rate) hears speech stop for the
+    /// hangover (or `max_buffer_seconds` is reached), so long sentences
+    /// aren't chopped mid-word. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_segmentation(&mut self, mode: GString) {
+        let Some(mode) = Segmentation::parse(&mode.to_string()) else {
+            godot_error!("set_segmentation: expected fixed or vad, got '{}'", mode);
+            return;
+        };
+        self.config.segmentation = mode;
+    }
+
+    /// Silence in seconds after speech before a "vad" segment ends (default 0.3).
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_vad_hangover(&mut self, seconds: f32) {
+        self.config.vad_hangover = seconds.max(0.0);
+    }
+
+    /// Seconds of audio (default 0, try 0.5) kept as the start of the next
+    /// utterance when one is cut at its maximum length while the player is
+    /// still talking, so a keyword spoken across the cut is heard whole. A
+    /// keyword reported from that audio before the cut isn't reported twice.
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_segment_overlap(&mut self, seconds: f32) {
+        self.config.segment_overlap = seconds.max(0.0);
+    }
+
+    /// Run the model on the GPU (default on). Turn off for deterministic CPU
+    /// inference or on machines whose GPU driver misbehaves. Takes effect on
+    /// the next `init_whisper`.
+    #[func]
+    fn set_use_gpu(&mut self, enabled: bool) {
+        self.config.use_gpu = enabled;
+    }
+
+    /// CPU threads used for transcription, clamped to the available cores
+    /// (default: all of them). Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_whisper_threads(&mut self, n: i32) {
+        self.config.threads = n.clamp(1, available_threads());
+    }
+
+    /// Language spoken into the microphone as a whisper code ("en", "pt", "de",
+    /// ...), or "auto" to let whisper detect it. Unknown codes fall back to "en".
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_language(&mut self, lang: String) {
+        let lang = lang.trim().to_lowercase();
+        if is_valid_language(&lang) {
+            self.config.language = lang;
+        } else {
+            godot_error!("Unknown whisper language '{}', using \"en\"", lang);
+            self.config.language = "en".to_owned();
+        }
+    }
+
+    /// Translate what is said into English instead of transcribing it in the
+    /// spoken language. With `set_language("auto")`, players can trigger the
+    /// English spellbook in any language. Needs a multilingual model (not
+    /// `.en`). Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_translate(&mut self, enabled: bool) {
+        self.config.translate = enabled;
+    }
+
+    /// Emit `segment_transcribed` for each segment while whisper is still
+    /// working on the rest of a long utterance. Segments come out before the
+    /// no-speech and log-probability filters run, so they may include text
+    /// the final transcription drops. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_stream_segments(&mut self, enabled: bool) {
+        self.config.stream_segments = enabled;
+    }
+
+    /// Decoder search: "greedy" (default, `size` = candidates per token) or
+    /// "beam_search" (`size` = beam width). Beam search hits keywords more
+    /// reliably on noisy input, but every transcription takes about `size`
+    /// times longer. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_sampling_strategy(&mut self, mode: String, size: i32) {
+        let size = size.max(1);
+        self.config.sampling = match mode.to_lowercase().as_str() {
+            "greedy" => Sampling::Greedy { best_of: size },
+            "beam_search" | "beam" => Sampling::BeamSearch { beam_size: size },
+            _ => {
+                godot_error!(
+                    "set_sampling_strategy: expected greedy or beam_search, got '{}'",
+                    mode
+                );
+                return;
+            }
+        };
+    }
+
+    /// Sampling temperature of the first decode (default 0, 0 to 1). Above 0
+    /// whisper picks less likely tokens now and then, which can help it out of
+    /// a misheard phrase but makes results less repeatable. Takes effect on
+    /// the next `init_whisper`.
+    #[func]
+    fn set_temperature(&mut self, temperature: f32) {
+        self.config.temperature = temperature.clamp(0.0, 1.0);
+    }
+
+    /// When a decode looks like a failure (repeated or improbable text),
+    /// decode again at a higher temperature (default on, as in whisper).
+    /// Turning it off bounds the time a transcription takes, at the cost of
+    /// more empty or garbled results on noisy audio. Takes effect on the
+    /// next `init_whisper`.
+    #[func]
+    fn set_temperature_fallback(&mut self, enabled: bool) {
+        self.config.temperature_fallback = enabled;
+    }
+
+    /// With the VAD disabled, ignore the words each window repeats from the
+    /// overlap with the previous one, so a spell said on a window boundary is
+    /// cast once (default on). Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_stabilization(&mut self, enabled: bool) {
+        self.config.stabilization = enabled;
+    }
+
+    /// Bias whisper toward the registered spell triggers, turning it into a
+    /// limited-vocabulary recognizer. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_constrain_to_keywords(&mut self, enabled: bool) {
+        self.config.constrain_to_keywords = enabled;
+    }
+
+    /// Text whisper is primed with before every transcription, e.g. the names
+    /// and jargon of the game ("Valoran", "mana potion") so they are spelled
+    /// right. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_initial_prompt(&mut self, prompt: String) {
+        self.config.initial_prompt = prompt;
+    }
+
+    /// Append the registered spell triggers to the initial prompt (default on),
+    /// biasing whisper toward them without limiting it to them like
+    /// `set_constrain_to_keywords`. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_prompt_keywords(&mut self, enabled: bool) {
+        self.config.prompt_keywords = enabled;
+    }
+
+    /// Reload the model and keep listening when the whisper thread panics, up to
+    /// `set_max_recoveries` times. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_auto_recover(&mut self, enabled: bool) {
+        self.config.auto_recover = enabled;
+    }
+
+    #[func]
+    fn set_max_recoveries(&mut self, recoveries: u32) {
+        self.config.max_recoveries = recoveries;
+    }
+
+    /// Seconds of captured audio kept for `retro_transcribe`. Takes effect on
+    /// the next `init_whisper`.
+    #[func]
+    fn set_history_seconds(&mut self, seconds: f32) {
+        self.config.history_seconds = seconds.max(0.0);
+    }
+
+    /// Decode a relay blob encoded at `sample_rate` (an Opus rate, 48000 for
+    /// the `speak` signal) to interleaved stereo, expecting frames as long as
+    /// this node's relay frames (`set_opus_frame_size`). Switching rates
+    /// resets the decoder. Malformed
+    /// network data is reported and decodes to nothing (or concealed audio)
+    /// instead of crashing.
+    #[func]
+    fn decode_audio(&mut self, encoded: Vec<u8>, sample_rate: i32) -> Vec<f32> {
+        let sample_rate = sample_rate.max(0) as u32;
+        if sample_rate != self.decoder_rate {
+            match new_decoder(sample_rate, 2) {
+                Ok(decoder) => {
+                    self.decoder = decoder;
+                    self.decoder_rate = sample_rate;
+                }
+                Err(CodecError::InvalidSampleRate(rate)) => {
+                    godot_error!(
+                        "decode_audio: {} Hz isn't an Opus rate, pass the rate the sender encoded at (48000 for `speak`)",
+                        rate
+                    );
+                    return Vec::new();
+                }
+                Err(err) => {
+                    godot_error!("decode_audio: {}", err);
+                    return Vec::new();
+                }
+            }
+        }
+
+        match decode_opus_to_stereo(
+            &mut self.decoder,
+            &encoded[..],
+            sample_rate,
+            self.microphone.relay_frame_size() * sample_rate as usize / 48000,
+            None,
+        ) {
+            Ok(pcm) => pcm,
+            Err(err) => {
+                godot_error!("decode_audio: {}", err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Resample interleaved audio with `channels` channels between any two rates.
+    /// `quality` is "linear" (cheap) or "sinc" (band-limited, slower).
     #[func]
-    fn decode_audio(&mut self, encoded: Vec<u8>, _sample_rate: i32) -> Vec<f32> {
-        // let frame_size = sample_rate * 10 / 1000;
-        decode_opus_to_stereo(&mut self.decoder, &encoded[..], 48000 as u32, 480 as usize).unwrap()
+    fn resample_audio(
+        &self,
+        samples: Vec<f32>,
+        channels: u32,
+        from_rate: u32,
+        to_rate: u32,
+        quality: GString,
+    ) -> Vec<f32> {
+        let Some(quality) = ResampleQuality::parse(&quality.to_string()) else {
+            godot_error!("resample_audio: expected linear or sinc, got '{}'", quality);
+            return Vec::new();
+        };
+
+        resample(&samples, channels as usize, from_rate, to_rate, quality)
     }
 
     #[func]
@@ -138,6 +842,40 @@ impl Whisper {
         return GString::from_str("").unwrap();
     }
 
+    /// Audio backend the microphone is captured through
+    #[func]
+    fn get_active_host(&self) -> GString {
+        GString::from_str(self.microphone.host_name()).unwrap()
+    }
+
+    /// Audio backends usable on this machine with this build, e.g. "ALSA" and
+    /// "JACK" on Linux or "WASAPI" and "ASIO" on Windows
+    #[func]
+    fn list_hosts(&self) -> Array<GString> {
+        Microphone::list_hosts()
+            .into_iter()
+            .map(GString::from)
+            .collect()
+    }
+
+    /// Capture through the backend named `host_name` (see `list_hosts`) on
+    /// its default input device. A running capture moves over right away.
+    /// Hosts left out of the build, like ASIO without cpal's `asio` feature,
+    /// are reported as such. Returns false when the host can't be used or
+    /// capture couldn't restart on it.
+    #[func]
+    fn select_host(&mut self, host_name: String) -> bool {
+        if let Err(err) = self.microphone.set_host(&host_name) {
+            self.report_error(format!("Can't use audio host '{}': {}", host_name, err));
+            return false;
+        }
+        if let Err(err) = self.microphone.restart() {
+            self.report_error(format!("Can't capture through '{}': {}", host_name, err));
+            return false;
+        }
+        true
+    }
+
     #[func]
     fn list_input_devices(&self) -> Array<GString> {
         let mut inputs: Array<GString> = Array::new();
@@ -152,26 +890,1186 @@ impl Whisper {
         inputs
     }
 
+    /// Switch to the input device named `device_name`. A running capture moves
+    /// over to it right away, whatever its sample rate.
     #[func]
     fn select_input_device(&mut self, device_name: String) {
         let inputs = self.microphone.list_inputs();
-        let device = inputs
-            .iter()
+        let Some(device) = inputs
+            .into_iter()
             .find(|d| d.name().unwrap_or("".to_owned()) == device_name)
-            .unwrap();
-        self.microphone.set_input(device.clone());
-        // TODO Implement the device change
+        else {
+            godot_error!("select_input_device: no input device '{}'", device_name);
+            return;
+        };
+
+        self.microphone.set_input(device);
+        if let Err(err) = self.microphone.restart() {
+            godot_error!("Can't capture from '{}': {:?}", device_name, err);
+        }
     }
 
+    /// RMS level of the latest captured audio, 0 to 1
     #[func]
-    fn register_spell_trigger(&mut self, trigger_frase: String, spell: String) {
-        self.keywords.push(trigger_frase.clone());
-        self.spellbook.insert(trigger_frase, spell);
+    fn get_input_level(&self) -> f32 {
+        self.microphone.input_rms()
     }
 
-    #[signal]
-    fn cast(magic: String);
+    /// Time between `input_level` signals (default 100ms), 0 to turn them off
+    #[func]
+    fn set_input_level_interval_ms(&mut self, interval_ms: u32) {
+        self.input_level_interval_ms = interval_ms;
+    }
 
-    #[signal]
-    fn speak(audio: Vec<u8>);
+    /// Output devices the debug monitor can play on
+    #[func]
+    fn list_output_devices(&self) -> Array<GString> {
+        let mut outputs: Array<GString> = Array::new();
+
+        for device in self.microphone.list_outputs() {
+            if let Ok(name) = device.name() {
+                outputs.push(&GString::from(name.as_str()));
+            }
+        }
+
+        outputs
+    }
+
+    /// Output device of the debug monitor, empty when there is none
+    #[func]
+    fn get_current_output_device(&self) -> GString {
+        match self
+            .microphone
+            .get_current_output()
+            .map(|device| device.name())
+        {
+            Some(Ok(name)) => GString::from(name.as_str()),
+            _ => GString::new(),
+        }
+    }
+
+    /// Play the debug monitor on the output device named `device_name`. A
+    /// running capture moves the monitor over right away.
+    #[func]
+    fn select_output_device(&mut self, device_name: String) {
+        let outputs = self.microphone.list_outputs();
+        let Some(device) = outputs
+            .into_iter()
+            .find(|d| d.name().unwrap_or("".to_owned()) == device_name)
+        else {
+            godot_error!("select_output_device: no output device '{}'", device_name);
+            return;
+        };
+
+        if let Err(err) = self.microphone.set_output(device) {
+            godot_error!("Can't play on '{}': {:?}", device_name, err);
+            return;
+        }
+        if let Err(err) = self.microphone.restart() {
+            godot_error!("Can't restart capture: {:?}", err);
+        }
+    }
+
+    /// Stop forwarding microphone audio and finalize the utterance in progress.
+    /// The model and thread stay alive so `resume` is instant.
+    #[func]
+    fn pause(&mut self) {
+        self.set_paused(true);
+    }
+
+    #[func]
+    fn resume(&mut self) {
+        self.set_paused(false);
+    }
+
+    /// Pause or resume listening, e.g. around a cutscene. While paused the
+    /// capture callback sends nothing and the whisper thread buffers nothing,
+    /// but the model stays loaded so resuming is instant.
+    #[func]
+    fn set_paused(&mut self, paused: bool) {
+        self.microphone.set_paused(paused);
+        self.shared.paused.store(paused, Ordering::Relaxed);
+        if paused {
+            self.shared.flush.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[func]
+    fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Push-to-talk: only audio captured while `on` (default) is transcribed
+    /// and relayed. Releasing the key finalizes what was said. Unlike
+    /// `set_paused` it's meant to follow a key bind, costs nothing to toggle
+    /// and leaves a pause in place.
+    #[func]
+    fn set_listening(&mut self, on: bool) {
+        self.microphone.set_listening(on);
+        let was_listening = self.shared.listening.swap(on, Ordering::Relaxed);
+        if was_listening && !on {
+            self.shared.flush.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[func]
+    fn is_listening(&self) -> bool {
+        self.shared.listening.load(Ordering::Relaxed)
+    }
+
+    /// Play the microphone back on the default output device, to check levels.
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_monitor_enabled(&mut self, enabled: bool) {
+        if let Err(err) = self.microphone.set_monitor_enabled(enabled) {
+            godot_error!("Can't enable the monitor: {:?}", err);
+        }
+    }
+
+    /// Audio buffered by the monitor before it plays (default 50ms). Lower
+    /// feels immediate, higher avoids crackling when the system is busy.
+    #[func]
+    fn set_monitor_latency_ms(&mut self, latency_ms: u32) {
+        self.microphone.set_monitor_latency_ms(latency_ms);
+    }
+
+    /// Remove game audio leaking from the speakers into the microphone before
+    /// recognition. Feed what is being played with `push_echo_reference`.
+    #[func]
+    fn set_echo_cancellation(&mut self, enabled: bool) {
+        self.microphone.set_echo_cancellation(enabled);
+    }
+
+    /// Queue audio that is about to be played, e.g. from an `AudioEffectCapture`
+    /// on the master bus, as the echo cancellation reference
+    #[func]
+    fn push_echo_reference(&mut self, frames: PackedVector2Array, sample_rate: u32) {
+        let mono: Vec<f32> = frames
+            .as_slice()
+            .iter()
+            .map(|frame| (frame.x + frame.y) * 0.5)
+            .collect();
+        let mono = resample(
+            &mono,
+            1,
+            sample_rate,
+            WHISPER_SAMPLE_RATE,
+            ResampleQuality::Linear,
+        );
+        self.microphone.push_echo_reference(&mono);
+    }
+
+    /// Pick the input channels to capture on multi-input interfaces: bit i of
+    /// `mask` selects channel i (e.g. 4 for the third input only), 0 captures
+    /// all. Selected channels are averaged for recognition; the relay carries a
+    /// lone channel on both sides, or the first two selected as left and right.
+    #[func]
+    fn set_capture_channel_selection(&mut self, mask: u32) {
+        self.microphone.set_channel_mask(mask);
+    }
+
+    /// Round trip in milliseconds from the monitor output back into the input,
+    /// measured with a click. Needs `set_monitor_enabled` and a running capture.
+    /// Blocks up to a second; returns -1 when the click isn't heard.
+    #[func]
+    fn measure_monitor_latency(&mut self) -> f32 {
+        match self.microphone.measure_monitor_latency() {
+            Ok(latency_ms) => latency_ms,
+            Err(err) => {
+                godot_error!("measure_monitor_latency: {}", err);
+                -1.0
+            }
+        }
+    }
+
+    /// Resampler bringing the microphone to whisper's 16kHz: "sinc" (default)
+    /// avoids the aliasing that blurs sibilants, "linear" is cheaper for
+    /// low-end machines. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_whisper_resample_quality(&mut self, quality: GString) {
+        let Some(quality) = ResampleQuality::parse(&quality.to_string()) else {
+            godot_error!(
+                "set_whisper_resample_quality: expected linear or sinc, got '{}'",
+                quality
+            );
+            return;
+        };
+        self.microphone.set_whisper_resample(quality);
+    }
+
+    /// Capture in mono when the input device supports it, skipping the stereo
+    /// downmix. Falls back to the default config otherwise.
+    #[func]
+    fn prefer_mono_input(&mut self, prefer: bool) {
+        self.microphone.set_prefer_mono(prefer);
+    }
+
+    /// Channels to capture from the input device, 0 (default) for its default
+    /// config. Devices without a matching config at the same rate and format
+    /// keep the default and are downmixed. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_capture_channels(&mut self, channels: u32) {
+        self.microphone
+            .set_capture_channels(channels.min(u16::MAX as u32) as u16);
+    }
+
+    /// How the captured channels reach whisper and the relay: "average"
+    /// (default) mixes them all, "first" keeps only the first channel,
+    /// "loudest" the one with the most signal and "weighted" mixes them by
+    /// level. The last two suit stereo mics with a dead side, and relay
+    /// their mix on both sides. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_downmix_mode(&mut self, mode: GString) {
+        let Some(downmix) = Downmix::parse(&mode.to_string()) else {
+            godot_error!(
+                "set_downmix_mode: expected average, first, loudest or weighted, got '{}'",
+                mode
+            );
+            return;
+        };
+        self.microphone.set_downmix(downmix);
+    }
+
+    #[func]
+    fn register_spell_trigger(&mut self, trigger_frase: String, spell: String) {
+        self.register_spell_trigger_with_cooldown(trigger_frase, spell, 0);
+    }
+
+    /// Like `register_spell_trigger`, but detections of the phrase within
+    /// `cooldown_ms` of its last cast are ignored.
+    #[func]
+    fn register_spell_trigger_with_cooldown(
+        &mut self,
+        trigger_frase: String,
+        spell: String,
+        cooldown_ms: u32,
+    ) {
+        let mut keywords = self.keywords.lock().unwrap();
+        if !keywords.contains(&trigger_frase) {
+            keywords.push(trigger_frase.clone());
+        }
+        self.spellbook.register(
+            trigger_frase,
+            spell,
+            Duration::from_millis(cooldown_ms as u64),
+        );
+    }
+
+    /// Stop listening for `trigger_frase` and casting its spell
+    #[func]
+    fn unregister_spell_trigger(&mut self, trigger_frase: String) -> bool {
+        self.keywords
+            .lock()
+            .unwrap()
+            .retain(|keyword| *keyword != trigger_frase);
+        self.spellbook.unregister(&trigger_frase)
+    }
+
+    /// Drop every spell trigger
+    #[func]
+    fn clear_spell_triggers(&mut self) {
+        self.keywords.lock().unwrap().clear();
+        self.spellbook.clear();
+    }
+
+    /// Register every phrase → spell pair of `triggers` at once, e.g.
+    /// `{"fireball": "cast_fireball", "heal me": "heal"}`. Like all keyword
+    /// changes it applies to a running transcriber from its next utterance.
+    /// Returns how many pairs were registered; entries that aren't two
+    /// strings are skipped with a warning.
+    #[func]
+    fn register_keywords(&mut self, triggers: Dictionary) -> u32 {
+        let mut registered = 0;
+        for (phrase, spell) in triggers.iter_shared() {
+            match (phrase.try_to::<String>(), spell.try_to::<String>()) {
+                (Ok(phrase), Ok(spell)) => {
+                    self.register_spell_trigger(phrase, spell);
+                    registered += 1;
+                }
+                _ => godot_warn!(
+                    "register_keywords: skipping {} -> {}, expected a phrase and a spell name",
+                    phrase,
+                    spell
+                ),
+            }
+        }
+        registered
+    }
+
+    /// Forget every keyword and its spell, same as `clear_spell_triggers`
+    #[func]
+    fn clear_keywords(&mut self) {
+        self.clear_spell_triggers();
+    }
+
+    /// Strip punctuation from transcriptions before keyword matching (default on).
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_strip_punctuation(&mut self, enabled: bool) {
+        self.config.strip_punctuation = enabled;
+    }
+
+    /// Accept misheard keywords whose closest run of words is at least this
+    /// similar (0-1, by edit distance, spaces ignored): "fire ball" and
+    /// "fire bowl" reach 1.0 and 0.75 against "fireball". The default 1.0 only
+    /// matches exact spellings. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_keyword_similarity(&mut self, threshold: f32) {
+        self.config.keyword_similarity = threshold.clamp(0.0, 1.0);
+    }
+
+    /// Discard segments whisper rates as more likely than this (0-1, default
+    /// 0.6) to hold no speech before keyword matching. Filters out the text it
+    /// makes up on silence and noise; 1.0 keeps every segment. Takes effect on
+    /// the next `init_whisper`.
+    #[func]
+    fn set_no_speech_threshold(&mut self, threshold: f32) {
+        self.config.no_speech_threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    /// Discard segments whose mean token log-probability is below this
+    /// (default -1.0) before keyword matching. Lower it to accept less certain
+    /// transcriptions. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_logprob_threshold(&mut self, threshold: f32) {
+        self.config.logprob_threshold = threshold.min(0.0);
+    }
+
+    /// Report keywords from interim transcriptions through `cast_provisional`
+    /// before the utterance ends. The final result then either emits `cast`
+    /// for the same spell or `cast_retracted`. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_provisional_casting(&mut self, enabled: bool) {
+        self.config.provisional_casting = enabled;
+    }
+
+    /// Minimum interim confidence (0-1) for a provisional cast
+    #[func]
+    fn set_provisional_threshold(&mut self, threshold: f32) {
+        self.config.provisional_threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    /// RMS level (0-1) below which the microphone counts as silent (default
+    /// 0.015). Lower it for quiet rooms, raise it for noisy ones. Applies
+    /// immediately, but is ignored while the adaptive VAD is on.
+    #[func]
+    fn set_silence_threshold(&mut self, threshold: f32) {
+        if let Ok(mut silence) = self.shared.silence.lock() {
+            silence.threshold = threshold.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Silence that ends an utterance (default 256ms). Applies immediately.
+    #[func]
+    fn set_silence_hold_ms(&mut self, hold_ms: u32) {
+        if let Ok(mut silence) = self.shared.silence.lock() {
+            silence.hold = ms_to_samples(hold_ms);
+        }
+    }
+
+    /// Speech buffered before it is transcribed even without a pause
+    /// (default 3000ms). Applies immediately.
+    #[func]
+    fn set_min_utterance_ms(&mut self, utterance_ms: u32) {
+        if let Ok(mut silence) = self.shared.silence.lock() {
+            silence.min_utterance = ms_to_samples(utterance_ms).max(1);
+        }
+    }
+
+    /// Longest utterance buffered before it is transcribed no matter what
+    /// (default 30), bounding the delay before a keyword in a long stream of
+    /// speech is detected. Applies immediately.
+    #[func]
+    fn set_max_buffer_seconds(&mut self, seconds: f32) {
+        if let Ok(mut silence) = self.shared.silence.lock() {
+            silence.max_utterance = ((seconds * WHISPER_SAMPLE_RATE as f32) as usize).max(1);
+        }
+    }
+
+    /// Longest segment whisper emits, in characters (default 0, no limit).
+    /// Longer ones are split at a word, giving finer `segment_transcribed`
+    /// and `get_last_segments` results. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_max_segment_length(&mut self, chars: u32) {
+        self.config.max_segment_len = chars.min(i32::MAX as u32) as i32;
+    }
+
+    /// Track the room's noise floor and keep the silence threshold a fixed margin
+    /// above it, instead of the static threshold. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_adaptive_vad(&mut self, enabled: bool) {
+        self.config.adaptive_vad = enabled;
+    }
+
+    /// Margin in dB between the estimated noise floor and the adaptive threshold
+    #[func]
+    fn set_adaptive_vad_margin(&mut self, margin_db: f32) {
+        self.config.adaptive_vad_margin_db = margin_db.max(0.0);
+    }
+
+    /// Encode and emit microphone audio through `speak` (default on). Turn it
+    /// off when only keyword spotting is needed to save the Opus encoding cost.
+    #[func]
+    fn set_relay_enabled(&mut self, enabled: bool) {
+        self.microphone.set_relay_enabled(enabled);
+    }
+
+    /// Linear gain on the outgoing relay only (1.0 = unchanged), so chat volume
+    /// can be adjusted without affecting recognition. Samples are clamped to
+    /// full scale after the gain.
+    #[func]
+    fn set_relay_gain(&mut self, gain: f32) {
+        self.microphone.set_relay_gain(gain);
+    }
+
+    /// Gain in dB on the audio whisper hears (0 = unchanged), for quiet
+    /// microphones. Boosted peaks are soft-limited instead of clipped; the
+    /// relay and `get_input_level` are unaffected.
+    #[func]
+    fn set_input_gain(&mut self, db: f32) {
+        self.microphone.set_input_gain_db(db);
+    }
+
+    /// Boost quiet input automatically, following its peak level slowly (up
+    /// to +20dB on top of `set_input_gain`). Off by default.
+    #[func]
+    fn set_auto_gain(&mut self, enabled: bool) {
+        self.microphone.set_auto_gain(enabled);
+    }
+
+    /// Content hint for the relay encoder: "voice" (default), "music" or "auto".
+    /// The relay encoder runs in VoIP mode, so "voice" matches it; "music" suits
+    /// relaying instruments and "auto" lets Opus decide per frame. Takes effect
+    /// on the next `init_whisper`.
+    #[func]
+    fn set_opus_signal(&mut self, signal: GString) {
+        let Some(signal) = parse_signal(&signal.to_string()) else {
+            godot_error!(
+                "set_opus_signal: expected voice, music or auto, got '{}'",
+                signal
+            );
+            return;
+        };
+
+        let mut options = self.microphone.encode_options();
+        options.signal = signal;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Relay bitrate mode: "cvbr" (default, constrained VBR), "vbr" or "cbr".
+    /// CBR keeps every packet the same size for links planned at a fixed
+    /// rate, at some quality cost. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_opus_bitrate_mode(&mut self, mode: GString) {
+        let Some(mode) = BitrateMode::parse(&mode.to_string()) else {
+            godot_error!(
+                "set_opus_bitrate_mode: expected cbr, vbr or cvbr, got '{}'",
+                mode
+            );
+            return;
+        };
+
+        let mut options = self.microphone.encode_options();
+        options.bitrate_mode = mode;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Embed inband forward error correction in the relay so a receiver with
+    /// FEC enabled can rebuild single lost packets. Only speech-mode frames
+    /// carry it. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_opus_fec(&mut self, enabled: bool) {
+        let mut options = self.microphone.encode_options();
+        options.inband_fec = enabled;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Frames per channel of each relay packet at 48kHz: 120, 240, 480
+    /// (default, 10ms), 960, 1920 or 2880. Shorter frames lower the latency,
+    /// longer ones save bandwidth. Receivers must decode with the same frame
+    /// duration (`OpusDecoderNode.set_frame_size`). Takes effect on the next
+    /// `init_whisper`.
+    #[func]
+    fn set_opus_frame_size(&mut self, frame_size: u32) {
+        if let Err(err) = self.microphone.set_relay_frame_size(frame_size as usize) {
+            godot_error!("set_opus_frame_size: {}", err);
+        }
+    }
+
+    /// Discontinuous transmission: while the player is silent the relay sends
+    /// nothing instead of a packet every frame, so receivers play the gap as
+    /// silence. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_opus_dtx(&mut self, enabled: bool) {
+        let mut options = self.microphone.encode_options();
+        options.dtx = enabled;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Relay encoder complexity, 0-10 (default 10). Lower it on machines
+    /// short on CPU; speech stays intelligible down to 0 at a lower quality.
+    /// Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_opus_complexity(&mut self, complexity: u32) {
+        if complexity > MAX_COMPLEXITY as u32 {
+            godot_error!(
+                "set_opus_complexity: expected 0 to {}, got {}",
+                MAX_COMPLEXITY,
+                complexity
+            );
+            return;
+        }
+
+        let mut options = self.microphone.encode_options();
+        options.complexity = complexity as u8;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Packet loss (0-100%) expected on the network. The relay encoder adds
+    /// more redundancy the higher it is. Takes effect on the next `init_whisper`.
+    #[func]
+    fn set_opus_packet_loss_perc(&mut self, percent: u32) {
+        let mut options = self.microphone.encode_options();
+        options.packet_loss_perc = percent.min(100) as u8;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Zero-pad and send the relay's last partial Opus frame when capture is
+    /// paused, so word endings aren't cut off (default off). Takes effect on
+    /// the next `init_whisper`.
+    #[func]
+    fn set_pad_final_frame(&mut self, enabled: bool) {
+        let mut options = self.microphone.encode_options();
+        options.pad_final_frame = enabled;
+        self.microphone.set_encode_options(options);
+    }
+
+    /// Put a header with a sequence number and the capture timestamp in front
+    /// of each `speak` blob (default on), for `JitterBufferNode.push_blob`.
+    /// Turn it off for receivers built before the header existed.
+    #[func]
+    fn set_relay_sequencing(&mut self, enabled: bool) {
+        self.microphone.relay_clock().set_enabled(enabled);
+    }
+
+    /// Keep a copy of the audio whisper hears (16kHz mono) for `get_recorded_pcm`,
+    /// e.g. to replay what the player said. Off by default; turning it off
+    /// keeps what was recorded so far.
+    #[func]
+    fn set_pcm_recording_enabled(&mut self, enabled: bool) {
+        if let Ok(mut recording) = self.shared.pcm_recording.lock() {
+            recording.enabled = enabled;
+        }
+    }
+
+    /// Most audio the PCM recording holds, in seconds (default 60). Audio
+    /// past it isn't recorded until `clear_recording`.
+    #[func]
+    fn set_pcm_recording_max_seconds(&mut self, seconds: f32) {
+        if let Ok(mut recording) = self.shared.pcm_recording.lock() {
+            recording.max_samples = (seconds.max(0.0) * WHISPER_SAMPLE_RATE as f32) as usize;
+            let max_samples = recording.max_samples;
+            recording.samples.truncate(max_samples);
+        }
+    }
+
+    /// Audio recorded since `set_pcm_recording_enabled`, 16kHz mono
+    #[func]
+    fn get_recorded_pcm(&self) -> PackedFloat32Array {
+        match self.shared.pcm_recording.lock() {
+            Ok(recording) => PackedFloat32Array::from(recording.samples.as_slice()),
+            Err(_) => PackedFloat32Array::new(),
+        }
+    }
+
+    /// Discard the PCM recording; recording carries on if enabled
+    #[func]
+    fn clear_recording(&mut self) {
+        if let Ok(mut recording) = self.shared.pcm_recording.lock() {
+            recording.samples = Vec::new();
+        }
+    }
+
+    /// Start keeping the relay audio sent through `speak`, discarding anything
+    /// recorded before, until `save_recording`
+    #[func]
+    fn start_recording(&mut self) {
+        // Stands in for the frames DTX left out
+        let frame_size = self.microphone.relay_frame_size();
+        let mut options = self.microphone.encode_options();
+        options.dtx = false;
+        let silence = match encode_stereo_to_opus(
+            &mut self.keepalive_encoder,
+            &vec![0.0; frame_size * 2],
+            48000,
+            frame_size,
+            &options,
+        ) {
+            Ok(blob) => framed_packets(&blob).concat(),
+            Err(err) => {
+                godot_error!("start_recording: can't encode silence: {}", err);
+                return;
+            }
+        };
+        self.recording = Some(RelayRecording::new(frame_size, silence));
+    }
+
+    /// Write the relay audio recorded since `start_recording` to `path`
+    /// (res://, user:// or absolute) as an Ogg Opus file and stop recording.
+    /// The packets are stored as sent, so this costs no re-encoding; frames
+    /// DTX left out are filled in with silence when `set_relay_sequencing` is on.
+    #[func]
+    fn save_recording(&mut self, path: GString) -> bool {
+        let Some(recording) = self.recording.take() else {
+            godot_error!("save_recording: call start_recording first");
+            return false;
+        };
+
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        match write_ogg_opus_packets(
+            Path::new(&path),
+            recording.packets(),
+            2,
+            48000,
+            recording.frame_size(),
+            default_lookahead(48000),
+            recording.frames(),
+        ) {
+            Ok(_) => true,
+            Err(err) => {
+                godot_error!("save_recording: can't write {}: {}", path, err);
+                false
+            }
+        }
+    }
+
+    /// Write the microphone audio exactly as the device delivers it (its own
+    /// sample rate and channels, before gain, downmix or resampling) to a WAV
+    /// file at `path` (res://, user:// or absolute), to hear what recognition
+    /// was given when it goes wrong. Runs until `stop_raw_capture`, or until
+    /// the input device changes.
+    #[func]
+    fn start_raw_capture(&mut self, path: GString) -> bool {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&path)
+            .to_string();
+        match self.microphone.start_raw_capture(Path::new(&path)) {
+            Ok(()) => true,
+            Err(err) => {
+                godot_error!("start_raw_capture: can't record to {}: {}", path, err);
+                false
+            }
+        }
+    }
+
+    /// Finish the file of `start_raw_capture`. Returns false when no raw
+    /// capture was running or the file couldn't be written.
+    #[func]
+    fn stop_raw_capture(&mut self) -> bool {
+        match self.microphone.stop_raw_capture() {
+            Ok(stopped) => stopped,
+            Err(err) => {
+                godot_error!("stop_raw_capture: {}", err);
+                false
+            }
+        }
+    }
+
+    /// Send Opus silence on the relay when the capture thread stalls, so the
+    /// receiving end keeps a steady stream instead of hearing a gap
+    #[func]
+    fn set_relay_keepalive(&mut self, enabled: bool) {
+        self.relay_keepalive = enabled;
+    }
+
+    /// Maximum relay packets emitted through `speak` per frame. Packets beyond
+    /// the budget wait for the next frame.
+    #[func]
+    fn set_relay_packets_per_frame(&mut self, packets: u32) {
+        self.relay_packets_per_frame = packets.max(1) as usize;
+    }
+
+    /// All tunable settings, keyed by the name of their setter without `set_`,
+    /// ready to be saved and passed back to `set_config`
+    #[func]
+    fn get_config(&self) -> Dictionary {
+        let mut config = Dictionary::new();
+        config.set("language", self.config.language.as_str());
+        config.set("translate", self.config.translate);
+        config.set("stream_segments", self.config.stream_segments);
+        config.set("whisper_threads", self.config.threads);
+        config.set("use_gpu", self.config.use_gpu);
+        let (mode, size) = match self.config.sampling {
+            Sampling::Greedy { best_of } => ("greedy", best_of),
+            Sampling::BeamSearch { beam_size } => ("beam_search", beam_size),
+        };
+        config.set("sampling_strategy", mode);
+        config.set("sampling_size", size);
+        config.set("temperature", self.config.temperature);
+        config.set("temperature_fallback", self.config.temperature_fallback);
+        config.set("strip_punctuation", self.config.strip_punctuation);
+        config.set("keyword_similarity", self.config.keyword_similarity);
+        config.set("no_speech_threshold", self.config.no_speech_threshold);
+        config.set("logprob_threshold", self.config.logprob_threshold);
+        config.set("provisional_casting", self.config.provisional_casting);
+        config.set("provisional_threshold", self.config.provisional_threshold);
+        let silence = self.shared.silence();
+        config.set("silence_threshold", silence.threshold);
+        config.set("silence_hold_ms", samples_to_ms(silence.hold));
+        config.set("min_utterance_ms", samples_to_ms(silence.min_utterance));
+        config.set(
+            "max_buffer_seconds",
+            silence.max_utterance as f32 / WHISPER_SAMPLE_RATE as f32,
+        );
+        config.set("max_segment_length", self.config.max_segment_len);
+        config.set("adaptive_vad", self.config.adaptive_vad);
+        config.set("adaptive_vad_margin", self.config.adaptive_vad_margin_db);
+        config.set("history_seconds", self.config.history_seconds);
+        if let Ok(recording) = self.shared.pcm_recording.lock() {
+            config.set("pcm_recording_enabled", recording.enabled);
+            config.set(
+                "pcm_recording_max_seconds",
+                recording.max_samples as f32 / WHISPER_SAMPLE_RATE as f32,
+            );
+        }
+        config.set("vad_enabled", self.config.vad_enabled);
+        config.set("segmentation", self.config.segmentation.name());
+        config.set("vad_hangover", self.config.vad_hangover);
+        config.set("segment_overlap", self.config.segment_overlap);
+        config.set("constrain_to_keywords", self.config.constrain_to_keywords);
+        config.set("initial_prompt", self.config.initial_prompt.as_str());
+        config.set("prompt_keywords", self.config.prompt_keywords);
+        config.set("stabilization", self.config.stabilization);
+        config.set("auto_recover", self.config.auto_recover);
+        config.set("max_recoveries", self.config.max_recoveries);
+        config.set("monitor_latency_ms", self.microphone.monitor_latency_ms());
+        config.set("echo_cancellation", self.microphone.is_echo_cancellation());
+        config.set("capture_channel_selection", self.microphone.channel_mask());
+        config.set(
+            "capture_channels",
+            self.microphone.capture_channels() as u32,
+        );
+        config.set("downmix_mode", self.microphone.downmix().name());
+        config.set(
+            "whisper_resample_quality",
+            self.microphone.whisper_resample().name(),
+        );
+        config.set("relay_enabled", self.microphone.is_relay_enabled());
+        config.set("relay_gain", self.microphone.relay_gain());
+        config.set("input_gain", self.microphone.input_gain_db());
+        config.set("auto_gain", self.microphone.auto_gain());
+        config.set(
+            "opus_signal",
+            signal_name(self.microphone.encode_options().signal),
+        );
+        config.set(
+            "opus_bitrate_mode",
+            self.microphone.encode_options().bitrate_mode.name(),
+        );
+        config.set("opus_fec", self.microphone.encode_options().inband_fec);
+        config.set("opus_dtx", self.microphone.encode_options().dtx);
+        config.set(
+            "opus_complexity",
+            self.microphone.encode_options().complexity as u32,
+        );
+        config.set("opus_frame_size", self.microphone.relay_frame_size() as u32);
+        config.set(
+            "opus_packet_loss_perc",
+            self.microphone.encode_options().packet_loss_perc as u32,
+        );
+        config.set(
+            "pad_final_frame",
+            self.microphone.encode_options().pad_final_frame,
+        );
+        config.set(
+            "relay_sequencing",
+            self.microphone.relay_clock().is_enabled(),
+        );
+        config.set("relay_keepalive", self.relay_keepalive);
+        config.set("input_level_interval_ms", self.input_level_interval_ms);
+        config.set(
+            "relay_packets_per_frame",
+            self.relay_packets_per_frame as u32,
+        );
+        config
+    }
+
+    /// Apply settings saved from `get_config`. Missing keys keep their current
+    /// value; unknown keys and values of the wrong type are skipped with a warning.
+    #[func]
+    fn set_config(&mut self, config: Dictionary) {
+        for (key, value) in config.iter_shared() {
+            let key = key.to_string();
+            let applied = match key.as_str() {
+                // Applied together below
+                "sampling_strategy" | "sampling_size" => continue,
+                "temperature" => config_f32(&value).map(|v| self.set_temperature(v)),
+                "temperature_fallback" => {
+                    config_bool(&value).map(|v| self.set_temperature_fallback(v))
+                }
+                "whisper_threads" => config_u32(&value).map(|v| self.set_whisper_threads(v as i32)),
+                "language" => value.try_to::<String>().ok().map(|v| self.set_language(v)),
+                "translate" => config_bool(&value).map(|v| self.set_translate(v)),
+                "stream_segments" => config_bool(&value).map(|v| self.set_stream_segments(v)),
+                "strip_punctuation" => config_bool(&value).map(|v| self.set_strip_punctuation(v)),
+                "keyword_similarity" => config_f32(&value).map(|v| self.set_keyword_similarity(v)),
+                "no_speech_threshold" => {
+                    config_f32(&value).map(|v| self.set_no_speech_threshold(v))
+                }
+                "logprob_threshold" => config_f32(&value).map(|v| self.set_logprob_threshold(v)),
+                "provisional_casting" => {
+                    config_bool(&value).map(|v| self.set_provisional_casting(v))
+                }
+                "provisional_threshold" => {
+                    config_f32(&value).map(|v| self.set_provisional_threshold(v))
+                }
+                "silence_threshold" => config_f32(&value).map(|v| self.set_silence_threshold(v)),
+                "silence_hold_ms" => config_u32(&value).map(|v| self.set_silence_hold_ms(v)),
+                "min_utterance_ms" => config_u32(&value).map(|v| self.set_min_utterance_ms(v)),
+                "max_buffer_seconds" => config_f32(&value).map(|v| self.set_max_buffer_seconds(v)),
+                "max_segment_length" => config_u32(&value).map(|v| self.set_max_segment_length(v)),
+                "adaptive_vad" => config_bool(&value).map(|v| self.set_adaptive_vad(v)),
+                "adaptive_vad_margin" => {
+                    config_f32(&value).map(|v| self.set_adaptive_vad_margin(v))
+                }
+                "history_seconds" => config_f32(&value).map(|v| self.set_history_seconds(v)),
+                "pcm_recording_enabled" => {
+                    config_bool(&value).map(|v| self.set_pcm_recording_enabled(v))
+                }
+                "pcm_recording_max_seconds" => {
+                    config_f32(&value).map(|v| self.set_pcm_recording_max_seconds(v))
+                }
+                "vad_enabled" => config_bool(&value).map(|v| self.set_vad_enabled(v)),
+                "segmentation" => value
+                    .try_to::<GString>()
+                    .ok()
+                    .map(|v| self.set_segmentation(v)),
+                "vad_hangover" => config_f32(&value).map(|v| self.set_vad_hangover(v)),
+                "segment_overlap" => config_f32(&value).map(|v| self.set_segment_overlap(v)),
+                "constrain_to_keywords" => {
+                    config_bool(&value).map(|v| self.set_constrain_to_keywords(v))
+                }
+                "initial_prompt" => value
+                    .try_to::<String>()
+                    .ok()
+                    .map(|v| self.set_initial_prompt(v)),
+                "prompt_keywords" => config_bool(&value).map(|v| self.set_prompt_keywords(v)),
+                "stabilization" => config_bool(&value).map(|v| self.set_stabilization(v)),
+                "auto_recover" => config_bool(&value).map(|v| self.set_auto_recover(v)),
+                "max_recoveries" => config_u32(&value).map(|v| self.set_max_recoveries(v)),
+                "monitor_latency_ms" => config_u32(&value).map(|v| self.set_monitor_latency_ms(v)),
+                "echo_cancellation" => config_bool(&value).map(|v| self.set_echo_cancellation(v)),
+                "capture_channel_selection" => {
+                    config_u32(&value).map(|v| self.set_capture_channel_selection(v))
+                }
+                // Superseded by capture_channels, still read from older saves
+                "prefer_mono_input" => config_bool(&value).map(|v| self.prefer_mono_input(v)),
+                "capture_channels" => config_u32(&value).map(|v| self.set_capture_channels(v)),
+                "downmix_mode" => value
+                    .try_to::<GString>()
+                    .ok()
+                    .map(|v| self.set_downmix_mode(v)),
+                "whisper_resample_quality" => value
+                    .try_to::<GString>()
+                    .ok()
+                    .map(|v| self.set_whisper_resample_quality(v)),
+                "relay_enabled" => config_bool(&value).map(|v| self.set_relay_enabled(v)),
+                "relay_gain" => config_f32(&value).map(|v| self.set_relay_gain(v)),
+                "input_gain" => config_f32(&value).map(|v| self.set_input_gain(v)),
+                "auto_gain" => config_bool(&value).map(|v| self.set_auto_gain(v)),
+                "opus_signal" => value
+                    .try_to::<GString>()
+                    .ok()
+                    .map(|v| self.set_opus_signal(v)),
+                "opus_bitrate_mode" => value
+                    .try_to::<GString>()
+                    .ok()
+                    .map(|v| self.set_opus_bitrate_mode(v)),
+                "opus_fec" => config_bool(&value).map(|v| self.set_opus_fec(v)),
+                "opus_dtx" => config_bool(&value).map(|v| self.set_opus_dtx(v)),
+                "opus_complexity" => config_u32(&value).map(|v| self.set_opus_complexity(v)),
+                "opus_frame_size" => config_u32(&value).map(|v| self.set_opus_frame_size(v)),
+                "opus_packet_loss_perc" => {
+                    config_u32(&value).map(|v| self.set_opus_packet_loss_perc(v))
+                }
+                "pad_final_frame" => config_bool(&value).map(|v| self.set_pad_final_frame(v)),
+                "relay_sequencing" => config_bool(&value).map(|v| self.set_relay_sequencing(v)),
+                "relay_keepalive" => config_bool(&value).map(|v| self.set_relay_keepalive(v)),
+                "input_level_interval_ms" => {
+                    config_u32(&value).map(|v| self.set_input_level_interval_ms(v))
+                }
+                "relay_packets_per_frame" => {
+                    config_u32(&value).map(|v| self.set_relay_packets_per_frame(v))
+                }
+                _ => {
+                    godot_warn!("set_config: ignoring unknown key '{}'", key);
+                    continue;
+                }
+            };
+
+            if applied.is_none() {
+                godot_warn!("set_config: invalid value {} for '{}'", value, key);
+            }
+        }
+
+        if config.contains_key("sampling_strategy") || config.contains_key("sampling_size") {
+            let (mode, size) = match self.config.sampling {
+                Sampling::Greedy { best_of } => ("greedy".to_owned(), best_of),
+                Sampling::BeamSearch { beam_size } => ("beam_search".to_owned(), beam_size),
+            };
+            let mode = config
+                .get("sampling_strategy")
+                .and_then(|v| v.try_to::<String>().ok())
+                .unwrap_or(mode);
+            let size = config
+                .get("sampling_size")
+                .and_then(|v| config_u32(&v))
+                .map(|v| v as i32)
+                .unwrap_or(size);
+            self.set_sampling_strategy(mode, size);
+        }
+    }
+
+    #[signal]
+    fn cast(magic: String);
+
+    /// Same detection as `cast`, with the transcription, confidence and timing
+    #[signal]
+    fn detected(result: Gd<DetectionResult>);
+
+    /// Every non-empty final transcription, spell trigger or not, for
+    /// dictation and subtitles
+    #[signal]
+    fn transcribed(text: String);
+
+    /// Each segment of a final transcription as soon as whisper decoded it,
+    /// before `transcribed` reports the whole text. `t0` and `t1` are
+    /// centiseconds from the start of the utterance. Needs `set_stream_segments`.
+    #[signal]
+    fn segment_transcribed(text: String, t0: i64, t1: i64);
+
+    /// Language of the last final transcription, as a whisper code and its
+    /// probability (0 to 1), whenever it differs from the one emitted before.
+    /// Only emitted when the language is set to "auto".
+    #[signal]
+    fn language_detected(code: String, probability: f32);
+
+    #[signal]
+    fn cast_provisional(magic: String);
+
+    #[signal]
+    fn cast_retracted(magic: String);
+
+    /// Opus relay blob: a header with its sequence number and capture
+    /// timestamp (see `set_relay_sequencing`), then length-prefixed packets
+    #[signal]
+    fn speak(audio: Vec<u8>);
+
+    /// Whisper can't keep up: since the last report `dropped_frames` captured
+    /// samples were discarded and `skipped_detections` interim transcriptions
+    /// skipped. Emitted at most once per second. Try a smaller model.
+    #[signal]
+    fn recognition_overloaded(dropped_frames: u64, skipped_detections: u64);
+
+    /// Outcome of `start_calibration`: `noise_floor`, `peak` and `clipping`
+    /// (share of full-scale samples), `speech_detected`, the input's
+    /// `sample_rate`, `channels`, `sample_format` and `device`, and a `verdict`
+    /// of "ok", "silent", "too_quiet", "clipping" or "no_speech"
+    #[signal]
+    fn calibration_result(result: Dictionary);
+
+    /// The model finished loading after `init_whisper` (or a recovery) and
+    /// transcription started. Speech captured during the load, up to 10
+    /// seconds of it, is transcribed right after.
+    #[signal]
+    fn whisper_ready();
+
+    /// The whisper thread was restarted after a panic; `attempt` counts from 1
+    #[signal]
+    fn whisper_recovered(attempt: u32);
+
+    /// The whisper thread stopped after a panic and will not be restarted
+    #[signal]
+    fn whisper_failed(reason: String);
+
+    /// Microphone level for a mic-check meter, 0 to 1: `rms` of the latest
+    /// audio and `peak` since the previous signal. See `set_input_level_interval_ms`.
+    #[signal]
+    fn input_level(rms: f32, peak: f32);
+
+    /// The input device `name` was disconnected. Capture has moved to the
+    /// default input device, if there is one; prompt the player to pick another.
+    #[signal]
+    fn input_device_lost(name: GString);
+
+    /// Recognition can't run: the model failed to load, capture didn't start
+    /// or the whisper thread stopped for good. `message` names the model path
+    /// and the underlying error where there is one.
+    #[signal]
+    fn whisper_error(message: String);
+}
+
+impl Whisper {
+    /// Spawn the whisper thread on `model` and start capturing
+    fn start_whisper(&mut self, model: ModelSource) -> bool {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<f32>>(CAPTURE_QUEUE_CAPACITY);
+
+        self.shared.stop.store(false, Ordering::Relaxed);
+        self.shared.set_status(WhisperStatus::Initializing);
+        Runtime::start(&self.running);
+
+        self.whisper_thread = Some(WhisperKeywordSpotter::start(
+            model,
+            rx,
+            self.running.clone(),
+            self.keywords.clone(),
+            self.config.clone(),
+            self.shared.clone(),
+        ));
+
+        // The relay sender moves into the capture stream; start a fresh relay
+        // channel when a previous stream already took it
+        let relay = match self.sender.take() {
+            Some(sender) => sender,
+            None => {
+                let (sender, reander) = std::sync::mpsc::channel::<Vec<u8>>();
+                self.reander = reander;
+                sender
+            }
+        };
+
+        match self.microphone.start(tx, relay) {
+            Ok(_) => {
+                GodotThreadPrint::print("started".to_owned());
+                true
+            }
+            Err(err) => {
+                self.report_error(format!("Failed to start capture: {}", err));
+                false
+            }
+        }
+    }
+
+    fn report_error(&mut self, message: String) {
+        godot_error!("{}", message);
+        self.signals().whisper_error().emit(message);
+    }
+
+    /// Analyze the last `seconds` of captured audio and emit `calibration_result`
+    fn finish_calibration(&mut self, seconds: f32) {
+        let samples: Vec<f32> = {
+            let history = self.shared.history.lock().unwrap();
+            let wanted = (seconds * WHISPER_SAMPLE_RATE as f32) as usize;
+            let skip = history.len().saturating_sub(wanted);
+            history.iter().skip(skip).copied().collect()
+        };
+        let report = calibration::analyze(&samples);
+
+        let mut result = Dictionary::new();
+        result.set("noise_floor", report.noise_floor);
+        result.set("peak", report.peak);
+        result.set("clipping", report.clipping);
+        result.set("speech_detected", report.speech_detected);
+        result.set("verdict", report.verdict);
+        result.set("sample_rate", self.microphone.get_sample_rate());
+        result.set("channels", self.microphone.get_channels() as u32);
+        result.set(
+            "sample_format",
+            self.microphone.sample_format_name().as_str(),
+        );
+        result.set("device", self.get_current_input_device());
+
+        self.signals().calibration_result().emit(&result);
+    }
+
+    /// Emit `recognition_overloaded` with the drops and skips since the last report
+    fn report_overload(&mut self) {
+        self.last_overload_report = Instant::now();
+
+        let dropped = self.microphone.dropped_frames();
+        let skipped = self.shared.skipped_detections.load(Ordering::Relaxed);
+        let dropped_delta = dropped - self.reported_dropped_frames;
+        let skipped_delta = skipped - self.reported_skipped_detections;
+        self.reported_dropped_frames = dropped;
+        self.reported_skipped_detections = skipped;
+
+        if dropped_delta > 0 || skipped_delta > 0 {
+            self.signals()
+                .recognition_overloaded()
+                .emit(dropped_delta, skipped_delta);
+        }
+    }
+
+    /// Keep the packets of a relay blob while recording
+    fn record_relay(&mut self, audio: &[u8]) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(audio);
+        }
+    }
+
+    /// Cover a relay gap longer than a few frames with encoded silence
+    fn send_keepalive(&mut self) {
+        let Some(last_relay) = self.last_relay else {
+            return;
+        };
+        if self.microphone.is_paused() || !self.microphone.is_listening() {
+            return;
+        }
+
+        let frame_size = self.microphone.relay_frame_size();
+        let frame = Duration::from_secs_f64(frame_size as f64 / 48000.0);
+        let gap = last_relay.elapsed();
+        if gap < frame * KEEPALIVE_GAP_FRAMES {
+            return;
+        }
+
+        let missing = ((gap.as_secs_f64() / frame.as_secs_f64()) as u32).min(MAX_KEEPALIVE_FRAMES);
+        let silence = vec![0.0f32; frame_size * 2 * missing as usize];
+
+        match encode_stereo_to_opus(
+            &mut self.keepalive_encoder,
+            &silence,
+            48000,
+            frame_size,
+            &self.microphone.encode_options(),
+        ) {
+            Ok(audio) => {
+                let audio = self.microphone.relay_clock().stamp(audio, frame_size);
+                self.last_relay = Some(last_relay + frame * missing);
+                self.record_relay(&audio);
+                self.signals().speak().emit(audio);
+            }
+            Err(err) => godot_error!("Keepalive encode failed: {}", err),
+        }
+    }
+}
+
+/// Milliseconds of 16kHz audio in samples
+fn ms_to_samples(ms: u32) -> usize {
+    ms as usize * WHISPER_SAMPLE_RATE as usize / 1000
+}
+
+fn samples_to_ms(samples: usize) -> u32 {
+    (samples * 1000 / WHISPER_SAMPLE_RATE as usize) as u32
+}
+
+fn config_bool(value: &Variant) -> Option<bool> {
+    value.try_to::<bool>().ok()
+}
+
+/// Numbers read back from JSON or a ConfigFile may come as int or float
+fn config_f32(value: &Variant) -> Option<f32> {
+    value
+        .try_to::<f64>()
+        .or_else(|_| value.try_to::<i64>().map(|v| v as f64))
+        .ok()
+        .map(|v| v as f32)
+}
+
+fn config_u32(value: &Variant) -> Option<u32> {
+    config_f32(value)
+        .filter(|v| *v >= 0.0)
+        .map(|v| v.round() as u32)
 }